@@ -0,0 +1,532 @@
+use flate2::read::GzDecoder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Once};
+use std::time::SystemTime;
+use zip::ZipArchive;
+
+use crate::error::Error;
+
+/// Separates the archive from the entry in a path, as in `/packs/drums.zip!/Kicks/kick 01.wav`.
+pub const ENTRY_SEPARATOR: &str = "!/";
+
+/// Entries bigger than this aren't decompressed at all.
+pub const MAX_ENTRY_SIZE: u64 = 4 << 30;
+
+// Entries up to this size are held in memory, bigger ones are decompressed to a spool file
+const MEMORY_LIMIT: u64 = 64 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` is an archive on disk that can be browsed like a folder.
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveKind::from_path(path).is_some() && path.is_file()
+}
+
+/// Splits `/pack.zip!/dir/file.wav` into the archive and the entry inside it.
+pub fn split_entry_path(path: &str) -> Option<(&str, &str)> {
+    let (archive, entry) = path.split_once(ENTRY_SEPARATOR)?;
+    ArchiveKind::from_path(Path::new(archive))?;
+    Some((archive, entry))
+}
+
+pub fn entry_path(archive: &str, entry: &str) -> String {
+    format!("{}{}{}", archive, ENTRY_SEPARATOR, entry)
+}
+
+/// The section an entry is listed under: the folder inside the archive it sits in, or the
+/// archive itself for entries at the top.
+pub fn entry_folder(archive: &str, entry: &str) -> String {
+    match entry.rsplit_once('/') {
+        Some((folder, _)) => entry_path(archive, folder),
+        None => archive.to_string(),
+    }
+}
+
+/// The file on disk behind `path`: the archive for an entry, otherwise the path itself.
+pub fn backing_file(path: &str) -> &str {
+    split_entry_path(path).map_or(path, |(archive, _)| archive)
+}
+
+// Folders, hidden folders and the resource forks macOS adds to zips aren't samples, same as
+// when scanning a folder on disk
+fn is_listable(name: &str) -> bool {
+    let mut parts: Vec<&str> = name.split('/').collect();
+    let file = parts.pop().unwrap_or_default();
+    !file.is_empty()
+        && !file.starts_with("._")
+        && !parts
+            .iter()
+            .any(|part| part.starts_with('.') || *part == "__MACOSX")
+}
+
+/// Calls `visit` with the name, uncompressed size and contents of every file in the archive.
+///
+/// Compressed tars can only be read front to back, so everything goes through one pass.
+pub fn visit_entries(
+    archive: &str,
+    mut visit: impl FnMut(&str, u64, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let kind = ArchiveKind::from_path(Path::new(archive)).ok_or(Error::InvalidPath)?;
+    let file = BufReader::new(File::open(archive)?);
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(|e| Error::Archive(e.to_string()))?;
+            for index in 0..zip.len() {
+                let mut entry = zip
+                    .by_index(index)
+                    .map_err(|e| Error::Archive(e.to_string()))?;
+                let name = entry.name().to_string();
+                if entry.is_dir() || !is_listable(&name) {
+                    continue;
+                }
+                let size = entry.size();
+                visit(&name, size, &mut entry)?;
+            }
+        }
+        ArchiveKind::Tar => visit_tar(tar::Archive::new(file), visit)?,
+        ArchiveKind::TarGz => visit_tar(tar::Archive::new(GzDecoder::new(file)), visit)?,
+    }
+    Ok(())
+}
+
+fn visit_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    mut visit: impl FnMut(&str, u64, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = tar_entry_name(&entry)? else {
+            continue;
+        };
+        let size = entry.size();
+        visit(&name, size, &mut entry)?;
+    }
+    Ok(())
+}
+
+// The name a file entry is listed under, None for anything that isn't listed
+fn tar_entry_name<R: Read>(entry: &tar::Entry<R>) -> Result<Option<String>, Error> {
+    if !entry.header().entry_type().is_file() {
+        return Ok(None);
+    }
+    let name = entry.path()?.to_string_lossy().to_string();
+    // Tars made with `tar -C dir .` prefix every entry with ./
+    let name = name.trim_start_matches("./").to_string();
+    Ok(is_listable(&name).then_some(name))
+}
+
+/// The uncompressed contents of one entry, readable and seekable like the file it was.
+pub enum EntryData {
+    Memory(Cursor<Vec<u8>>),
+    Spooled(Spool),
+}
+
+impl EntryData {
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Memory(cursor) => cursor.get_ref().len() as u64,
+            Self::Spooled(spool) => spool.len,
+        }
+    }
+}
+
+impl Read for EntryData {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(cursor) => cursor.read(buf),
+            Self::Spooled(spool) => spool.file.read(buf),
+        }
+    }
+}
+
+impl Seek for EntryData {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Memory(cursor) => cursor.seek(pos),
+            Self::Spooled(spool) => spool.file.seek(pos),
+        }
+    }
+}
+
+/// A temporary file in the spool folder, deleted when dropped.
+pub struct Spool {
+    file: File,
+    path: PathBuf,
+    len: u64,
+}
+
+impl Spool {
+    fn create() -> Result<Self, Error> {
+        let id = NEXT_SPOOL.fetch_add(1, Ordering::SeqCst);
+        let path = spool_dir()?.join(format!("{}-{}.spool", std::process::id(), id));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { file, path, len: 0 })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Decompresses an entry said to be `size` bytes, keeping it in memory only if it's small.
+///
+/// The size comes from the archive's own headers, so it only decides where to start. What's
+/// actually read is capped at `MAX_ENTRY_SIZE` either way.
+pub fn buffer_entry(name: &str, size: u64, reader: &mut dyn Read) -> Result<EntryData, Error> {
+    if size > MAX_ENTRY_SIZE {
+        return Err(Error::ArchiveEntryTooLarge(name.to_string()));
+    }
+    let mut limited = reader.take(MAX_ENTRY_SIZE + 1);
+    let mut bytes = Vec::new();
+    if size <= MEMORY_LIMIT {
+        bytes.reserve(size as usize);
+        (&mut limited)
+            .take(MEMORY_LIMIT + 1)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() as u64 <= MEMORY_LIMIT {
+            return Ok(EntryData::Memory(Cursor::new(bytes)));
+        }
+    }
+
+    // What was read before the entry turned out to be big goes first
+    let mut spool = Spool::create()?;
+    let mut writer = BufWriter::new(&spool.file);
+    writer.write_all(&bytes)?;
+    let len = bytes.len() as u64 + io::copy(&mut limited, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    if len > MAX_ENTRY_SIZE {
+        return Err(Error::ArchiveEntryTooLarge(name.to_string()));
+    }
+    spool.len = len;
+    spool.file.seek(SeekFrom::Start(0))?;
+    Ok(EntryData::Spooled(spool))
+}
+
+/// The uncompressed contents of one entry.
+pub fn open_entry(archive: &str, entry: &str) -> Result<EntryData, Error> {
+    let name = entry_path(archive, entry);
+    if ArchiveKind::from_path(Path::new(archive)) == Some(ArchiveKind::Zip) {
+        // Zips have a central directory, so there's no need to walk every entry
+        let file = BufReader::new(File::open(archive)?);
+        let mut zip = ZipArchive::new(file).map_err(|e| Error::Archive(e.to_string()))?;
+        let mut found = zip
+            .by_name(entry)
+            .map_err(|_| Error::ArchiveEntryNotFound(name.clone()))?;
+        let size = found.size();
+        return buffer_entry(&name, size, &mut found);
+    }
+
+    let index = tar_index(archive)?;
+    let &(offset, size) = index
+        .entries
+        .get(entry)
+        .ok_or_else(|| Error::ArchiveEntryNotFound(name.clone()))?;
+    let mut file = File::open(&index.data)?;
+    file.seek(SeekFrom::Start(offset))?;
+    buffer_entry(&name, size, &mut file.take(size))
+}
+
+/// The uncompressed size of one entry.
+pub fn entry_size(archive: &str, entry: &str) -> Result<u64, Error> {
+    if ArchiveKind::from_path(Path::new(archive)) == Some(ArchiveKind::Zip) {
+        let file = BufReader::new(File::open(archive)?);
+        let mut zip = ZipArchive::new(file).map_err(|e| Error::Archive(e.to_string()))?;
+        let found = zip
+            .by_name(entry)
+            .map_err(|_| Error::ArchiveEntryNotFound(entry_path(archive, entry)))?;
+        return Ok(found.size());
+    }
+
+    let index = tar_index(archive)?;
+    index
+        .entries
+        .get(entry)
+        .map(|&(_, size)| size)
+        .ok_or_else(|| Error::ArchiveEntryNotFound(entry_path(archive, entry)))
+}
+
+/// Where each entry of a tar sits, so entries can be read without walking the archive.
+///
+/// A plain tar is read in place. A compressed one can only be read front to back, so it's
+/// decompressed once into a spool file that is deleted along with the index.
+struct TarIndex {
+    modified: Option<SystemTime>,
+    data: PathBuf,
+    // Owns the file at `data` when it was spooled
+    _spool: Option<Spool>,
+    // Entry name to the offset and size of its contents in `data`
+    entries: HashMap<String, (u64, u64)>,
+}
+
+// One slot per archive, locked while that archive is indexed so parallel decodes of it wait
+// for the one index instead of each building their own
+type IndexSlot = Arc<Mutex<Option<Arc<TarIndex>>>>;
+
+static TAR_INDEXES: LazyLock<Mutex<HashMap<String, IndexSlot>>> = LazyLock::new(Default::default);
+static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+const SPOOL_DIR: &str = "crate-archives";
+
+// Indexed once per archive and again only when the file on disk changes
+fn tar_index(archive: &str) -> Result<Arc<TarIndex>, Error> {
+    let modified = fs::metadata(archive)?.modified().ok();
+    let slot = {
+        let mut indexes = TAR_INDEXES.lock().map_err(|_| Error::LockPoisoned)?;
+        Arc::clone(indexes.entry(archive.to_string()).or_default())
+    };
+    let mut slot = slot.lock().map_err(|_| Error::LockPoisoned)?;
+    if let Some(index) = slot.as_ref() {
+        if index.modified.is_some() && index.modified == modified {
+            return Ok(Arc::clone(index));
+        }
+    }
+
+    let kind = ArchiveKind::from_path(Path::new(archive)).ok_or(Error::InvalidPath)?;
+    let index = match kind {
+        ArchiveKind::TarGz => spool_tar_gz(archive, modified)?,
+        _ => index_tar(archive, modified)?,
+    };
+    println!("🗜️ Indexed {} entries of {}", index.entries.len(), archive);
+    let index = Arc::new(index);
+    *slot = Some(Arc::clone(&index));
+    Ok(index)
+}
+
+/// Drops the index of every archive not in `in_use`, deleting its spool once the last read
+/// from it is done.
+pub fn release_indexes(in_use: &HashSet<&str>) {
+    let Ok(mut indexes) = TAR_INDEXES.lock() else {
+        return;
+    };
+    indexes.retain(|archive, _| {
+        let keep = in_use.contains(archive.as_str());
+        if !keep {
+            println!("🗜️ Released the index of {}", archive);
+        }
+        keep
+    });
+}
+
+fn index_tar(archive: &str, modified: Option<SystemTime>) -> Result<TarIndex, Error> {
+    let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+    let mut entries = HashMap::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if let Some(name) = tar_entry_name(&entry)? {
+            entries.insert(name, (entry.raw_file_position(), entry.size()));
+        }
+    }
+    Ok(TarIndex {
+        modified,
+        data: PathBuf::from(archive),
+        _spool: None,
+        entries,
+    })
+}
+
+fn spool_tar_gz(archive: &str, modified: Option<SystemTime>) -> Result<TarIndex, Error> {
+    // Owns the file from here on, so a failed spool is cleaned up too
+    let spool = Spool::create()?;
+    let mut entries = HashMap::new();
+    let mut writer = BufWriter::new(&spool.file);
+    let mut offset = 0;
+    let file = BufReader::new(File::open(archive)?);
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if let Some(name) = tar_entry_name(&entry)? {
+            // Only the contents are kept, the index replaces the headers
+            let size = io::copy(&mut entry, &mut writer)?;
+            entries.insert(name, (offset, size));
+            offset += size;
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    Ok(TarIndex {
+        modified,
+        data: spool.path.clone(),
+        _spool: Some(spool),
+        entries,
+    })
+}
+
+// Spools left behind by a previous run are cleared the first time the folder is used
+fn spool_dir() -> Result<PathBuf, Error> {
+    static CLEARED: Once = Once::new();
+    let dir = std::env::temp_dir().join(SPOOL_DIR);
+    CLEARED.call_once(|| {
+        let _ = fs::remove_dir_all(&dir);
+    });
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const FILES: [(&str, &[u8]); 4] = [
+        ("Kicks/kick.wav", b"kick"),
+        ("Kicks/._kick.wav", b"fork"),
+        (".hidden/snare.wav", b"snare"),
+        ("__MACOSX/Kicks/kick.wav", b"fork"),
+    ];
+
+    fn write_zip(path: &Path) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in FILES {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar_gz(path: &Path) {
+        let gz = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (name, contents) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, format!("./{}", name), contents)
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn read_all(mut data: EntryData) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn entry_paths_split_at_the_archive() {
+        let path = "/packs/drums.zip!/Kicks/kick 01.wav";
+        assert_eq!(
+            split_entry_path(path),
+            Some(("/packs/drums.zip", "Kicks/kick 01.wav"))
+        );
+        assert_eq!(backing_file(path), "/packs/drums.zip");
+        assert_eq!(split_entry_path("/music/wow!/kick.wav"), None);
+        assert_eq!(backing_file("/music/kick.wav"), "/music/kick.wav");
+    }
+
+    #[test]
+    fn skips_resource_forks_and_hidden_folders() {
+        assert!(is_listable("Kicks/kick.wav"));
+        assert!(is_listable(".kick.wav"));
+        assert!(!is_listable("Kicks/"));
+        assert!(!is_listable("Kicks/._kick.wav"));
+        assert!(!is_listable(".hidden/snare.wav"));
+        assert!(!is_listable("__MACOSX/Kicks/kick.wav"));
+    }
+
+    #[test]
+    fn lists_and_reads_zip_entries() {
+        let dir = TempDir::new("archive");
+        let path = dir.join("pack.zip");
+        write_zip(&path);
+        let archive = path.to_str().unwrap();
+
+        let mut listed = Vec::new();
+        visit_entries(archive, |name, size, _| {
+            listed.push((name.to_string(), size));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(listed, vec![("Kicks/kick.wav".to_string(), 4)]);
+
+        assert_eq!(
+            read_all(open_entry(archive, "Kicks/kick.wav").unwrap()),
+            b"kick"
+        );
+        assert_eq!(entry_size(archive, "Kicks/kick.wav").unwrap(), 4);
+        assert!(matches!(
+            open_entry(archive, "Kicks/missing.wav"),
+            Err(Error::ArchiveEntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn spooled_tar_index_is_deleted_on_release() {
+        let dir = TempDir::new("archive");
+        let path = dir.join("pack.tar.gz");
+        write_tar_gz(&path);
+        let archive = path.to_str().unwrap();
+
+        assert_eq!(
+            read_all(open_entry(archive, "Kicks/kick.wav").unwrap()),
+            b"kick"
+        );
+        let spool = tar_index(archive).unwrap().data.clone();
+        assert!(spool.is_file());
+
+        release_indexes(&HashSet::from([archive]));
+        assert!(spool.is_file());
+        release_indexes(&HashSet::new());
+        assert!(!spool.exists());
+        // Indexed again on the next read
+        assert_eq!(entry_size(archive, "Kicks/kick.wav").unwrap(), 4);
+        release_indexes(&HashSet::new());
+    }
+
+    #[test]
+    fn big_entries_are_spooled_and_huge_ones_rejected() {
+        let small = buffer_entry("small", 4, &mut &b"tiny"[..]).unwrap();
+        assert!(matches!(small, EntryData::Memory(_)));
+
+        // The header says it's small, the contents say otherwise
+        let len = MEMORY_LIMIT + 10;
+        let mut data = buffer_entry("big", 4, &mut io::repeat(7).take(len)).unwrap();
+        assert!(matches!(data, EntryData::Spooled(_)));
+        assert_eq!(data.size(), len);
+        data.seek(SeekFrom::Start(len - 2)).unwrap();
+        assert_eq!(read_all(data), vec![7, 7]);
+
+        let mut nothing = io::empty();
+        assert!(matches!(
+            buffer_entry("huge", MAX_ENTRY_SIZE + 1, &mut nothing),
+            Err(Error::ArchiveEntryTooLarge(_))
+        ));
+    }
+}
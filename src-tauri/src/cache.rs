@@ -0,0 +1,676 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::State;
+
+use crate::archive::backing_file;
+use crate::combine::DecodedAudio;
+use crate::error::Error;
+use crate::resample::{ProjectFormat, SampleDepth, SourceSpec};
+use crate::source::SourceRef;
+use crate::state::AppState;
+
+const CACHE_MAGIC: &[u8; 4] = b"SSPC";
+// Bump whenever the entry layout or decode pipeline changes so stale entries miss
+const CACHE_VERSION: u32 = 4;
+const INDEX_FILE: &str = "index.json";
+const ENTRY_EXTENSION: &str = "pcm";
+const DEFAULT_LIMIT_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    pub source_path: String,
+    pub bytes: u64,
+    pub last_used: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheIndex {
+    limit_bytes: Option<u64>,
+    entries: HashMap<String, CacheEntry>,
+}
+
+struct CacheState {
+    dir: Option<PathBuf>,
+    limit_bytes: u64,
+    index: CacheIndex,
+    // Hits only touch last_used, the index is written on the next change or flush
+    dirty: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheInfo {
+    pub dir: Option<String>,
+    pub total_bytes: u64,
+    pub limit_bytes: u64,
+    pub entries: Vec<CacheEntry>,
+}
+
+/// Decoded PCM and waveform peaks on disk, keyed by source path, size, mtime and project format.
+///
+/// The index only guards the bookkeeping, entry files are read and written outside the lock
+/// so decode workers don't queue up behind each other.
+pub struct SampleCache {
+    state: Mutex<CacheState>,
+}
+
+impl SampleCache {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                dir: None,
+                limit_bytes: DEFAULT_LIMIT_BYTES,
+                index: CacheIndex::default(),
+                dirty: false,
+            }),
+        }
+    }
+
+    /// Points the cache at `dir` and loads its index. Until this is called nothing is cached.
+    pub fn open(&self, dir: PathBuf) -> Result<(), Error> {
+        fs::create_dir_all(&dir)?;
+        let mut index: CacheIndex = fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        index
+            .entries
+            .retain(|key, _| dir.join(entry_file_name(key)).is_file());
+        remove_orphans(&dir, &index);
+
+        let mut state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        state.limit_bytes = index.limit_bytes.unwrap_or(DEFAULT_LIMIT_BYTES);
+        state.index = index;
+        state.dir = Some(dir);
+        Ok(())
+    }
+
+    pub fn load(&self, source_path: &str, format: &ProjectFormat) -> Option<DecodedAudio> {
+        let key = cache_key(source_path, format)?;
+        let file_path = {
+            let state = self.state.lock().ok()?;
+            if !state.index.entries.contains_key(&key) {
+                return None;
+            }
+            state.dir.as_ref()?.join(entry_file_name(&key))
+        };
+
+        let decoded = read_entry(&file_path);
+        let mut state = self.state.lock().ok()?;
+        match decoded {
+            Ok(decoded) => {
+                if let Some(entry) = state.index.entries.get_mut(&key) {
+                    entry.last_used = now_secs();
+                    state.dirty = true;
+                }
+                Some(decoded)
+            }
+            Err(e) => {
+                eprintln!(
+                    "⚠️ Dropping unreadable cache entry for {}: {}",
+                    source_path, e
+                );
+                state.index.entries.remove(&key);
+                let _ = fs::remove_file(&file_path);
+                save_index(&mut state);
+                None
+            }
+        }
+    }
+
+    pub fn store(
+        &self,
+        source_path: &str,
+        format: &ProjectFormat,
+        decoded: &DecodedAudio,
+    ) -> Result<(), Error> {
+        let Some(key) = cache_key(source_path, format) else {
+            return Ok(());
+        };
+        let Some(dir) = self
+            .state
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .dir
+            .clone()
+        else {
+            return Ok(());
+        };
+
+        // Write to a temp name first so a crash never leaves a half-written entry behind
+        let file_path = dir.join(entry_file_name(&key));
+        let temp_path = file_path.with_extension("tmp");
+        write_entry(&temp_path, decoded)?;
+        fs::rename(&temp_path, &file_path)?;
+        let bytes = fs::metadata(&file_path)?.len();
+
+        let mut state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        // Older entries for the same file were made from a previous size/mtime and can't hit again
+        let stale: Vec<String> = state
+            .index
+            .entries
+            .iter()
+            .filter(|(k, entry)| entry.source_path == source_path && **k != key)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for stale_key in stale {
+            state.index.entries.remove(&stale_key);
+            let _ = fs::remove_file(dir.join(entry_file_name(&stale_key)));
+        }
+
+        state.index.entries.insert(
+            key,
+            CacheEntry {
+                source_path: source_path.to_string(),
+                bytes,
+                last_used: now_secs(),
+            },
+        );
+        evict_to_limit(&mut state);
+        save_index(&mut state);
+        Ok(())
+    }
+
+    /// Writes out last-used times recorded by cache hits since the index was last saved.
+    pub fn flush(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.dirty {
+                save_index(&mut state);
+            }
+        }
+    }
+
+    pub fn info(&self) -> Result<CacheInfo, Error> {
+        let state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        let mut entries: Vec<CacheEntry> = state.index.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| Reverse(entry.last_used));
+
+        Ok(CacheInfo {
+            dir: state
+                .dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().to_string()),
+            total_bytes: entries.iter().map(|entry| entry.bytes).sum(),
+            limit_bytes: state.limit_bytes,
+            entries,
+        })
+    }
+
+    pub fn clear(&self) -> Result<usize, Error> {
+        let mut state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        let removed = state.index.entries.len();
+        if let Some(dir) = state.dir.clone() {
+            for key in state.index.entries.keys() {
+                let _ = fs::remove_file(dir.join(entry_file_name(key)));
+            }
+        }
+        state.index.entries.clear();
+        save_index(&mut state);
+        Ok(removed)
+    }
+
+    pub fn set_limit(&self, limit_bytes: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        state.limit_bytes = limit_bytes;
+        state.index.limit_bytes = Some(limit_bytes);
+        evict_to_limit(&mut state);
+        save_index(&mut state);
+        Ok(())
+    }
+}
+
+impl Default for SampleCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn cache_key(source_path: &str, format: &ProjectFormat) -> Option<String> {
+    // The key includes the track, the file on disk is what gets checked for changes. For
+    // an entry that's its archive.
+    let path = SourceRef::parse(source_path).path;
+    let metadata = fs::metadata(backing_file(&path)).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+
+    // FNV-1a over explicit little-endian bytes, so keys stay the same across builds and
+    // platforms where std's hashers make no such promise
+    let mut hasher = Fnv64::new();
+    hasher.write(&CACHE_VERSION.to_le_bytes());
+    hasher.write_str(source_path);
+    hasher.write(&metadata.len().to_le_bytes());
+    hasher.write(&modified.to_le_bytes());
+    hasher.write(&format.sample_rate.to_le_bytes());
+    hasher.write(&format.channels.to_le_bytes());
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+struct Fnv64(u64);
+
+impl Fnv64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    // Length first so adjacent strings can't run into each other
+    fn write_str(&mut self, value: &str) {
+        self.write(&(value.len() as u64).to_le_bytes());
+        self.write(value.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn entry_file_name(key: &str) -> String {
+    format!("{}.{}", key, ENTRY_EXTENSION)
+}
+
+// Entries missing from the index (from a crash, an older version or an index that failed
+// to save) would otherwise sit on disk forever, uncounted against the limit
+fn remove_orphans(dir: &Path, index: &CacheIndex) {
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut removed = 0;
+    for file in files.flatten() {
+        let path = file.path();
+        let orphaned = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ENTRY_EXTENSION) => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_none_or(|key| !index.entries.contains_key(key)),
+            // Left by a write that never got renamed into place
+            Some("tmp") => true,
+            _ => false,
+        };
+        if orphaned && fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        println!("🗑️  Removed {} orphaned cache files", removed);
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Least recently used entries go first until the cache fits its limit again
+fn evict_to_limit(state: &mut CacheState) {
+    let mut total: u64 = state.index.entries.values().map(|entry| entry.bytes).sum();
+    if total <= state.limit_bytes {
+        return;
+    }
+
+    let mut by_age: Vec<(String, u64, u64)> = state
+        .index
+        .entries
+        .iter()
+        .map(|(key, entry)| (key.clone(), entry.last_used, entry.bytes))
+        .collect();
+    by_age.sort_by_key(|(_, last_used, _)| *last_used);
+
+    for (key, _, bytes) in by_age {
+        if total <= state.limit_bytes {
+            break;
+        }
+        state.index.entries.remove(&key);
+        if let Some(dir) = &state.dir {
+            let _ = fs::remove_file(dir.join(entry_file_name(&key)));
+        }
+        total -= bytes;
+        println!("🗑️  Evicted cache entry {}", key);
+    }
+}
+
+fn save_index(state: &mut CacheState) {
+    let Some(dir) = &state.dir else {
+        return;
+    };
+    let data = match serde_json::to_vec(&state.index) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("⚠️ Failed to serialize cache index: {}", e);
+            return;
+        }
+    };
+    // Renamed into place so a crash mid-write never leaves a truncated index
+    let index_path = dir.join(INDEX_FILE);
+    let temp_path = index_path.with_extension("tmp");
+    match fs::write(&temp_path, data).and_then(|_| fs::rename(&temp_path, &index_path)) {
+        Ok(()) => state.dirty = false,
+        Err(e) => {
+            eprintln!("⚠️ Failed to write cache index: {}", e);
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+}
+
+fn write_entry(path: &Path, decoded: &DecodedAudio) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+    writer.write_all(&decoded.original_spec.sample_rate.to_le_bytes())?;
+    writer.write_all(&decoded.original_spec.channels.to_le_bytes())?;
+    writer.write_all(&decoded.original_spec.channel_mask.to_le_bytes())?;
+    let (depth_kind, depth_bits) = match decoded.original_spec.depth {
+        None => (0u8, 0),
+        Some(SampleDepth::Int(bits)) => (1, bits),
+        Some(SampleDepth::Float(bits)) => (2, bits),
+    };
+    writer.write_all(&[depth_kind])?;
+    writer.write_all(&depth_bits.to_le_bytes())?;
+    writer.write_all(&(decoded.samples.len() as u64).to_le_bytes())?;
+    writer.write_all(&(decoded.peaks.len() as u64).to_le_bytes())?;
+    for sample in &decoded.samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    for (min, max) in &decoded.peaks {
+        writer.write_all(&min.to_le_bytes())?;
+        writer.write_all(&max.to_le_bytes())?;
+    }
+    // The report is small and only read back whole, JSON keeps it easy to extend
+    let report = serde_json::to_vec(&decoded.report).map_err(|_| Error::InvalidCacheEntry)?;
+    writer.write_all(&(report.len() as u64).to_le_bytes())?;
+    writer.write_all(&report)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Magic, version, spec, depth and the sample and peak counts
+const ENTRY_HEADER_LEN: u64 = 4 + 4 + 4 + 2 + 4 + 1 + 4 + 8 + 8;
+
+fn read_entry(path: &Path) -> Result<DecodedAudio, Error> {
+    let file = File::open(path)?;
+    // Every length in the entry is checked against what the file holds before anything is
+    // allocated for it, so a truncated or corrupt entry is a miss rather than a huge allocation
+    let mut remaining = file
+        .metadata()?
+        .len()
+        .checked_sub(ENTRY_HEADER_LEN)
+        .ok_or(Error::InvalidCacheEntry)?;
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC || read_u32(&mut reader)? != CACHE_VERSION {
+        return Err(Error::InvalidCacheEntry);
+    }
+
+    let original_spec = SourceSpec {
+        sample_rate: read_u32(&mut reader)?,
+        channels: read_u16(&mut reader)?,
+        channel_mask: read_u32(&mut reader)?,
+        depth: read_depth(&mut reader)?,
+    };
+    let sample_count = read_u64(&mut reader)?;
+    let peak_count = read_u64(&mut reader)?;
+
+    let mut bytes = vec![0u8; claim(&mut remaining, sample_count, 4)?];
+    reader.read_exact(&mut bytes)?;
+    let samples = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+
+    let mut bytes = vec![0u8; claim(&mut remaining, peak_count, 8)?];
+    reader.read_exact(&mut bytes)?;
+    let peaks = bytes
+        .chunks_exact(8)
+        .map(|b| {
+            (
+                f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                f32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+            )
+        })
+        .collect();
+
+    claim(&mut remaining, 1, 8)?;
+    let report_len = read_u64(&mut reader)?;
+    let mut bytes = vec![0u8; claim(&mut remaining, report_len, 1)?];
+    reader.read_exact(&mut bytes)?;
+    if remaining != 0 {
+        return Err(Error::InvalidCacheEntry);
+    }
+    let report = serde_json::from_slice(&bytes).map_err(|_| Error::InvalidCacheEntry)?;
+
+    Ok(DecodedAudio {
+        samples,
+        original_spec,
+        peaks,
+        report,
+    })
+}
+
+// Takes `count` items of `size` bytes out of what's left of the entry, in bytes
+fn claim(remaining: &mut u64, count: u64, size: u64) -> Result<usize, Error> {
+    let len = count
+        .checked_mul(size)
+        .filter(|&len| len <= *remaining)
+        .ok_or(Error::InvalidCacheEntry)?;
+    *remaining -= len;
+    usize::try_from(len).map_err(|_| Error::InvalidCacheEntry)
+}
+
+fn read_depth(reader: &mut impl Read) -> Result<Option<SampleDepth>, Error> {
+    let mut kind = [0u8; 1];
+    reader.read_exact(&mut kind)?;
+    let bits = read_u32(reader)?;
+    match kind[0] {
+        0 => Ok(None),
+        1 => Ok(Some(SampleDepth::Int(bits))),
+        2 => Ok(Some(SampleDepth::Float(bits))),
+        _ => Err(Error::InvalidCacheEntry),
+    }
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16, Error> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[tauri::command]
+pub fn get_cache_info(state: State<'_, Arc<AppState>>) -> Result<CacheInfo, Error> {
+    state.sample_cache.info()
+}
+
+#[tauri::command]
+pub fn clear_cache(state: State<'_, Arc<AppState>>) -> Result<String, Error> {
+    let removed = state.sample_cache.clear()?;
+    println!("🗑️  Cleared {} cache entries", removed);
+    Ok(format!("Removed {} cached files", removed))
+}
+
+#[tauri::command]
+pub fn set_cache_limit(limit_bytes: u64, state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+    state.sample_cache.set_limit(limit_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combine::DecodeReport;
+    use crate::test_support::TempDir;
+
+    fn decoded(samples: Vec<f32>) -> DecodedAudio {
+        DecodedAudio {
+            peaks: vec![(-0.5, 0.5)],
+            samples,
+            original_spec: SourceSpec {
+                sample_rate: 48000,
+                channels: 1,
+                channel_mask: 0,
+                depth: Some(SampleDepth::Int(24)),
+            },
+            report: DecodeReport {
+                packets_decoded: 3,
+                ..Default::default()
+            },
+        }
+    }
+
+    // A cache in `dir`/cache plus a source file for it to key entries on
+    fn cache_with_source(dir: &TempDir, name: &str) -> (SampleCache, String) {
+        let cache = SampleCache::new();
+        cache.open(dir.join("cache")).unwrap();
+        let source = dir.join(name);
+        fs::write(&source, name).unwrap();
+        (cache, source.to_string_lossy().to_string())
+    }
+
+    fn entry_file(cache: &SampleCache, source: &str) -> PathBuf {
+        let key = cache_key(source, &ProjectFormat::default()).unwrap();
+        let state = cache.state.lock().unwrap();
+        state.dir.as_ref().unwrap().join(entry_file_name(&key))
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = TempDir::new("cache");
+        let (cache, source) = cache_with_source(&dir, "a.wav");
+        let format = ProjectFormat::default();
+        assert!(cache.load(&source, &format).is_none());
+
+        cache
+            .store(&source, &format, &decoded(vec![0.25, -0.5, 1.0]))
+            .unwrap();
+        let loaded = cache.load(&source, &format).unwrap();
+        assert_eq!(loaded.samples, vec![0.25, -0.5, 1.0]);
+        assert_eq!(loaded.peaks, vec![(-0.5, 0.5)]);
+        assert_eq!(loaded.original_spec.depth, Some(SampleDepth::Int(24)));
+        assert_eq!(loaded.report.packets_decoded, 3);
+
+        // Another project format is another entry
+        let mono = ProjectFormat {
+            sample_rate: 22050,
+            channels: 1,
+        };
+        assert!(cache.load(&source, &mono).is_none());
+    }
+
+    #[test]
+    fn changed_sources_miss() {
+        let dir = TempDir::new("cache");
+        let (cache, source) = cache_with_source(&dir, "a.wav");
+        let format = ProjectFormat::default();
+        cache
+            .store(&source, &format, &decoded(vec![0.5; 4]))
+            .unwrap();
+        fs::write(&source, "a different size").unwrap();
+        assert!(cache.load(&source, &format).is_none());
+    }
+
+    #[test]
+    fn corrupt_entries_are_dropped_as_misses() {
+        let dir = TempDir::new("cache");
+        let (cache, source) = cache_with_source(&dir, "a.wav");
+        let format = ProjectFormat::default();
+        cache
+            .store(&source, &format, &decoded(vec![0.5; 16]))
+            .unwrap();
+        let path = entry_file(&cache, &source);
+
+        // A sample count far past the end of the file
+        let mut bytes = fs::read(&path).unwrap();
+        let count_at = (ENTRY_HEADER_LEN - 16) as usize;
+        bytes[count_at..count_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(cache.load(&source, &format).is_none());
+        assert!(!path.exists());
+        assert!(cache.info().unwrap().entries.is_empty());
+
+        // And one cut short
+        cache
+            .store(&source, &format, &decoded(vec![0.5; 16]))
+            .unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(cache.load(&source, &format).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_entries_go_first() {
+        let dir = TempDir::new("cache");
+        let (cache, first) = cache_with_source(&dir, "first.wav");
+        let second = dir.join("second.wav");
+        fs::write(&second, "second").unwrap();
+        let second = second.to_string_lossy().to_string();
+        let format = ProjectFormat::default();
+        cache
+            .store(&first, &format, &decoded(vec![0.5; 64]))
+            .unwrap();
+        cache
+            .store(&second, &format, &decoded(vec![0.5; 64]))
+            .unwrap();
+        {
+            let mut state = cache.state.lock().unwrap();
+            for entry in state.index.entries.values_mut() {
+                entry.last_used = if entry.source_path == first { 1 } else { 2 };
+            }
+        }
+
+        let one_entry = cache.info().unwrap().entries[0].bytes;
+        cache.set_limit(one_entry).unwrap();
+        let info = cache.info().unwrap();
+        assert_eq!(info.entries.len(), 1);
+        assert_eq!(info.entries[0].source_path, second);
+        assert!(!entry_file(&cache, &first).exists());
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.load(&second, &format).is_none());
+    }
+
+    #[test]
+    fn orphaned_files_are_removed_on_open() {
+        let dir = TempDir::new("cache");
+        let cache_dir = dir.join("cache");
+        fs::create_dir_all(&cache_dir).unwrap();
+        let orphan = cache_dir.join(entry_file_name("orphan"));
+        let partial = cache_dir.join("half-written.tmp");
+        fs::write(&orphan, "samples").unwrap();
+        fs::write(&partial, "samples").unwrap();
+        SampleCache::new().open(cache_dir).unwrap();
+        assert!(!orphan.exists());
+        assert!(!partial.exists());
+    }
+}
@@ -1,33 +1,37 @@
+use crate::archive::{backing_file, release_indexes};
+use crate::cache::SampleCache;
+use crate::duplicates::{content_hash, refresh_duplicates, DuplicateGroup};
+use crate::encoder::{wav_spec, write_wav_samples};
 use crate::error::Error;
+use crate::playlist::{cue_tracks, is_playlist, PlaylistKind};
+use crate::render::{Arrangement, LaneMixer, Mixdown, Trim};
+use crate::resample::{convert_to_project, ProjectFormat, SampleDepth, SourceSpec};
+use crate::sample_store::SamplesSource;
+use crate::silence::{
+    compress_timeline, suggest_trim, time_saved, with_compressed, SilenceSettings,
+};
+use crate::source::{open_format, select_track, SourceRef};
 use crate::state::{AppState, AudioFile};
-use hound::{SampleFormat, WavSpec, WavWriter};
-use rodio::buffer::SamplesBuffer;
+use crate::timeline::{self, Clip};
+use hound::WavWriter;
 use rodio::{OutputStream, Sink};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{self, sleep};
 use std::time::{Duration, Instant};
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::default::{get_codecs, get_probe};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::default::get_codecs;
 use tauri::ipc::Channel;
 use tauri::{AppHandle, Emitter, State}; // Add to Cargo.toml
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct CombineAudioResult {
-    output: String,
-    svg_path: String,
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CachedCombineResult {
@@ -35,15 +39,20 @@ pub struct CachedCombineResult {
     duration: f64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct CombineEvent {
-    progress: f32,
-}
-
-pub fn generate_waveform_path(samples: &[i16], width: usize, height: usize, offset: f64) -> String {
+// Frames summarised by each entry of AudioFile::peaks
+pub const PEAK_BLOCK_FRAMES: usize = 512;
+
+/// Vertical min/max bars for `samples`, scaled by `gain` (called with a sample index).
+pub fn generate_waveform_path(
+    samples: &[f32],
+    width: usize,
+    height: usize,
+    offset: f64,
+    gain: impl Fn(usize) -> f32,
+) -> String {
     let samples_per_pixel = samples.len() / width.max(1);
     let mid_y = height as f32 / 2.0;
-    let amplitude_scale = mid_y / i16::MAX as f32;
+    let amplitude_scale = mid_y;
 
     let mut d = String::new();
     for x in 0..width {
@@ -55,8 +64,9 @@ pub fn generate_waveform_path(samples: &[i16], width: usize, height: usize, offs
             continue;
         }
 
-        let min = *slice.iter().min().unwrap_or(&0) as f32;
-        let max = *slice.iter().max().unwrap_or(&0) as f32;
+        let (min, max) = min_max(slice);
+        let gain = gain((start + end) / 2);
+        let (min, max) = ((min * gain).max(-1.0), (max * gain).min(1.0));
 
         let y1 = mid_y - max * amplitude_scale;
         let y2 = mid_y - min * amplitude_scale;
@@ -70,14 +80,55 @@ pub fn generate_waveform_path(samples: &[i16], width: usize, height: usize, offs
     d
 }
 
+/// Min/max pairs over fixed blocks of frames, enough to draw a file's overview without its samples.
+pub fn compute_peaks(samples: &[f32], channels: u16) -> Vec<(f32, f32)> {
+    let block_len = PEAK_BLOCK_FRAMES * channels.max(1) as usize;
+    samples.chunks(block_len).map(min_max).collect()
+}
+
+// Clamped to full scale so overs from float sources don't draw outside the lane
+fn min_max(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let (min, max) = samples.iter().fold((f32::MAX, f32::MIN), |(min, max), &s| {
+        (min.min(s), max.max(s))
+    });
+    (min.clamp(-1.0, 1.0), max.clamp(-1.0, 1.0))
+}
+
+#[tauri::command]
+pub fn get_waveform_peaks(
+    id: Uuid,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<(f32, f32)>, Error> {
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    audio_files
+        .values()
+        .find(|file| file.id == id)
+        .map(|file| file.peaks.clone())
+        .ok_or(Error::NoAudioData)
+}
+
+#[tauri::command]
+pub fn get_decode_report(id: Uuid, state: State<'_, Arc<AppState>>) -> Result<DecodeReport, Error> {
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    audio_files
+        .values()
+        .find(|file| file.id == id)
+        .map(|file| file.decode_report.clone())
+        .ok_or(Error::NoAudioData)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AudioSend {
     path: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Section {
-    folderPath: String,
+    folder_path: String,
     paths: Vec<AudioSend>,
 }
 
@@ -89,11 +140,104 @@ pub struct Section {
     content = "data"
 )]
 pub enum BufferAudioEvent {
-    Started { content_length: usize },
-    Progress { chunk_length: usize },
+    Started {
+        content_length: usize,
+    },
+    FileStarted {
+        path: String,
+        id: Uuid,
+    },
+    FileFinished {
+        path: String,
+        id: Uuid,
+        report: DecodeReport,
+    },
+    FileFailed {
+        path: String,
+        id: Uuid,
+        error: String,
+    },
+    DuplicatesFound {
+        groups: Vec<DuplicateGroup>,
+    },
     Finished,
 }
 
+struct DecodeJob {
+    path: String,
+    id: Uuid,
+}
+
+struct DecodeResult {
+    path: String,
+    id: Uuid,
+    // Decoded with its suggested trim, both worked out on the worker
+    decoded: Result<(DecodedAudio, Trim), Error>,
+}
+
+/// Decodes `jobs` on a worker pool, handing each result to `on_done` on the calling thread as
+/// soon as it's ready. Workers stop taking jobs once `cancel_token` moves past `token`, and a
+/// file that panics its decoder fails on its own.
+fn decode_jobs(
+    jobs: Vec<DecodeJob>,
+    project_format: &ProjectFormat,
+    silence: &SilenceSettings,
+    state: &AppState,
+    token: u64,
+    on_start: impl Fn(&DecodeJob) + Sync,
+    mut on_done: impl FnMut(DecodeResult),
+) {
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(jobs.len())
+        .max(1);
+    let queue = Mutex::new(jobs.into_iter());
+    let (result_tx, result_rx) = mpsc::channel::<DecodeResult>();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let result_tx = result_tx.clone();
+            let queue = &queue;
+            let on_start = &on_start;
+            scope.spawn(move || loop {
+                // A newer update supersedes this one
+                if state.cancel_token.load(Ordering::SeqCst) != token {
+                    break;
+                }
+                let Ok(mut queue) = queue.lock() else {
+                    break;
+                };
+                let Some(job) = queue.next() else {
+                    break;
+                };
+                drop(queue);
+                on_start(&job);
+                let decoded = panic::catch_unwind(AssertUnwindSafe(|| {
+                    load_audio(&job.path, project_format, &state.sample_cache).map(|decoded| {
+                        let trim = suggest_trim(&decoded.samples, project_format, silence);
+                        (decoded, trim)
+                    })
+                }))
+                .unwrap_or_else(|_| Err(Error::WorkerPanicked(job.path.clone())));
+                let result = DecodeResult {
+                    path: job.path,
+                    id: job.id,
+                    decoded,
+                };
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+
+        for result in result_rx {
+            on_done(result);
+        }
+    });
+}
+
 #[tauri::command]
 pub async fn update_inputs(
     sections: Vec<Section>,
@@ -109,14 +253,17 @@ pub async fn update_inputs(
     // *count.lock().unwrap() += 1;
 
     tauri::async_runtime::spawn_blocking(move || {
-        let mut audio_files = state.audio_files.lock().unwrap();
-        let mut inserted_count = 0;
         let mut removed_count = 0;
 
-        let valid_paths: HashSet<String> = sections
-            .iter()
-            .flat_map(|section| section.paths.iter().map(|audio| audio.path.clone()))
-            .collect();
+        // Path to the section it was listed under, the first section wins if it's in several
+        let mut valid_paths: HashMap<String, String> = HashMap::new();
+        for section in &sections {
+            for audio in &section.paths {
+                valid_paths
+                    .entry(audio.path.clone())
+                    .or_insert_with(|| section.folder_path.clone());
+            }
+        }
 
         on_event
             .send(BufferAudioEvent::Started {
@@ -124,64 +271,224 @@ pub async fn update_inputs(
             })
             .unwrap();
 
-        // Collect IDs of files that will be removed
-        let removed_ids: Vec<Uuid> = audio_files
-            .iter()
-            .filter_map(|(path, file)| {
-                if !valid_paths.contains(path) {
-                    Some(file.id)
-                } else {
-                    None
+        // Only hold the lock long enough to find the new files, decoding happens without it
+        // so the rest of the app stays responsive. Nothing changes until decoding is done.
+        let jobs: Vec<DecodeJob> = {
+            let audio_files = state.audio_files.lock().unwrap();
+
+            // Copies under different paths are caught after decoding, by content hash
+            valid_paths
+                .keys()
+                .filter(|path| !audio_files.contains_key(*path))
+                .map(|path| DecodeJob {
+                    path: path.clone(),
+                    id: Uuid::new_v4(),
+                })
+                .collect()
+        };
+
+        let project_format = *state.project_format.lock().unwrap();
+        let silence = *state.silence.lock().unwrap();
+        let total_jobs = jobs.len();
+        let mut inserted_count = 0;
+        let mut failed_count = 0;
+        let mut inserted: HashSet<String> = HashSet::new();
+        let mut decoded_files: Vec<AudioFile> = Vec::with_capacity(total_jobs);
+
+        // Insert each file as soon as its worker is done with it
+        let on_start = |job: &DecodeJob| {
+            let _ = on_event.send(BufferAudioEvent::FileStarted {
+                path: job.path.clone(),
+                id: job.id,
+            });
+        };
+        let mut done = 0;
+        decode_jobs(
+            jobs,
+            &project_format,
+            &silence,
+            &state,
+            current_token,
+            on_start,
+            |result| {
+                let audio_file = result.decoded.and_then(|(decoded, suggested_trim)| {
+                    let hash = content_hash(&decoded.samples);
+                    Ok(AudioFile {
+                        samples: state.sample_store.insert(decoded.samples)?,
+                        id: result.id,
+                        path: result.path.clone(),
+                        original_spec: decoded.original_spec,
+                        peaks: decoded.peaks,
+                        decode_report: decoded.report,
+                        section: valid_paths[&result.path].clone(),
+                        content_hash: hash,
+                        duplicate_of: None,
+                        fingerprint: None,
+                        analysis: None,
+                        suggested_trim,
+                    })
+                });
+                match audio_file {
+                    Ok(audio_file) => {
+                        if state.cancel_token.load(Ordering::SeqCst) != current_token {
+                            return;
+                        }
+                        let report = audio_file.decode_report.clone();
+                        decoded_files.push(audio_file);
+                        inserted_count += 1;
+                        inserted.insert(result.path.clone());
+                        let _ = on_event.send(BufferAudioEvent::FileFinished {
+                            path: result.path,
+                            id: result.id,
+                            report,
+                        });
+                    }
+                    Err(e) => {
+                        failed_count += 1;
+                        eprintln!("⚠️ Failed to decode {}: {}", result.path, e);
+                        let _ = on_event.send(BufferAudioEvent::FileFailed {
+                            path: result.path,
+                            id: result.id,
+                            error: e.to_string(),
+                        });
+                    }
                 }
-            })
-            .collect();
 
-        audio_files.retain(|path, _| {
-            if valid_paths.contains(path) {
-                true
-            } else {
-                removed_count += 1;
-                false
-            }
-        });
+                done += 1;
+                let progress = done as f32 / total_jobs as f32;
+                let _ = app_handle.emit("buffering-progress", progress);
+            },
+        );
+        // Cache hits only touched last-used times in memory
+        state.sample_cache.flush();
+
+        if state.cancel_token.load(Ordering::SeqCst) != current_token {
+            println!("🛑 Superseded by a newer update");
+            return Ok(format!(
+                "Cancelled after decoding {}, nothing changed.",
+                inserted_count
+            ));
+        }
 
-        // Remove corresponding IDs from custom_order
-        if !removed_ids.is_empty() {
-            let mut custom_order = state.custom_order.lock().unwrap();
-            custom_order.retain(|id| !removed_ids.contains(id));
-            println!("Removed {} IDs from custom_order", removed_ids.len());
+        // A CUE sheet's tracks become clips on the file they're cut from, trimmed to their
+        // index times. The file is decoded once however many tracks it holds.
+        let mut track_trims: HashMap<String, Vec<Trim>> = HashMap::new();
+        for section in &sections {
+            if PlaylistKind::from_path(Path::new(&section.folder_path)) != Some(PlaylistKind::Cue) {
+                continue;
+            }
+            match cue_tracks(&section.folder_path) {
+                Ok(tracks) => {
+                    for track in tracks {
+                        if valid_paths.get(&track.file) == Some(&section.folder_path) {
+                            track_trims
+                                .entry(track.file.clone())
+                                .or_default()
+                                .push(track.trim());
+                        }
+                    }
+                }
+                Err(e) => eprintln!(
+                    "⚠️ Failed to read the tracks of {}: {}",
+                    section.folder_path, e
+                ),
+            }
         }
 
-        let mut combined: Vec<i16> = Vec::new();
-        //TODO: DUPLICATE FILES
-        for (i, path) in valid_paths.iter().enumerate() {
-            if !audio_files.contains_key(path) {
-                let samples = get_samples(path)?;
-                combined.extend(&samples);
-                audio_files.insert(
-                    path.clone(),
-                    AudioFile {
-                        samples,
-                        start_offset: 0.,
-                        waveform_path: String::from(""),
-                        id: Uuid::new_v4(),
-                        path: path.clone(),
-                    },
+        // Taken right before the change, edits made while decoding stay out of this step
+        let edit = state.history.begin(&state, true)?;
+        {
+            let mut audio_files = state.audio_files.lock().unwrap();
+            let mut timeline = state.timeline.lock().unwrap();
+
+            audio_files.retain(|path, file| {
+                if let Some(section) = valid_paths.get(path) {
+                    file.section = section.clone();
+                    true
+                } else {
+                    removed_count += 1;
+                    false
+                }
+            });
+            // Clips of the removed files go with them
+            if removed_count > 0 {
+                let before = timeline.len();
+                timeline.retain(|clip| audio_files.contains_key(&clip.source));
+                println!(
+                    "Removed {} clips from the timeline",
+                    before - timeline.len()
                 );
-                let progress = (i as f32) / ((valid_paths.len() - 1) as f32);
-                let _ = app_handle.emit("buffering-progress", progress);
-                inserted_count += 1;
-                println!("INSERTING {} into BTree", path.clone());
             }
+            for audio_file in decoded_files {
+                println!("INSERTING {} into BTree", audio_file.path);
+                audio_files.insert(audio_file.path.clone(), audio_file);
+            }
+
+            // Every new source gets a clip at the end of the timeline. Playlists and CUE
+            // sheets have an order of their own, which path order would lose, so those go in
+            // section order.
+            let mut new_paths: Vec<&String> = inserted.iter().collect();
+            new_paths.sort();
+            if sections
+                .iter()
+                .any(|section| is_playlist(Path::new(&section.folder_path)))
+            {
+                let valid_paths = &valid_paths;
+                new_paths = sections
+                    .iter()
+                    .flat_map(|section| {
+                        section
+                            .paths
+                            .iter()
+                            // Only where the file was assigned, a path listed twice goes in once
+                            .filter(move |audio| valid_paths[&audio.path] == section.folder_path)
+                            .map(|audio| &audio.path)
+                    })
+                    .filter(|path| inserted.contains(*path))
+                    .collect();
+            }
+            let mut placed: HashSet<&String> = HashSet::new();
+            new_paths.retain(|path| placed.insert(*path));
+            for path in new_paths {
+                if let Some(file) = audio_files.get(path) {
+                    let trims = track_trims.get(path).map_or(&[][..], Vec::as_slice);
+                    timeline.extend(Clip::for_tracks(file, trims));
+                }
+            }
+        }
+
+        edit.record("Update inputs")?;
+
+        // Archives no longer imported from don't need their entries indexed
+        let in_use: HashSet<&str> = sections
+            .iter()
+            .map(|section| backing_file(&section.folder_path))
+            .collect();
+        release_indexes(&in_use);
+
+        // Keep watching exactly the sections this update imported
+        let watched = sections
+            .iter()
+            .map(|section| section.folder_path.clone())
+            .collect();
+        if let Err(e) = state.folder_watcher.sync(&app_handle, watched) {
+            eprintln!("⚠️ Folder watching unavailable: {}", e);
         }
 
+        let duplicates = refresh_duplicates(&state)?;
+        if !duplicates.is_empty() {
+            println!("Found {} groups of duplicate files", duplicates.len());
+            let _ = on_event.send(BufferAudioEvent::DuplicatesFound { groups: duplicates });
+        }
+
+        // The inputs changed, so the old mix is stale until the next combine
         let mut combined_audio = state.combined_audio.lock().unwrap();
-        *combined_audio = Some(combined);
-        on_event.send(BufferAudioEvent::Finished);
+        *combined_audio = None;
+        let _ = on_event.send(BufferAudioEvent::Finished);
 
         Ok(format!(
-            "Inserted {}, removed {}.",
-            inserted_count, removed_count
+            "Inserted {}, removed {}, failed {}.",
+            inserted_count, removed_count, failed_count
         ))
     })
     .await?
@@ -198,12 +505,20 @@ pub enum CombineAudioEvent {
     Started {
         content_length: usize,
         duration: f64,
+        // Seconds taken off by pause compression
+        time_saved: f64,
     },
     Progress {
         svg_path: String,
         start_offset: f64,
+        // In samples per channel
+        start: u64,
+        lane: Uuid,
         file_name: String,
+        // Played length after trimming and pause compression, as a fraction of the mix
         size: f64,
+        // The same length in samples per channel
+        length: u64,
         id: String,
     },
     Finished {
@@ -231,42 +546,61 @@ pub async fn combine_all_cached_samples(
         println!("ORIGIN: {}, COUNT: {}", orig, count.lock().unwrap());
         state.buffering_samples.store(true, Ordering::Relaxed);
 
-        let mut audio_files = state.audio_files.lock().unwrap();
-        let sample_rate = 44100.0;
-        let full_waveform_width = 1000.0;
-
-        let mut combined_samples: Vec<i16> = Vec::new();
-        let mut total_samples = 0;
+        // Long pauses inside clips are cut down before anything is laid out
+        let compressed = compress_timeline(&state)?;
+        let section_pauses = state.section_pauses.lock().unwrap().clone();
 
-        // Collect files in the specified order (custom or default BTreeMap order)
-        let ordered_files: Vec<AudioFile> = if let Some(order) = custom_order {
-            println!("USING CUSTOM ORDER");
-            // Use custom order
-            order
-                .iter()
-                .filter_map(|id| audio_files.values().find(|f| &f.id == id))
-                .cloned()
-                .collect()
-        } else {
-            println!("USING DEFAULT BTREE ORDER");
-            // Use default BTreeMap order
-            audio_files.values().cloned().collect()
-        };
+        let audio_files = state.audio_files.lock().unwrap();
+        let project_format = *state.project_format.lock().unwrap();
+        let samples_per_second = project_format.sample_rate as f64 * project_format.channels as f64;
+        let full_waveform_width = 1000.0;
 
-        for audio_file in &ordered_files {
-            println!("adding to samples: {}", audio_file.path);
-            if *process_count.lock().unwrap() != orig {
-                println!("🛑 Stopped while adding samples");
-                return Ok("stopped".to_string());
+        // Collect clips in the specified order (custom or timeline order)
+        let clips: Vec<Clip> = {
+            let timeline = state.timeline.lock().unwrap();
+            if let Some(order) = custom_order {
+                println!("USING CUSTOM ORDER");
+                order
+                    .iter()
+                    .filter_map(|id| timeline.iter().find(|clip| &clip.id == id))
+                    .cloned()
+                    .collect()
+            } else {
+                println!("USING TIMELINE ORDER");
+                timeline.clone()
             }
-            total_samples += audio_file.samples.len();
+        };
+        let auto_trim = state.silence.lock().unwrap().auto_trim;
+        let timeline = timeline::resolve(&clips, &audio_files, auto_trim);
+        let timeline = with_compressed(timeline, &compressed, &section_pauses);
+        let saved = time_saved(&timeline, &compressed, &project_format);
+        if saved > 0.0 {
+            let shortened: Vec<_> = timeline
+                .iter()
+                .filter(|clip| clip.compressed.is_some())
+                .filter_map(|clip| compressed.get(&clip.id()))
+                .collect();
+            println!(
+                "🗣️ Shortened {} pauses in {} clips, {:.2}s saved",
+                shortened.iter().map(|clip| clip.pauses).sum::<usize>(),
+                shortened.len(),
+                saved
+            );
         }
 
-        let duration = total_samples as f64 / sample_rate;
+        // Crossfades overlap neighbouring clips and gaps space them out, both change the length
+        let crossfades = state.crossfades.lock().unwrap().clone();
+        let gaps = state.gaps.lock().unwrap().clone();
+        let lanes = state.lanes.lock().unwrap().clone();
+        let arrangement = Arrangement::new(&timeline, &crossfades, &gaps, &project_format);
+        let total_samples = arrangement.total;
+
+        let duration = total_samples as f64 / samples_per_second;
         on_event
             .send(CombineAudioEvent::Started {
-                content_length: ordered_files.len(),
+                content_length: timeline.len(),
                 duration,
+                time_saved: saved,
             })
             .unwrap();
 
@@ -282,57 +616,81 @@ pub async fn combine_all_cached_samples(
             return Ok("No samples".to_string());
         }
 
-        let mut current_sample_offset = 0;
+        // Large mixes are written straight to disk rather than built up in memory
+        let mut combined_samples = state.sample_store.writer(total_samples)?;
+        let mut mixdown = Mixdown::new(project_format);
+        let mut mixer = LaneMixer::new(&arrangement, &lanes, &project_format);
+        let mut out = |samples: &[f32]| combined_samples.extend_from_slice(samples);
+        let mut write = |samples: &[f32]| mixer.write(samples, &mut out);
         let mut combined_svg_string = String::from("");
+        let channels = project_format.channels.max(1) as usize;
 
-        // Process files in the specified order
-        for audio_file in ordered_files {
+        // Process clips in the specified order, the main lane first so it streams out in
+        // order and the other lanes are mixed in as it passes them
+        for (index, (clip, current_sample_offset)) in
+            arrangement.starts(&project_format).into_iter().enumerate()
+        {
             println!("test: {}", *process_count.lock().unwrap());
-            println!("audio file: {} ", audio_file.path.clone());
+            println!("audio file: {} ", clip.source.path.clone());
             if *process_count.lock().unwrap() != orig {
                 println!("🛑 Stopped while adding samples");
                 return Ok("stopped".to_string());
             }
 
-            // Update the original file in the BTreeMap with new start_offset and waveform_path
-            if let Some(original_file) = audio_files.values_mut().find(|f| f.id == audio_file.id) {
-                original_file.start_offset =
-                    (current_sample_offset as f64) / (total_samples as f64);
-                combined_samples.extend(&audio_file.samples);
-
-                let relative_length = audio_file.samples.len() as f64 / total_samples as f64;
-                let segment_width = full_waveform_width * relative_length;
-                let x_offset =
-                    full_waveform_width * (current_sample_offset as f64 / total_samples as f64);
-                if *process_count.lock().unwrap() != orig {
-                    println!("🛑 Stopped while adding samples");
-                    return Ok("stopped".to_string());
-                }
-                let svg_path = generate_waveform_path(
-                    &audio_file.samples,
-                    segment_width as usize,
-                    70,
-                    x_offset,
-                );
-                original_file.waveform_path = svg_path.clone();
-                on_event
-                    .send(CombineAudioEvent::Progress {
-                        file_name: audio_file.path.clone(),
-                        svg_path: original_file.waveform_path.clone(),
-                        start_offset: original_file.start_offset,
-                        size: relative_length,
-                        id: audio_file.id.to_string(),
-                    })
-                    .unwrap();
-                if *process_count.lock().unwrap() != orig {
-                    println!("🛑 Stopped while adding samples");
-                    return Ok("stopped".to_string());
-                }
-                // sleep(Duration::from_millis(500)); // slow down 200ms per file
-                combined_svg_string.push_str(&svg_path);
-                current_sample_offset += audio_file.samples.len();
+            let start = (current_sample_offset / channels) as u64;
+            let start_offset = (current_sample_offset as f64) / (total_samples as f64);
+            let samples = clip.samples(&project_format);
+            if index < arrangement.main.len() {
+                mixdown.push(&clip, &arrangement.layout, index, &mut write)?;
+            }
+
+            let relative_length = samples.len() as f64 / total_samples as f64;
+            let segment_width = full_waveform_width * relative_length;
+            let x_offset =
+                full_waveform_width * (current_sample_offset as f64 / total_samples as f64);
+            if *process_count.lock().unwrap() != orig {
+                println!("🛑 Stopped while adding samples");
+                return Ok("stopped".to_string());
+            }
+            // Drawn with the clip's gain and fades so the picture matches the mix
+            let shape = clip.shape(&project_format);
+            let svg_path =
+                generate_waveform_path(samples, segment_width as usize, 70, x_offset, |i| {
+                    shape.gain_at(i / channels)
+                });
+
+            // Update the clip on the timeline with its new start and waveform_path
+            if let Some(placed) = state
+                .timeline
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .find(|placed| placed.id == clip.id())
+            {
+                placed.start = start;
+                placed.waveform_path = svg_path.clone();
             }
+            on_event
+                .send(CombineAudioEvent::Progress {
+                    file_name: clip.source.path.clone(),
+                    svg_path: svg_path.clone(),
+                    start_offset,
+                    start,
+                    lane: clip.clip.lane,
+                    size: relative_length,
+                    length: (samples.len() / channels) as u64,
+                    id: clip.id().to_string(),
+                })
+                .unwrap();
+            if *process_count.lock().unwrap() != orig {
+                println!("🛑 Stopped while adding samples");
+                return Ok("stopped".to_string());
+            }
+            // sleep(Duration::from_millis(500)); // slow down 200ms per file
+            combined_svg_string.push_str(&svg_path);
         }
+        mixdown.finish(&arrangement.layout, &mut write)?;
+        mixer.finish(&mut out)?;
 
         println!("✅ Successfully combined all samples");
         let _ = app.emit("combine-complete", ());
@@ -340,7 +698,7 @@ pub async fn combine_all_cached_samples(
 
         // Store the combined samples in state
         let mut combined_audio = state.combined_audio.lock().unwrap();
-        *combined_audio = Some(combined_samples);
+        *combined_audio = Some(combined_samples.finish()?);
 
         let mut state_svg_path = state.svg_path.lock().unwrap();
         on_event
@@ -355,13 +713,8 @@ pub async fn combine_all_cached_samples(
     .await? // <-- This unwraps spawn_blocking Result
 }
 #[tauri::command]
-pub async fn test_async(
-    state: State<'_, Arc<AppState>>,
-    app: AppHandle,
-    on_event: Channel<CombineAudioEvent>,
-) -> Result<String, Error> {
+pub async fn test_async(state: State<'_, Arc<AppState>>) -> Result<String, Error> {
     let state = Arc::clone(&state); // Clone for thread
-    let app = app.clone(); // Clone for thread
 
     let count = state.combine_process.clone();
     *count.lock().unwrap() += 1;
@@ -374,7 +727,7 @@ pub async fn test_async(
 }
 
 #[tauri::command]
-pub fn cancel_combine(state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+pub fn cancel_combine() -> Result<(), Error> {
     println!("🚨 Cancellation flag set");
     Ok(())
 }
@@ -382,7 +735,7 @@ pub fn cancel_combine(state: State<'_, Arc<AppState>>) -> Result<(), Error> {
 #[tauri::command]
 pub fn export_combined_audio_as_wav(
     state: State<'_, Arc<AppState>>,
-    outputPath: String,
+    output_path: String,
 ) -> Result<String, String> {
     // Get a lock on the combined audio
     let combined_audio = state.combined_audio.lock().unwrap();
@@ -394,27 +747,24 @@ pub fn export_combined_audio_as_wav(
         return Err("⚠️ Combined audio is empty".to_string());
     }
 
-    // Define WAV format
-    let spec = WavSpec {
-        channels: 2,
-        sample_rate: 44100,
-        bits_per_sample: 16,
-        sample_format: SampleFormat::Int,
+    // Define WAV format, deep enough for the highest resolution source
+    let project_format = *state.project_format.lock().unwrap();
+    let depth = {
+        let audio_files = state.audio_files.lock().unwrap();
+        SampleDepth::widest(audio_files.values().map(|file| file.original_spec.depth))
     };
+    let spec = wav_spec(project_format.sample_rate, project_format.channels, depth);
 
     // Create file
-    let path = Path::new(&outputPath);
-    let writer = WavWriter::create(&path, spec).map_err(|e| e.to_string())?;
+    let path = Path::new(&output_path);
+    let mut writer = WavWriter::create(path, spec).map_err(|e| e.to_string())?;
 
     // Write samples
-    let mut writer = writer;
-    for sample in samples {
-        writer.write_sample(*sample).map_err(|e| e.to_string())?;
-    }
+    write_wav_samples(&mut writer, samples, depth).map_err(|e| e.to_string())?;
 
     writer.finalize().map_err(|e| e.to_string())?;
 
-    Ok(format!("WAV file successfully saved to {}", outputPath))
+    Ok(format!("WAV file successfully saved to {}", output_path))
 }
 
 #[tauri::command]
@@ -443,18 +793,18 @@ pub fn play_combined_audio(
             return;
         }
 
-        let sample_rate = 44100;
-        let channels = 2;
+        let project_format = *state.project_format.lock().unwrap();
+        let sample_rate = project_format.sample_rate;
+        let channels = project_format.channels;
         let total_samples = samples.len();
-        let start_sample_index =
-            (start_seconds.unwrap_or(0.0) * sample_rate as f32 * channels as f32).round() as usize;
+        let start_frame = (start_seconds.unwrap_or(0.0) * sample_rate as f32).round() as usize;
+        let start_sample_index = start_frame * channels as usize;
 
         if start_sample_index >= total_samples {
             eprintln!("Start time exceeds audio length.");
             return;
         }
 
-        let trimmed_samples = &samples[start_sample_index..];
         let (_stream, stream_handle) = match OutputStream::try_default() {
             Ok(output) => output,
             Err(e) => {
@@ -471,10 +821,12 @@ pub fn play_combined_audio(
             }
         };
 
-        let duration = trimmed_samples.len() as f32 / (channels as f32 * sample_rate as f32);
+        let remaining_samples = total_samples - start_sample_index;
+        let duration = remaining_samples as f32 / (channels as f32 * sample_rate as f32);
         let start = Instant::now();
 
-        let source = SamplesBuffer::new(channels as u16, sample_rate, trimmed_samples.to_vec());
+        // Reads straight from the store, so spilled mixes are paged in as they play
+        let source = SamplesSource::new(samples, start_sample_index, channels, sample_rate);
         sink.append(source);
         sink.set_volume(1.0);
         sink.play();
@@ -516,50 +868,309 @@ pub fn pause_combined_audio(state: State<'_, Arc<AppState>>) {
     }
 }
 
-#[tauri::command]
-pub fn resume_combined_audio(state: State<'_, Arc<AppState>>) {
-    let current_song = state.current_song.lock().unwrap();
-    if let Some(sink) = &*current_song {
-        sink.play();
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub original_spec: SourceSpec,
+    pub peaks: Vec<(f32, f32)>,
+    pub report: DecodeReport,
+}
+
+/// What happened while decoding a file, so damaged files can be flagged instead of
+/// silently cut short.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeReport {
+    pub packets_decoded: u64,
+    pub packets_dropped: u64,
+    // Frames that made it out of the decoder, in the source's own rate
+    pub frames_recovered: u64,
+    pub resets: u32,
+    // The stream ended on an error rather than a clean end of file
+    pub truncated: bool,
+    pub first_error: Option<String>,
+}
+
+impl DecodeReport {
+    fn note_error(&mut self, error: impl std::fmt::Display) {
+        if self.first_error.is_none() {
+            self.first_error = Some(error.to_string());
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.packets_dropped == 0 && !self.truncated
     }
 }
 
-fn get_samples(file_path: &str) -> Result<Vec<i16>, Error> {
-    let file = File::open(file_path).map_err(|_| Error::InvalidPath)?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-    let probed = get_probe()
-        .format(
-            &Default::default(),
-            mss,
-            &FormatOptions::default(),
-            &MetadataOptions::default(),
-        )
-        .map_err(|_| Error::InvalidPath)?;
-
-    let mut format = probed.format;
-    let track = format.default_track().ok_or(Error::NoDefaultTrackFound)?;
-    let mut decoder = get_codecs()
-        .make(&track.codec_params, &DecoderOptions::default())
-        .map_err(|_| Error::InvalidPath)?;
-
-    let mut samples: Vec<i16> = Vec::new();
-
-    while let Ok(packet) = format.next_packet() {
-        let decoded = decoder.decode(&packet).map_err(|_| Error::InvalidPath)?;
+/// Decodes `file_path` unless the on-disk cache already holds it for this project format.
+pub fn load_audio(
+    file_path: &str,
+    project_format: &ProjectFormat,
+    cache: &SampleCache,
+) -> Result<DecodedAudio, Error> {
+    if let Some(cached) = cache.load(file_path, project_format) {
+        println!("Loaded {} from cache", file_path);
+        return Ok(cached);
+    }
+
+    let decoded = get_samples(file_path, project_format)?;
+    if let Err(e) = cache.store(file_path, project_format, &decoded) {
+        eprintln!("⚠️ Failed to cache {}: {}", file_path, e);
+    }
+    Ok(decoded)
+}
+
+pub fn get_samples(file_path: &str, project_format: &ProjectFormat) -> Result<DecodedAudio, Error> {
+    let source = SourceRef::parse(file_path);
+    let mut format = open_format(&source.path)?;
+    let track = select_track(format.as_ref(), source.track)?;
+    let mut track_id = track.id;
+    let depth = SampleDepth::from_codec_params(&track.codec_params);
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples: Vec<f32> = Vec::new();
+    let mut original_spec: Option<SourceSpec> = None;
+    let mut report = DecodeReport::default();
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // Symphonia signals a clean end of stream this way
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => {
+                // The track list changed (chained Ogg streams do this), pick the track again
+                report.resets += 1;
+                let track = select_track(format.as_ref(), source.track)?;
+                track_id = track.id;
+                decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+                continue;
+            }
+            Err(e) => {
+                // Keep what was decoded so far, the report says it's incomplete
+                eprintln!("⚠️ {} ended early: {}", file_path, e);
+                report.note_error(&e);
+                report.truncated = true;
+                break;
+            }
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt or short packets are skipped, the decoder picks up at the next one
+            Err(e @ (SymphoniaError::DecodeError(_) | SymphoniaError::IoError(_))) => {
+                report.packets_dropped += 1;
+                report.note_error(&e);
+                continue;
+            }
+            Err(SymphoniaError::ResetRequired) => {
+                report.resets += 1;
+                report.packets_dropped += 1;
+                decoder.reset();
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
         let spec = *decoded.spec();
-        let mut sample_buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+        let source_spec = SourceSpec {
+            sample_rate: spec.rate,
+            channels: spec.channels.count() as u16,
+            channel_mask: spec.channels.bits(),
+            depth,
+        };
+        match original_spec {
+            None => original_spec = Some(source_spec),
+            // Everything is converted with the first spec, so audio in any other can't be joined on
+            Some(first)
+                if first.sample_rate != source_spec.sample_rate
+                    || first.channels != source_spec.channels =>
+            {
+                report.packets_dropped += 1;
+                report.note_error(format!(
+                    "Stream changed from {} Hz/{} ch to {} Hz/{} ch",
+                    first.sample_rate, first.channels, spec.rate, source_spec.channels
+                ));
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        report.packets_decoded += 1;
+        report.frames_recovered += decoded.frames() as u64;
+        let mut sample_buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
         sample_buf.copy_interleaved_ref(decoded);
         samples.extend(sample_buf.samples().iter().copied());
     }
 
-    Ok(samples)
+    if !report.is_clean() {
+        println!(
+            "🩹 {}: dropped {} of {} packets, first error: {}",
+            file_path,
+            report.packets_dropped,
+            report.packets_dropped + report.packets_decoded,
+            report.first_error.as_deref().unwrap_or("none")
+        );
+    }
+
+    let original_spec = match (original_spec, &report.first_error) {
+        (Some(spec), _) => spec,
+        (None, Some(error)) => return Err(Error::Undecodable(error.clone())),
+        (None, None) => return Err(Error::NoAudioData),
+    };
+    let samples = convert_to_project(samples, &original_spec, project_format)?;
+    let peaks = compute_peaks(&samples, project_format.channels);
+
+    Ok(DecodedAudio {
+        samples,
+        original_spec,
+        peaks,
+        report,
+    })
 }
 
 #[tauri::command]
 pub fn get_custom_order(state: State<'_, Arc<AppState>>) -> Result<Vec<Uuid>, Error> {
-    let custom_order = state.custom_order.lock().map_err(|_| Error::LockPoisoned)?;
-    Ok(custom_order.clone())
+    let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(timeline.iter().map(|clip| clip.id).collect())
+}
+
+#[tauri::command]
+pub fn get_project_format(state: State<'_, Arc<AppState>>) -> Result<ProjectFormat, Error> {
+    let project_format = state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    Ok(*project_format)
+}
+
+#[tauri::command]
+pub async fn set_project_format(
+    sample_rate: u32,
+    channels: u16,
+    state: State<'_, Arc<AppState>>,
+) -> Result<String, Error> {
+    if sample_rate == 0 || channels == 0 {
+        return Err(Error::InvalidProjectFormat);
+    }
+    let state = state.inner().clone();
+    let project_format = ProjectFormat {
+        sample_rate,
+        channels,
+    };
+    // Converting supersedes a running update_inputs, and a newer one supersedes this
+    let current_token = state.cancel_token.fetch_add(1, Ordering::SeqCst) + 1;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        if *state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            == project_format
+        {
+            return Ok("Project format unchanged".to_string());
+        }
+
+        // Everything already loaded was converted to the old format, decode it again. The
+        // lock is only held to list the files, the rest of the app keeps working meanwhile.
+        let jobs: Vec<DecodeJob> = state
+            .audio_files
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .values()
+            .map(|file| DecodeJob {
+                path: file.path.clone(),
+                id: file.id,
+            })
+            .collect();
+        let silence = *state.silence.lock().map_err(|_| Error::LockPoisoned)?;
+        let mut converted = Vec::with_capacity(jobs.len());
+        let mut failures = Vec::new();
+        decode_jobs(
+            jobs,
+            &project_format,
+            &silence,
+            &state,
+            current_token,
+            |_| {},
+            |result| match result.decoded {
+                Ok(decoded) => converted.push((result.path, result.id, decoded)),
+                Err(e) => {
+                    eprintln!("⚠️ Failed to convert {}: {}", result.path, e);
+                    failures.push(format!("{}: {}", result.path, e));
+                }
+            },
+        );
+        state.sample_cache.flush();
+        if state.cancel_token.load(Ordering::SeqCst) != current_token {
+            println!("🛑 Superseded by a newer update");
+            return Ok("Cancelled, the project format is unchanged.".to_string());
+        }
+        // The old format stays until every file has a copy in the new one
+        if !failures.is_empty() {
+            return Err(Error::FormatConversion(failures.join("; ")));
+        }
+
+        // Sections and duplicate links are filled in from the file each one replaces
+        let mut converted_files = Vec::with_capacity(converted.len());
+        for (path, id, (decoded, suggested_trim)) in converted {
+            let hash = content_hash(&decoded.samples);
+            converted_files.push(AudioFile {
+                samples: state.sample_store.insert(decoded.samples)?,
+                id,
+                path,
+                original_spec: decoded.original_spec,
+                peaks: decoded.peaks,
+                decode_report: decoded.report,
+                section: String::new(),
+                content_hash: hash,
+                duplicate_of: None,
+                fingerprint: None,
+                analysis: None,
+                suggested_trim,
+            });
+        }
+
+        let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        let mut current = state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+        let count = converted_files.len();
+        for converted in converted_files {
+            // Files removed while converting stay removed
+            let Some(audio_file) = audio_files
+                .get_mut(&converted.path)
+                .filter(|file| file.id == converted.id)
+            else {
+                continue;
+            };
+            *audio_file = AudioFile {
+                section: std::mem::take(&mut audio_file.section),
+                duplicate_of: audio_file.duplicate_of,
+                ..converted
+            };
+        }
+        *current = project_format;
+        drop(current);
+        drop(audio_files);
+
+        *state
+            .combined_audio
+            .lock()
+            .map_err(|_| Error::LockPoisoned)? = None;
+        *state.svg_path.lock().map_err(|_| Error::LockPoisoned)? = None;
+        // Snapshots hold samples in the old format, they can't come back
+        state.history.clear()?;
+
+        Ok(format!(
+            "Converted {} files to {} Hz, {} channels",
+            count, sample_rate, channels
+        ))
+    })
+    .await?
 }
 
 #[tauri::command]
@@ -568,16 +1179,74 @@ pub async fn combine_all_cached_samples_with_custom_order(
     app: AppHandle,
     on_event: Channel<CombineAudioEvent>,
 ) -> Result<String, Error> {
-    // Get the stored custom order
-    let custom_order = {
-        let order = state.custom_order.lock().map_err(|_| Error::LockPoisoned)?;
-        if order.is_empty() {
-            None
-        } else {
-            Some(order.clone())
+    // The timeline holds the sorted order
+    combine_all_cached_samples(state, app, on_event, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_wav, TempDir};
+
+    #[test]
+    fn decode_jobs_converts_and_reports_each_file() {
+        let dir = TempDir::new("decode");
+        let wav = dir.join("mono.wav");
+        write_wav(&wav, 22050, 1, 2205);
+        let missing = dir.join("missing.wav");
+
+        let state = AppState::new();
+        let format = ProjectFormat::default();
+        let jobs = [&wav, &missing]
+            .iter()
+            .map(|path| DecodeJob {
+                path: path.to_string_lossy().to_string(),
+                id: Uuid::new_v4(),
+            })
+            .collect();
+        let mut results = Vec::new();
+        decode_jobs(
+            jobs,
+            &format,
+            &SilenceSettings::default(),
+            &state,
+            0,
+            |_| {},
+            |result| results.push(result),
+        );
+
+        assert_eq!(results.len(), 2);
+        for result in results {
+            if result.path.ends_with("missing.wav") {
+                assert!(result.decoded.is_err());
+                continue;
+            }
+            let (decoded, _) = result.decoded.unwrap();
+            // A tenth of a second, upsampled and duplicated to stereo
+            assert_eq!(decoded.samples.len(), 4410 * 2);
+            assert_eq!(decoded.original_spec.sample_rate, 22050);
+            assert_eq!(decoded.original_spec.channels, 1);
         }
-    };
+    }
 
-    // Call the main combine function with the custom order
-    combine_all_cached_samples(state, app, on_event, custom_order).await
+    #[test]
+    fn decode_jobs_stop_once_superseded() {
+        let state = AppState::new();
+        state.cancel_token.store(1, Ordering::SeqCst);
+        let jobs = vec![DecodeJob {
+            path: "never-opened.wav".to_string(),
+            id: Uuid::new_v4(),
+        }];
+        let mut count = 0;
+        decode_jobs(
+            jobs,
+            &ProjectFormat::default(),
+            &SilenceSettings::default(),
+            &state,
+            0,
+            |_| {},
+            |_| count += 1,
+        );
+        assert_eq!(count, 0);
+    }
 }
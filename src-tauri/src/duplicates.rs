@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hasher;
+use std::sync::Arc;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::state::{AppState, AudioFile};
+use crate::timeline::Clip;
+
+/// Which copies of identical audio make it onto the timeline.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    #[default]
+    #[serde(rename = "keepAll")]
+    All,
+    #[serde(rename = "keepFirst")]
+    First,
+    #[serde(rename = "keepOnePerSection")]
+    OnePerSection,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub hash: String,
+    // Timeline order, the first entry is the one "keep first" holds on to
+    pub paths: Vec<String>,
+    pub kept: Vec<String>,
+}
+
+/// Hash of the decoded PCM, so copies of a file match however they're named or encoded.
+pub fn content_hash(samples: &[f32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_usize(samples.len());
+    for sample in samples {
+        hasher.write_u32(sample.to_bits());
+    }
+    hasher.finish()
+}
+
+/// Groups files with identical audio, ordered as they appear on the timeline.
+pub fn find_duplicates(
+    audio_files: &BTreeMap<String, AudioFile>,
+    timeline: &[Clip],
+) -> Vec<Vec<String>> {
+    let timeline_order = timeline_order(audio_files, timeline);
+
+    let mut by_hash: HashMap<u64, Vec<&AudioFile>> = HashMap::new();
+    for file in &timeline_order {
+        by_hash.entry(file.content_hash).or_default().push(file);
+    }
+
+    let mut groups = Vec::new();
+    for candidates in by_hash.into_values().filter(|files| files.len() > 1) {
+        // Confirm sample for sample rather than trusting a 64 bit hash on its own
+        let mut remaining = candidates;
+        while let Some(first) = remaining.first().copied() {
+            let (same, different): (Vec<&AudioFile>, Vec<&AudioFile>) = remaining
+                .into_iter()
+                .partition(|file| file.samples[..] == first.samples[..]);
+            if same.len() > 1 {
+                groups.push(same.iter().map(|file| file.path.clone()).collect());
+            }
+            remaining = different;
+        }
+    }
+
+    // Stable output: groups in the order their first member appears
+    let position: HashMap<&str, usize> = timeline_order
+        .iter()
+        .enumerate()
+        .map(|(i, file)| (file.path.as_str(), i))
+        .collect();
+    groups.sort_by_key(|paths: &Vec<String>| position[paths[0].as_str()]);
+    groups
+}
+
+// Sources where their first clip sits, then any without a clip in path order
+fn timeline_order<'a>(
+    audio_files: &'a BTreeMap<String, AudioFile>,
+    timeline: &[Clip],
+) -> Vec<&'a AudioFile> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut ordered: Vec<&AudioFile> = timeline
+        .iter()
+        .filter(|clip| seen.insert(clip.source.as_str()))
+        .filter_map(|clip| audio_files.get(&clip.source))
+        .collect();
+    ordered.extend(
+        audio_files
+            .values()
+            .filter(|file| !seen.contains(file.path.as_str())),
+    );
+    ordered
+}
+
+/// Marks the copies `policy` drops with `duplicate_of`, so combine and export skip them.
+pub fn apply_policy(
+    audio_files: &mut BTreeMap<String, AudioFile>,
+    groups: &[Vec<String>],
+    policy: DuplicatePolicy,
+) -> Vec<DuplicateGroup> {
+    for file in audio_files.values_mut() {
+        file.duplicate_of = None;
+    }
+
+    let mut results = Vec::with_capacity(groups.len());
+    for paths in groups {
+        let mut kept: Vec<String> = Vec::new();
+        let mut kept_sections: HashMap<String, Uuid> = HashMap::new();
+        let first_id = audio_files[&paths[0]].id;
+
+        for path in paths {
+            let file = &audio_files[path];
+            let original = match policy {
+                DuplicatePolicy::All => None,
+                DuplicatePolicy::First => (file.id != first_id).then_some(first_id),
+                DuplicatePolicy::OnePerSection => match kept_sections.get(&file.section) {
+                    Some(&id) => Some(id),
+                    None => {
+                        kept_sections.insert(file.section.clone(), file.id);
+                        None
+                    }
+                },
+            };
+            if original.is_none() {
+                kept.push(path.clone());
+            }
+            if let Some(file) = audio_files.get_mut(path) {
+                file.duplicate_of = original;
+            }
+        }
+
+        results.push(DuplicateGroup {
+            hash: format!("{:016x}", audio_files[&paths[0]].content_hash),
+            paths: paths.clone(),
+            kept,
+        });
+    }
+    results
+}
+
+/// Re-evaluates duplicates across everything loaded with the current policy.
+pub fn refresh_duplicates(state: &AppState) -> Result<Vec<DuplicateGroup>, Error> {
+    let policy = *state
+        .duplicate_policy
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    // Same lock order as update_inputs: audio files, then the timeline
+    let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    let timeline = state
+        .timeline
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .clone();
+
+    let groups = find_duplicates(&audio_files, &timeline);
+    Ok(apply_policy(&mut audio_files, &groups, policy))
+}
+
+#[tauri::command]
+pub fn get_duplicate_groups(state: State<'_, Arc<AppState>>) -> Result<Vec<DuplicateGroup>, Error> {
+    refresh_duplicates(&state)
+}
+
+#[tauri::command]
+pub fn set_duplicate_policy(
+    policy: DuplicatePolicy,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<DuplicateGroup>, Error> {
+    *state
+        .duplicate_policy
+        .lock()
+        .map_err(|_| Error::LockPoisoned)? = policy;
+    let groups = refresh_duplicates(&state)?;
+    println!(
+        "Duplicate policy {:?}: {} groups, {} files skipped",
+        policy,
+        groups.len(),
+        groups
+            .iter()
+            .map(|group| group.paths.len() - group.kept.len())
+            .sum::<usize>()
+    );
+
+    // The mix may have included copies that are now skipped
+    *state
+        .combined_audio
+        .lock()
+        .map_err(|_| Error::LockPoisoned)? = None;
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::audio_file;
+
+    // Files keyed by path, with clips in the order given
+    fn session(files: Vec<AudioFile>) -> (BTreeMap<String, AudioFile>, Vec<Clip>) {
+        let timeline = files.iter().map(Clip::for_source).collect();
+        let audio_files = files
+            .into_iter()
+            .map(|file| (file.path.clone(), file))
+            .collect();
+        (audio_files, timeline)
+    }
+
+    fn kick_copies() -> (BTreeMap<String, AudioFile>, Vec<Clip>) {
+        session(vec![
+            audio_file("b/kick.wav", "b", vec![0.5; 8]),
+            audio_file("a/kick.wav", "a", vec![0.5; 8]),
+            audio_file("a/snare.wav", "a", vec![0.25; 8]),
+            audio_file("a/kick copy.wav", "a", vec![0.5; 8]),
+        ])
+    }
+
+    #[test]
+    fn hash_follows_the_samples() {
+        assert_eq!(content_hash(&[0.5, 0.25]), content_hash(&[0.5, 0.25]));
+        assert_ne!(content_hash(&[0.5, 0.25]), content_hash(&[0.25, 0.5]));
+        assert_ne!(content_hash(&[0.0]), content_hash(&[0.0, 0.0]));
+    }
+
+    #[test]
+    fn groups_are_in_timeline_order() {
+        let (audio_files, timeline) = kick_copies();
+        assert_eq!(
+            find_duplicates(&audio_files, &timeline),
+            vec![vec!["b/kick.wav", "a/kick.wav", "a/kick copy.wav"]]
+        );
+    }
+
+    #[test]
+    fn matching_hashes_are_confirmed_sample_for_sample() {
+        let mut lookalike = audio_file("b.wav", "", vec![0.75; 8]);
+        let original = audio_file("a.wav", "", vec![0.5; 8]);
+        lookalike.content_hash = original.content_hash;
+        let (audio_files, timeline) = session(vec![original, lookalike]);
+        assert!(find_duplicates(&audio_files, &timeline).is_empty());
+    }
+
+    #[test]
+    fn policies_mark_the_copies_they_drop() {
+        let (mut audio_files, timeline) = kick_copies();
+        let groups = find_duplicates(&audio_files, &timeline);
+        let first_id = audio_files["b/kick.wav"].id;
+        let a_id = audio_files["a/kick.wav"].id;
+
+        let kept = |policy| {
+            apply_policy(&mut audio_files.clone(), &groups, policy)[0]
+                .kept
+                .clone()
+        };
+        assert_eq!(kept(DuplicatePolicy::All).len(), 3);
+        assert_eq!(kept(DuplicatePolicy::First), vec!["b/kick.wav"]);
+        assert_eq!(
+            kept(DuplicatePolicy::OnePerSection),
+            vec!["b/kick.wav", "a/kick.wav"]
+        );
+
+        apply_policy(&mut audio_files, &groups, DuplicatePolicy::OnePerSection);
+        assert_eq!(audio_files["a/kick copy.wav"].duplicate_of, Some(a_id));
+        assert_eq!(audio_files["a/snare.wav"].duplicate_of, None);
+        apply_policy(&mut audio_files, &groups, DuplicatePolicy::First);
+        assert_eq!(audio_files["a/kick.wav"].duplicate_of, Some(first_id));
+        apply_policy(&mut audio_files, &groups, DuplicatePolicy::All);
+        assert!(audio_files.values().all(|file| file.duplicate_of.is_none()));
+    }
+
+    #[test]
+    fn policies_keep_their_wire_names() {
+        let policy: DuplicatePolicy = serde_json::from_str("\"keepOnePerSection\"").unwrap();
+        assert_eq!(policy, DuplicatePolicy::OnePerSection);
+        assert_eq!(
+            serde_json::to_string(&DuplicatePolicy::First).unwrap(),
+            "\"keepFirst\""
+        );
+    }
+}
@@ -1,46 +1,46 @@
+use crate::render::render_to_store;
+use crate::resample::{remix, resample_into, resampled_len, SampleDepth, SourceSpec};
+use crate::silence::{compress_timeline, with_compressed};
 use crate::state::AppState;
+use crate::timeline;
 use crate::Error;
-use flacenc::bitsink::BitSink;
 use flacenc::bitsink::ByteSink;
 use flacenc::component::BitRepr;
 use flacenc::config::Encoder as FlacConfig;
 use flacenc::encode_with_fixed_block_size;
 use flacenc::error::Verify;
 use flacenc::source::MemSource;
-use hound::WavWriter;
+use hound::{SampleFormat, WavSpec, WavWriter};
 use mp3lame_encoder::{
     max_required_buffer_size, Bitrate, Builder, DualPcm, FlushNoGap, Id3Tag, Quality,
 };
 use serde::Serialize;
-use std::io::Write;
+use std::io::{Seek, Write};
 use std::sync::Arc;
 use std::{collections::HashMap, fs::File, io::BufWriter, path::Path};
 use tauri::ipc::Channel;
 use tauri::State;
 
-pub enum AudioFormat {
-    Wav,
-    Mp3,
-    Flac,
-}
-
 pub trait AudioEncoder {
     fn encode(
         &self,
         samples: &[f32],
         sample_rate: u32,
+        channels: u16,
+        depth: SampleDepth,
         channel: Channel<ExportAudioEvent>,
     ) -> Result<Vec<u8>, Error>;
     fn file_extension(&self) -> &'static str;
-    fn mime_type(&self) -> &'static str;
     fn write(
         &self,
         samples: &[f32],
         sample_rate: u32,
+        channels: u16,
+        depth: SampleDepth,
         path: &str,
         channel: Channel<ExportAudioEvent>,
     ) -> Result<&'static str, Error> {
-        let data = self.encode(samples, sample_rate, channel)?;
+        let data = self.encode(samples, sample_rate, channels, depth, channel)?;
         let file = File::create(Path::new(path))?;
         let mut writer = BufWriter::new(file);
         writer.write_all(&data)?;
@@ -57,26 +57,17 @@ impl AudioEncoder for WavEncoder {
         &self,
         samples: &[f32],
         sample_rate: u32,
-        channel: Channel<ExportAudioEvent>,
+        channels: u16,
+        depth: SampleDepth,
+        _channel: Channel<ExportAudioEvent>,
     ) -> Result<Vec<u8>, Error> {
-        use hound::{SampleFormat, WavSpec, WavWriter};
         use std::io::Cursor;
 
         let mut buffer = Cursor::new(Vec::new());
-        let spec = WavSpec {
-            channels: 1,
-            sample_rate,
-            bits_per_sample: 16,
-            sample_format: SampleFormat::Int,
-        };
+        let spec = wav_spec(sample_rate, channels, depth);
 
         let mut writer = WavWriter::new(&mut buffer, spec)?;
-        for &sample in samples {
-            let s = (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
-            writer.write_sample(s)?;
-        }
-        println!("FINALIZED WAV ");
-
+        write_wav_samples(&mut writer, samples, depth)?;
         writer.finalize()?;
         Ok(buffer.into_inner())
     }
@@ -84,11 +75,55 @@ impl AudioEncoder for WavEncoder {
     fn file_extension(&self) -> &'static str {
         "wav"
     }
-    fn mime_type(&self) -> &'static str {
-        "audio/wav"
+}
+
+/// WAV header for `depth`, rounded up to a whole number of bytes per sample.
+pub fn wav_spec(sample_rate: u32, channels: u16, depth: SampleDepth) -> WavSpec {
+    let (bits_per_sample, sample_format) = match wav_depth(depth) {
+        SampleDepth::Float(bits) => (bits as u16, SampleFormat::Float),
+        SampleDepth::Int(bits) => (bits as u16, SampleFormat::Int),
+    };
+    WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    }
+}
+
+pub fn write_wav_samples<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    samples: &[f32],
+    depth: SampleDepth,
+) -> Result<(), hound::Error> {
+    match wav_depth(depth) {
+        SampleDepth::Float(_) => {
+            for &sample in samples {
+                writer.write_sample(sample)?;
+            }
+        }
+        SampleDepth::Int(bits) => {
+            for &sample in samples {
+                writer.write_sample(to_int(sample, bits))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn wav_depth(depth: SampleDepth) -> SampleDepth {
+    match depth {
+        SampleDepth::Float(_) => SampleDepth::Float(32),
+        SampleDepth::Int(bits) => SampleDepth::Int(bits.clamp(8, 32).div_ceil(8) * 8),
     }
 }
 
+// Scales a full-scale float to a signed integer of `bits` bits
+fn to_int(sample: f32, bits: u32) -> i32 {
+    let scale = ((1i64 << (bits - 1)) - 1) as f64;
+    (sample.clamp(-1.0, 1.0) as f64 * scale).round() as i32
+}
+
 pub struct FlacEncoder;
 
 impl AudioEncoder for FlacEncoder {
@@ -96,23 +131,30 @@ impl AudioEncoder for FlacEncoder {
         &self,
         samples: &[f32],
         sample_rate: u32,
+        channels: u16,
+        depth: SampleDepth,
         channel: Channel<ExportAudioEvent>,
     ) -> Result<Vec<u8>, Error> {
-        let num_channels = 2;
-        let bits_per_sample = 16;
+        let num_channels = channels as usize;
+        // FLAC tops out at 24 bits here, float sources get the most it can hold
+        let bits_per_sample = match depth {
+            SampleDepth::Int(bits) => bits.clamp(16, 24).div_ceil(8) * 8,
+            SampleDepth::Float(_) => 24,
+        };
 
-        if samples.len() % num_channels != 0 {
+        if !samples.len().is_multiple_of(num_channels) {
             return Err(Error::UnevenNumberOfSamples);
         }
 
-        channel.send(ExportAudioEvent::Progress {
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Converting to i32 PCM".into(),
         });
         // Convert to i32 PCM (flacenc expects i32 sample slices)
-        let to_i32 = |s: f32| ((s.clamp(-1.0, 1.0)) * i16::MAX as f32).round() as i32;
-
-        let pcm: Vec<i32> = samples.iter().map(|&s| to_i32(s)).collect();
+        let pcm: Vec<i32> = samples
+            .iter()
+            .map(|&s| to_int(s, bits_per_sample))
+            .collect();
 
         // --- Send "started" event ---
         let _ = channel.send(ExportAudioEvent::Started {
@@ -120,28 +162,32 @@ impl AudioEncoder for FlacEncoder {
             message: "FLAC encoding started".into(),
         });
 
-        channel.send(ExportAudioEvent::Progress {
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Building encoder config".into(),
         });
         // Build encoder config
         let config = FlacConfig::default()
             .into_verified()
-            .map_err(|_| Error::FlacEncodeError("Invalid config".into()))?;
-        channel.send(ExportAudioEvent::Progress {
+            .map_err(|_| Error::FlacEncode("Invalid config".into()))?;
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Building mem source".into(),
         });
         // Build MemSource
-        let source =
-            MemSource::from_samples(&pcm, num_channels, bits_per_sample, sample_rate as usize);
-        channel.send(ExportAudioEvent::Progress {
+        let source = MemSource::from_samples(
+            &pcm,
+            num_channels,
+            bits_per_sample as usize,
+            sample_rate as usize,
+        );
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Encoding to flac stream".into(),
         });
         // Encode into a FLAC stream
         let flac_stream = encode_with_fixed_block_size(&config, source, config.block_size)
-            .map_err(|_| Error::FlacEncodeError("Flac encode error".into()))?;
+            .map_err(|_| Error::FlacEncode("Flac encode error".into()))?;
 
         // Write to sink (Vec<u8>)
         let mut sink = ByteSink::new();
@@ -155,12 +201,11 @@ impl AudioEncoder for FlacEncoder {
                     .frame(i)
                     .unwrap()
                     .write(&mut sink)
-                    .map_err(|_| Error::FlacOutputError("Flac frame write error".into()))?;
+                    .map_err(|_| Error::FlacOutput("Flac frame write error".into()))?;
 
                 // throttle progress updates every 1% or last frame
                 if i % (total_frames / 100 + 1) == 0 || i == total_frames - 1 {
                     let progress = (i + 1) as f32 / total_frames as f32;
-                    println!("PROGRESS {}", progress);
                     let _ = channel.send(ExportAudioEvent::Progress {
                         progress,
                         message: format!("Encoded FLAC frame {}/{}", i + 1, total_frames),
@@ -172,12 +217,12 @@ impl AudioEncoder for FlacEncoder {
             // Use library’s default write method (no per-frame progress)
             flac_stream
                 .write(&mut sink)
-                .map_err(|_| Error::FlacOutputError("Flac sink error".into()))?;
+                .map_err(|_| Error::FlacOutput("Flac sink error".into()))?;
         }
 
         flac_stream
             .write(&mut sink)
-            .map_err(|_| Error::FlacOutputError("Flac sink error".into()))?;
+            .map_err(|_| Error::FlacOutput("Flac sink error".into()))?;
 
         // --- Send "finished" event ---
         let _ = channel.send(ExportAudioEvent::Finished {
@@ -190,10 +235,6 @@ impl AudioEncoder for FlacEncoder {
     fn file_extension(&self) -> &'static str {
         "flac"
     }
-
-    fn mime_type(&self) -> &'static str {
-        "audio/flac"
-    }
 }
 
 pub struct Mp3Encoder;
@@ -203,14 +244,31 @@ impl AudioEncoder for Mp3Encoder {
         &self,
         samples: &[f32],
         sample_rate: u32,
+        channels: u16,
+        // Lossy, LAME picks its own internal resolution
+        _depth: SampleDepth,
         channel: Channel<ExportAudioEvent>,
     ) -> Result<Vec<u8>, Error> {
+        // LAME is always fed a stereo pair, fold other layouts onto it first
+        let stereo;
+        let samples = if channels == 2 {
+            samples
+        } else {
+            let spec = SourceSpec {
+                sample_rate,
+                channels,
+                channel_mask: 0,
+                depth: None,
+            };
+            stereo = remix(samples.to_vec(), &spec, 2);
+            &stereo[..]
+        };
         let num_channels = 2;
 
-        if samples.len() % num_channels != 0 {
+        if !samples.len().is_multiple_of(num_channels) {
             return Err(Error::UnevenNumberOfSamples);
         }
-        channel.send(ExportAudioEvent::Progress {
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Converting to u16 PCM".into(),
         });
@@ -219,7 +277,7 @@ impl AudioEncoder for Mp3Encoder {
         let mut left = Vec::with_capacity(samples.len() / 2);
         let mut right = Vec::with_capacity(samples.len() / 2);
 
-        channel.send(ExportAudioEvent::Progress {
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Chucking channel samples".into(),
         });
@@ -228,37 +286,39 @@ impl AudioEncoder for Mp3Encoder {
             right.push(to_u16(chunk[1]));
         }
 
-        channel.send(ExportAudioEvent::Progress {
+        let _ = channel.send(ExportAudioEvent::Progress {
             progress: -1.,
             message: "Building encoder".into(),
         });
         // Configure encoder
         let mut builder =
-            Builder::new().ok_or_else(|| Error::MP3EncoderError("Failed to build".to_string()))?;
+            Builder::new().ok_or_else(|| Error::Mp3Encoder("Failed to build".to_string()))?;
         builder
             .set_num_channels(2)
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
         builder
             .set_sample_rate(sample_rate)
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
         builder
             .set_brate(Bitrate::Kbps192)
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
         builder
             .set_quality(Quality::Best)
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
-        builder.set_id3_tag(Id3Tag {
-            title: b"My title",
-            artist: b"My artist",
-            album: b"My album",
-            year: b"2025",
-            comment: b"Exported from Rust",
-            album_art: &[],
-        });
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
+        builder
+            .set_id3_tag(Id3Tag {
+                title: b"My title",
+                artist: b"My artist",
+                album: b"My album",
+                year: b"2025",
+                comment: b"Exported from Rust",
+                album_art: &[],
+            })
+            .map_err(|e| Error::Mp3Encoder(format!("{:?}", e)))?;
 
         let mut encoder = builder
             .build()
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
 
         let mut mp3_out = Vec::new();
 
@@ -277,7 +337,7 @@ impl AudioEncoder for Mp3Encoder {
             mp3_out.reserve(max_required_buffer_size(input.left.len()));
             let encoded = encoder
                 .encode(input, mp3_out.spare_capacity_mut())
-                .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+                .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
             unsafe { mp3_out.set_len(mp3_out.len() + encoded) };
 
             processed += l_chunk.len();
@@ -293,7 +353,7 @@ impl AudioEncoder for Mp3Encoder {
         // Flush
         let flushed = encoder
             .flush::<FlushNoGap>(mp3_out.spare_capacity_mut())
-            .map_err(|e| Error::MP3EncoderError(e.to_string()))?;
+            .map_err(|e| Error::Mp3Encoder(e.to_string()))?;
         unsafe { mp3_out.set_len(mp3_out.len() + flushed) };
 
         Ok(mp3_out)
@@ -302,20 +362,6 @@ impl AudioEncoder for Mp3Encoder {
     fn file_extension(&self) -> &'static str {
         "mp3"
     }
-
-    fn mime_type(&self) -> &'static str {
-        "audio/mpeg"
-    }
-}
-
-impl AudioFormat {
-    pub fn get_encoder(&self) -> Box<dyn AudioEncoder> {
-        match self {
-            AudioFormat::Wav => Box::new(WavEncoder),
-            AudioFormat::Mp3 => Box::new(Mp3Encoder),
-            AudioFormat::Flac => Box::new(FlacEncoder),
-        }
-    }
 }
 
 pub struct EncoderRegistry {
@@ -331,8 +377,8 @@ impl EncoderRegistry {
         Self { encoders }
     }
 
-    pub fn get(&self, format: &str) -> Option<&Box<dyn AudioEncoder>> {
-        self.encoders.get(format)
+    pub fn get(&self, format: &str) -> Option<&dyn AudioEncoder> {
+        self.encoders.get(format).map(|encoder| encoder.as_ref())
     }
 }
 
@@ -362,49 +408,194 @@ pub async fn export_audio(
     sample_rate: u32,
     format: String,
     output_file: String,
+    bit_depth: Option<SampleDepth>,
     state: State<'_, Arc<AppState>>,
     on_event: Channel<ExportAudioEvent>,
 ) -> Result<String, Error> {
     let state = state.inner().clone();
 
     tauri::async_runtime::spawn_blocking(move || {
-        // lock audio_files
-        let audio_files = state.audio_files.lock().unwrap();
-        println!("ENCODING STARTED of {} audio files", audio_files.len());
-        if (audio_files.len() == 0) {
-            return Err(Error::UnknownEncoderFormat(
-                "No audio files to export".into(),
-            ));
-        }
-
-        // find the total length needed for combined audio
-        let total_length: usize = audio_files.values().map(|file| file.samples.len()).sum();
-
-        // allocate combined samples buffer
-        let mut combined_samples: Vec<f32> = Vec::with_capacity(total_length);
-        on_event
-            .send(ExportAudioEvent::Started {
-                output_path: output_file.clone(),
-                message: format!(
-                    "Encoding {} files, with {}",
-                    &audio_files.len(),
-                    &combined_samples.len()
-                ),
-            })
-            .unwrap();
-        // iterate through all audio files and append their samples
-        for file in audio_files.values() {
-            combined_samples.extend(file.samples.iter().map(|&s| s as f32 / i16::MAX as f32));
+        // Pauses are compressed before the arrangement locks are taken
+        let compressed = compress_timeline(&state)?;
+        let section_pauses = state
+            .section_pauses
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .clone();
+
+        // Only the sources on the timeline are copied out, the locks aren't held while
+        // rendering and encoding
+        let (clips, sources) = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            if audio_files.is_empty() {
+                return Err(Error::UnknownEncoderFormat(
+                    "No audio files to export".into(),
+                ));
+            }
+            let clips = state
+                .timeline
+                .lock()
+                .map_err(|_| Error::LockPoisoned)?
+                .clone();
+            let sources = timeline::sources_of(&clips, &audio_files);
+            (clips, sources)
+        };
+        let auto_trim = state
+            .silence
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .auto_trim;
+        let project_format = *state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+        let crossfades = state
+            .crossfades
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .clone();
+        let gaps = state.gaps.lock().map_err(|_| Error::LockPoisoned)?.clone();
+        let lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?.clone();
+
+        // Copies dropped by the duplicate policy stay off the export too, and joins and
+        // lanes are mixed the same way as in the combined preview
+        let timeline = timeline::resolve(&clips, &sources, auto_trim);
+        let timeline = with_compressed(timeline, &compressed, &section_pauses);
+        let mixed = render_to_store(
+            &timeline,
+            &lanes,
+            &crossfades,
+            &gaps,
+            &project_format,
+            &state.sample_store,
+        )?;
+        // Keep the resolution of the highest quality source unless asked otherwise
+        let depth = bit_depth.unwrap_or_else(|| {
+            SampleDepth::widest(sources.values().map(|file| file.original_spec.depth))
+        });
+        if let Err(e) = on_event.send(ExportAudioEvent::Started {
+            output_path: output_file.clone(),
+            message: format!(
+                "Encoding {} clips, {} samples at {} bits",
+                timeline.len(),
+                mixed.len(),
+                depth.bits()
+            ),
+        }) {
+            eprintln!("⚠️ Failed to send export event: {}", e);
         }
-        println!("Num Samples: {}", combined_samples.iter().len());
+        // samples are stored in the project format, convert if a different rate was requested
+        let combined_samples = if sample_rate == project_format.sample_rate {
+            mixed
+        } else {
+            let expected = resampled_len(
+                mixed.len(),
+                project_format.channels,
+                project_format.sample_rate,
+                sample_rate,
+            );
+            let mut resampled = state.sample_store.writer(expected)?;
+            resample_into(
+                &mixed,
+                project_format.channels,
+                project_format.sample_rate,
+                sample_rate,
+                |samples| resampled.extend_from_slice(samples),
+            )?;
+            drop(mixed);
+            resampled.finish()?
+        };
         // set up encoder
         let registry = EncoderRegistry::new();
         let encoder = registry
             .get(&format)
             .ok_or(Error::UnknownEncoderFormat(format))?;
         // write combined samples to file
-        encoder.write(&combined_samples, sample_rate, &output_file, on_event)?;
+        encoder.write(
+            &combined_samples,
+            sample_rate,
+            project_format.channels,
+            depth,
+            &output_file,
+            on_event,
+        )?;
         Ok(format!("Encoded combined audio to {}", &output_file))
     })
     .await?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::WavReader;
+    use std::io::Cursor;
+
+    fn wav_round_trip(samples: &[f32], depth: SampleDepth) -> (WavSpec, Vec<f32>) {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut buffer, wav_spec(48000, 2, depth)).unwrap();
+        write_wav_samples(&mut writer, samples, depth).unwrap();
+        writer.finalize().unwrap();
+
+        let mut reader = WavReader::new(Cursor::new(buffer.into_inner())).unwrap();
+        let spec = reader.spec();
+        let read = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+            SampleFormat::Int => {
+                let scale = ((1i64 << (spec.bits_per_sample - 1)) - 1) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.unwrap() as f32 / scale)
+                    .collect()
+            }
+        };
+        (spec, read)
+    }
+
+    #[test]
+    fn wav_export_keeps_the_source_depth() {
+        let samples = [0.0, 0.5, -0.25, 1.0 / 3.0];
+        let (spec, read) = wav_round_trip(&samples, SampleDepth::Int(24));
+        assert_eq!(
+            (spec.bits_per_sample, spec.sample_format),
+            (24, SampleFormat::Int)
+        );
+        for (a, b) in samples.iter().zip(&read) {
+            assert!((a - b).abs() < 1.0 / (1 << 22) as f32);
+        }
+
+        let (spec, read) = wav_round_trip(&samples, SampleDepth::Float(64));
+        assert_eq!(
+            (spec.bits_per_sample, spec.sample_format),
+            (32, SampleFormat::Float)
+        );
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn odd_depths_round_up_to_whole_bytes() {
+        assert_eq!(wav_spec(44100, 2, SampleDepth::Int(20)).bits_per_sample, 24);
+        assert_eq!(wav_spec(44100, 2, SampleDepth::Int(4)).bits_per_sample, 8);
+        assert_eq!(wav_spec(44100, 2, SampleDepth::Int(64)).bits_per_sample, 32);
+    }
+
+    #[test]
+    fn integer_samples_clip_at_full_scale() {
+        assert_eq!(to_int(1.5, 16), i16::MAX as i32);
+        assert_eq!(to_int(-1.5, 16), -(i16::MAX as i32));
+        assert_eq!(to_int(0.5, 24), 4194304);
+    }
+
+    #[test]
+    fn the_widest_source_depth_wins() {
+        let int = |bits| Some(SampleDepth::Int(bits));
+        assert_eq!(SampleDepth::widest([None, None]), SampleDepth::Int(16));
+        assert_eq!(
+            SampleDepth::widest([int(16), int(24), None]),
+            SampleDepth::Int(24)
+        );
+        assert_eq!(
+            SampleDepth::widest([int(24), Some(SampleDepth::Float(32))]),
+            SampleDepth::Float(32)
+        );
+    }
+}
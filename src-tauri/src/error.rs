@@ -18,10 +18,10 @@ pub enum Error {
     Symphonia(#[from] symphonia::core::errors::Error),
 
     #[error(transparent)]
-    HoundWriteError(#[from] hound::Error),
+    HoundWrite(#[from] hound::Error),
 
     #[error(transparent)]
-    TauriError(#[from] tauri::Error),
+    Tauri(#[from] tauri::Error),
 
     #[error("No default track found for")]
     NoDefaultTrackFound,
@@ -29,23 +29,88 @@ pub enum Error {
     #[error("No audio data")]
     NoAudioData,
 
-    #[error("No audio data")]
-    PlaybackError,
-
     #[error("MP3 encoder build error: {0}")]
-    MP3EncoderError(String),
+    Mp3Encoder(String),
 
     #[error("Uneven Number of Samples Provided")]
     UnevenNumberOfSamples,
 
     #[error("FLAC encode error: {0}")]
-    FlacEncodeError(String),
+    FlacEncode(String),
 
     #[error("FLAC output error: {0}")]
-    FlacOutputError(String),
+    FlacOutput(String),
 
     #[error("Lock poisoned")]
     LockPoisoned,
+
+    #[error("Resample error: {0}")]
+    Resample(String),
+
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("Invalid cache entry")]
+    InvalidCacheEntry,
+
+    #[error("No directory to spill samples to")]
+    NoSpillDirectory,
+
+    #[error("No audio could be decoded: {0}")]
+    Undecodable(String),
+
+    #[error("No audio track with id {0}")]
+    TrackNotFound(u32),
+
+    #[error("Folder watch error: {0}")]
+    Watch(String),
+
+    #[error("Invalid playlist: {0}")]
+    InvalidPlaylist(String),
+
+    #[error("Archive error: {0}")]
+    Archive(String),
+
+    #[error("Not found in archive: {0}")]
+    ArchiveEntryNotFound(String),
+
+    #[error("Too large to import from an archive: {0}")]
+    ArchiveEntryTooLarge(String),
+
+    #[error("Trim points must be finite and end after they start")]
+    InvalidTrim,
+
+    #[error("Gain must be a finite number of dB")]
+    InvalidGain,
+
+    #[error("No clip with id {0}")]
+    ClipNotFound(uuid::Uuid),
+
+    #[error("No lane with id {0}")]
+    LaneNotFound(uuid::Uuid),
+
+    #[error("The main lane can't be removed")]
+    MainLaneRequired,
+
+    #[error("Couldn't read tags: {0}")]
+    Tag(String),
+
+    #[error("Silence threshold must be at most 0 dBFS and lengths can't be negative")]
+    InvalidSilenceSettings,
+
+    #[error("Sample rate and channel count must be above zero")]
+    InvalidProjectFormat,
+
+    #[error("Couldn't convert to the new project format: {0}")]
+    FormatConversion(String),
+
+    #[error("Worker panicked while processing {0}")]
+    WorkerPanicked(String),
+
+    #[error(
+        "Pause compression needs a threshold of at most 0 dBFS and can't keep more than it cuts"
+    )]
+    InvalidPauseCompression,
 }
 
 #[derive(serde::Serialize)]
@@ -60,12 +125,33 @@ pub enum ErrorKind {
     HoundWriteError(String),
     NoDefaultTrackFound(String),
     NoAudioData(String),
-    PlaybackError(String),
     TauriError(String),
     MP3EncoderError(String),
     UnevenNumberOfSamples,
     FlacEncodeError(String),
     FlacOutputError(String),
+    ResampleError(String),
+    InvalidPattern(String),
+    InvalidCacheEntry,
+    NoSpillDirectory,
+    Undecodable(String),
+    TrackNotFound(String),
+    WatchError(String),
+    InvalidPlaylist(String),
+    ArchiveError(String),
+    ArchiveEntryNotFound(String),
+    ArchiveEntryTooLarge(String),
+    InvalidTrim,
+    InvalidGain,
+    ClipNotFound(String),
+    LaneNotFound(String),
+    MainLaneRequired,
+    TagError(String),
+    InvalidSilenceSettings,
+    InvalidPauseCompression,
+    InvalidProjectFormat,
+    FormatConversion(String),
+    WorkerPanicked(String),
 }
 
 impl serde::Serialize for Error {
@@ -80,16 +166,37 @@ impl serde::Serialize for Error {
             Self::InvalidPath => ErrorKind::InvalidPath,
             Self::UnknownEncoderFormat(_) => ErrorKind::UnknownEncoderFormat(error_message),
             Self::Symphonia(_) => ErrorKind::Symphonia(error_message),
-            Self::HoundWriteError(_) => ErrorKind::HoundWriteError(error_message),
+            Self::HoundWrite(_) => ErrorKind::HoundWriteError(error_message),
             Self::NoDefaultTrackFound => ErrorKind::NoDefaultTrackFound(error_message),
             Self::NoAudioData => ErrorKind::NoAudioData(error_message),
-            Self::PlaybackError => ErrorKind::PlaybackError(error_message),
-            Self::TauriError(_) => ErrorKind::TauriError(error_message),
-            Self::MP3EncoderError(_) => ErrorKind::MP3EncoderError(error_message),
+            Self::Tauri(_) => ErrorKind::TauriError(error_message),
+            Self::Mp3Encoder(_) => ErrorKind::MP3EncoderError(error_message),
             Self::UnevenNumberOfSamples => ErrorKind::UnevenNumberOfSamples,
-            Self::FlacEncodeError(_) => ErrorKind::FlacEncodeError(error_message),
-            Self::FlacOutputError(_) => ErrorKind::FlacOutputError(error_message),
+            Self::FlacEncode(_) => ErrorKind::FlacEncodeError(error_message),
+            Self::FlacOutput(_) => ErrorKind::FlacOutputError(error_message),
             Self::LockPoisoned => ErrorKind::UnevenNumberOfSamples,
+            Self::Resample(_) => ErrorKind::ResampleError(error_message),
+            Self::InvalidPattern(_) => ErrorKind::InvalidPattern(error_message),
+            Self::InvalidCacheEntry => ErrorKind::InvalidCacheEntry,
+            Self::NoSpillDirectory => ErrorKind::NoSpillDirectory,
+            Self::Undecodable(_) => ErrorKind::Undecodable(error_message),
+            Self::TrackNotFound(_) => ErrorKind::TrackNotFound(error_message),
+            Self::Watch(_) => ErrorKind::WatchError(error_message),
+            Self::InvalidPlaylist(_) => ErrorKind::InvalidPlaylist(error_message),
+            Self::Archive(_) => ErrorKind::ArchiveError(error_message),
+            Self::ArchiveEntryNotFound(_) => ErrorKind::ArchiveEntryNotFound(error_message),
+            Self::ArchiveEntryTooLarge(_) => ErrorKind::ArchiveEntryTooLarge(error_message),
+            Self::InvalidTrim => ErrorKind::InvalidTrim,
+            Self::InvalidGain => ErrorKind::InvalidGain,
+            Self::ClipNotFound(_) => ErrorKind::ClipNotFound(error_message),
+            Self::LaneNotFound(_) => ErrorKind::LaneNotFound(error_message),
+            Self::MainLaneRequired => ErrorKind::MainLaneRequired,
+            Self::Tag(_) => ErrorKind::TagError(error_message),
+            Self::InvalidSilenceSettings => ErrorKind::InvalidSilenceSettings,
+            Self::InvalidPauseCompression => ErrorKind::InvalidPauseCompression,
+            Self::InvalidProjectFormat => ErrorKind::InvalidProjectFormat,
+            Self::FormatConversion(_) => ErrorKind::FormatConversion(error_message),
+            Self::WorkerPanicked(_) => ErrorKind::WorkerPanicked(error_message),
         };
         error_kind.serialize(serializer)
     }
@@ -0,0 +1,440 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::resample::ProjectFormat;
+use crate::sample_store::Samples;
+use crate::state::AppState;
+
+// ~46 ms analysis windows every ~12 ms at 44.1 kHz
+const FRAME_LEN: usize = 2048;
+const HOP_LEN: usize = 512;
+// 33 bands give 32 band-difference bits per frame
+const BANDS: usize = 33;
+const MIN_FREQ: f32 = 100.0;
+const MAX_FREQ: f32 = 8000.0;
+
+// How far apart two copies may start and still line up ("trimmed a little")
+const MAX_SHIFT_FRAMES: usize = 64;
+// Longer files are compared on their opening stretch only
+const MAX_COMPARE_FRAMES: usize = 1024;
+// Files whose lengths differ more than this can't be copies of each other
+const MIN_LENGTH_RATIO: f32 = 0.75;
+const DEFAULT_MIN_SIMILARITY: f32 = 0.5;
+
+/// Spectral band-difference hashes, one 32 bit word per frame.
+///
+/// Each bit says whether the energy difference between two neighbouring bands grew or
+/// shrank since the previous frame. Only the sign of a change is kept, so level changes,
+/// normalisation and lossy re-encoding leave most bits alone.
+pub fn compute_fingerprint(samples: &[f32], format: &ProjectFormat) -> Vec<u32> {
+    let channels = format.channels.max(1) as usize;
+    let mut mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    // Short one-shots still get a couple of frames to compare
+    if mono.len() < FRAME_LEN + HOP_LEN {
+        mono.resize(FRAME_LEN + HOP_LEN, 0.0);
+    }
+
+    let edges = band_edges(format.sample_rate);
+    let window: Vec<f32> = (0..FRAME_LEN)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / FRAME_LEN as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME_LEN);
+    let mut buffer = vec![Complex::new(0.0, 0.0); FRAME_LEN];
+
+    let mut fingerprint = Vec::with_capacity(mono.len() / HOP_LEN);
+    let mut previous: Option<[f32; BANDS]> = None;
+    for start in (0..=mono.len() - FRAME_LEN).step_by(HOP_LEN) {
+        for (slot, (&sample, &w)) in buffer
+            .iter_mut()
+            .zip(mono[start..start + FRAME_LEN].iter().zip(&window))
+        {
+            *slot = Complex::new(sample * w, 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut energies = [0.0f32; BANDS];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            let power: f32 = buffer[edges[band]..edges[band + 1]]
+                .iter()
+                .map(|bin| bin.norm_sqr())
+                .sum();
+            *energy = (power + 1e-10).ln();
+        }
+
+        if let Some(prev) = previous {
+            let mut bits = 0u32;
+            for m in 0..BANDS - 1 {
+                let delta = (energies[m] - energies[m + 1]) - (prev[m] - prev[m + 1]);
+                if delta > 0.0 {
+                    bits |= 1 << m;
+                }
+            }
+            fingerprint.push(bits);
+        }
+        previous = Some(energies);
+    }
+    fingerprint
+}
+
+// FFT bin boundaries of log-spaced bands between MIN_FREQ and MAX_FREQ
+fn band_edges(sample_rate: u32) -> Vec<usize> {
+    let nyquist_bin = FRAME_LEN / 2;
+    let max_freq = MAX_FREQ.min(sample_rate as f32 / 2.0);
+    let ratio = (max_freq / MIN_FREQ).powf(1.0 / BANDS as f32);
+
+    let mut edges = Vec::with_capacity(BANDS + 1);
+    for band in 0..=BANDS {
+        let freq = MIN_FREQ * ratio.powi(band as i32);
+        let bin = (freq * FRAME_LEN as f32 / sample_rate as f32).round() as usize;
+        // Every band needs at least one bin of its own
+        let min_bin = edges.last().map_or(1, |last| last + 1);
+        edges.push(bin.max(min_bin).min(nyquist_bin));
+    }
+    edges
+}
+
+/// 1.0 for identical fingerprints, 0.0 for unrelated audio, at the best alignment.
+pub fn similarity(a: &[u32], b: &[u32]) -> f32 {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() || (short.len() as f32) < long.len() as f32 * MIN_LENGTH_RATIO {
+        return 0.0;
+    }
+
+    let max_shift = MAX_SHIFT_FRAMES.min(short.len() / 4) as isize;
+    let min_overlap = ((short.len() as f32 * MIN_LENGTH_RATIO) as usize).max(1);
+
+    // Neighbouring frames share most of their window, so every offset has to be tried
+    let best = (-max_shift..=max_shift)
+        .filter_map(|shift| bit_error_rate(short, long, shift, min_overlap))
+        .fold(f32::MAX, f32::min);
+
+    if best == f32::MAX {
+        return 0.0;
+    }
+    // Unrelated audio disagrees on about half the bits
+    (1.0 - 2.0 * best).clamp(0.0, 1.0)
+}
+
+// Fraction of differing bits with `short` starting `shift` frames into `long`
+fn bit_error_rate(short: &[u32], long: &[u32], shift: isize, min_overlap: usize) -> Option<f32> {
+    let (short_start, long_start) = if shift >= 0 {
+        (0, shift as usize)
+    } else {
+        ((-shift) as usize, 0)
+    };
+    if short_start >= short.len() || long_start >= long.len() {
+        return None;
+    }
+    let overlap = (short.len() - short_start)
+        .min(long.len() - long_start)
+        .min(MAX_COMPARE_FRAMES);
+    if overlap < min_overlap.min(MAX_COMPARE_FRAMES) {
+        return None;
+    }
+
+    let errors: u32 = short[short_start..short_start + overlap]
+        .iter()
+        .zip(&long[long_start..long_start + overlap])
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+    Some(errors as f32 / (overlap * 32) as f32)
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarFile {
+    pub id: Uuid,
+    pub path: String,
+    // Best match against any other file in the cluster
+    pub similarity: f32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarCluster {
+    pub files: Vec<SimilarFile>,
+    // The weakest link that holds the cluster together
+    pub similarity: f32,
+}
+
+struct Candidate {
+    id: Uuid,
+    path: String,
+    fingerprint: Vec<u32>,
+}
+
+/// Groups files whose fingerprints are at least `min_similarity` alike.
+fn cluster_similar(files: &[Candidate], min_similarity: f32) -> Result<Vec<SimilarCluster>, Error> {
+    // Every pair is independent, so rows are shared out between threads
+    let next_row = AtomicUsize::new(0);
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(files.len())
+        .max(1);
+    let edges: Vec<(usize, usize, f32)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut found = Vec::new();
+                    loop {
+                        let i = next_row.fetch_add(1, Ordering::Relaxed);
+                        if i >= files.len() {
+                            break found;
+                        }
+                        for j in i + 1..files.len() {
+                            let score = similarity(&files[i].fingerprint, &files[j].fingerprint);
+                            if score >= min_similarity {
+                                found.push((i, j, score));
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| Error::WorkerPanicked("the similarity search".to_string()))
+            })
+            .collect::<Result<Vec<_>, Error>>()
+            .map(|found| found.into_iter().flatten().collect())
+    })?;
+
+    // Union-find over the matching pairs
+    let mut parent: Vec<usize> = (0..files.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut best = vec![0.0f32; files.len()];
+    for &(i, j, score) in &edges {
+        best[i] = best[i].max(score);
+        best[j] = best[j].max(score);
+        let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+        if a != b {
+            parent[b] = a;
+        }
+    }
+
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..files.len() {
+        let r = root(&mut parent, i);
+        members.entry(r).or_default().push(i);
+    }
+
+    let mut clusters: Vec<SimilarCluster> = members
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .map(|indices| {
+            let files: Vec<SimilarFile> = indices
+                .iter()
+                .map(|&i| SimilarFile {
+                    id: files[i].id,
+                    path: files[i].path.clone(),
+                    similarity: best[i],
+                })
+                .collect();
+            let similarity = files.iter().map(|file| file.similarity).fold(1.0, f32::min);
+            SimilarCluster { files, similarity }
+        })
+        .collect();
+    clusters.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+    Ok(clusters)
+}
+
+#[tauri::command]
+pub async fn find_similar_files(
+    min_similarity: Option<f32>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<SimilarCluster>, Error> {
+    let state = state.inner().clone();
+    let min_similarity = min_similarity.unwrap_or(DEFAULT_MIN_SIMILARITY);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let project_format = *state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+
+        // Fingerprints are worked out on first use and kept on the AudioFile afterwards
+        let pending: Vec<(Uuid, String, Samples)> = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            audio_files
+                .values()
+                .filter(|file| file.fingerprint.is_none())
+                .map(|file| (file.id, file.path.clone(), file.samples.clone()))
+                .collect()
+        };
+        if !pending.is_empty() {
+            println!("Fingerprinting {} files", pending.len());
+            let workers = thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4);
+            let chunk_len = pending.len().div_ceil(workers);
+            let results: Vec<(Uuid, Result<Vec<u32>, Error>)> = thread::scope(|scope| {
+                let handles: Vec<_> = pending
+                    .chunks(chunk_len)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|(id, path, samples)| {
+                                    // A file that panics the analysis only loses its own
+                                    // fingerprint
+                                    let fingerprint = panic::catch_unwind(|| {
+                                        compute_fingerprint(samples, &project_format)
+                                    })
+                                    .map_err(|_| Error::WorkerPanicked(path.clone()));
+                                    (*id, fingerprint)
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .map_err(|_| Error::WorkerPanicked("fingerprinting".to_string()))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+                    .map(|chunks| chunks.into_iter().flatten().collect())
+            })?;
+
+            let mut computed = Vec::with_capacity(results.len());
+            for (id, result) in results {
+                match result {
+                    Ok(fingerprint) => computed.push((id, fingerprint)),
+                    Err(e) => eprintln!("⚠️ Skipping near-duplicate search: {}", e),
+                }
+            }
+
+            let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            for (id, fingerprint) in computed {
+                if let Some(file) = audio_files.values_mut().find(|file| file.id == id) {
+                    file.fingerprint = Some(fingerprint);
+                }
+            }
+        }
+
+        let candidates: Vec<Candidate> = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            audio_files
+                .values()
+                .filter_map(|file| {
+                    Some(Candidate {
+                        id: file.id,
+                        path: file.path.clone(),
+                        fingerprint: file.fingerprint.clone()?,
+                    })
+                })
+                .collect()
+        };
+
+        let clusters = cluster_similar(&candidates, min_similarity)?;
+        println!(
+            "Found {} clusters of similar files among {}",
+            clusters.len(),
+            candidates.len()
+        );
+        Ok(clusters)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONO: ProjectFormat = ProjectFormat {
+        sample_rate: 22050,
+        channels: 1,
+    };
+
+    // Two seconds of noise bursts, different for every seed
+    fn material(seed: u64) -> Vec<f32> {
+        let mut state = seed;
+        (0..MONO.sample_rate as usize * 2)
+            .map(|i| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let noise = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+                let burst = ((i / 2205) % 3) as f32 / 2.0;
+                noise * burst
+            })
+            .collect()
+    }
+
+    fn candidate(path: &str, samples: &[f32]) -> Candidate {
+        Candidate {
+            id: Uuid::new_v4(),
+            path: path.to_string(),
+            fingerprint: compute_fingerprint(samples, &MONO),
+        }
+    }
+
+    #[test]
+    fn level_changes_and_small_trims_still_match() {
+        let original = material(1);
+        let quieter: Vec<f32> = original.iter().map(|sample| sample * 0.3).collect();
+        let trimmed = &original[HOP_LEN * 8..];
+        let fingerprint = compute_fingerprint(&original, &MONO);
+
+        assert_eq!(similarity(&fingerprint, &fingerprint), 1.0);
+        assert!(similarity(&fingerprint, &compute_fingerprint(&quieter, &MONO)) > 0.9);
+        assert!(similarity(&fingerprint, &compute_fingerprint(trimmed, &MONO)) > 0.9);
+        assert!(similarity(&fingerprint, &compute_fingerprint(&material(2), &MONO)) < 0.3);
+    }
+
+    #[test]
+    fn very_different_lengths_never_match() {
+        let original = material(1);
+        let fingerprint = compute_fingerprint(&original, &MONO);
+        let half = compute_fingerprint(&original[..original.len() / 2], &MONO);
+        assert_eq!(similarity(&fingerprint, &half), 0.0);
+        assert_eq!(similarity(&fingerprint, &[]), 0.0);
+    }
+
+    #[test]
+    fn similar_files_are_clustered() {
+        let original = material(1);
+        let quieter: Vec<f32> = original.iter().map(|sample| sample * 0.5).collect();
+        let files = [
+            candidate("b.wav", &quieter),
+            candidate("other.wav", &material(2)),
+            candidate("a.wav", &original),
+        ];
+        let clusters = cluster_similar(&files, DEFAULT_MIN_SIMILARITY).unwrap();
+        assert_eq!(clusters.len(), 1);
+        let paths: Vec<&str> = clusters[0]
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["b.wav", "a.wav"]);
+        assert!(clusters[0].similarity > 0.9);
+    }
+}
@@ -0,0 +1,869 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use tauri::ipc::Channel;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::metadata::read_tags;
+use crate::resample::ProjectFormat;
+use crate::sample_store::Samples;
+use crate::sorting::{apply_order, SortAudioEvent};
+use crate::state::AppState;
+use crate::timeline::MAIN_LANE;
+
+// ~46 ms windows every ~12 ms at 44.1 kHz, fine enough for beats
+const FRAME_LEN: usize = 2048;
+const HOP_LEN: usize = 512;
+// The key needs bins narrower than a semitone: ~186 ms windows resolve those from ~90 Hz up,
+// and bins too wide for the lower notes are left out of the chromagram
+const KEY_FRAME_LEN: usize = 8192;
+const KEY_HOP_LEN: usize = 4096;
+// Long files are analysed on their first three minutes
+const MAX_ANALYSIS_SECONDS: usize = 180;
+const MIN_FREQ: f32 = 65.0;
+const MAX_FREQ: f32 = 5000.0;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+// Tempo estimates lean towards this, so a beat isn't mistaken for its half or double
+const PREFERRED_BPM: f32 = 120.0;
+// Below these the estimate is left out rather than guessed
+const MIN_TEMPO_CONFIDENCE: f32 = 0.1;
+const MIN_KEY_CONFIDENCE: f32 = 0.5;
+// RMS levels mapped onto energy 0 and 1
+const QUIET_DB: f32 = -30.0;
+const LOUD_DB: f32 = -6.0;
+// A tempo change this big costs as much as a key clash
+const MAX_TEMPO_CHANGE: f64 = 0.06;
+// Up to this many clips the best order is searched exhaustively
+const EXACT_LIMIT: usize = 12;
+
+// Krumhansl-Kessler key profiles, from C
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+const PITCH_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// A key as a position on the Camelot wheel: 1-12, A for minor and B for major.
+///
+/// Neighbouring numbers are a fifth apart and the same number is the relative key, so the
+/// keys that mix well are the ones close together on the wheel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CamelotKey {
+    pub number: u8,
+    pub minor: bool,
+}
+
+impl CamelotKey {
+    /// The key with tonic `pitch_class` (0 = C).
+    pub fn from_pitch(pitch_class: usize, minor: bool) -> Self {
+        // A minor shares its number with its relative major, three semitones up
+        let major_tonic = (if minor { pitch_class + 3 } else { pitch_class }) % 12;
+        // C major is 8B and every fifth up is one more
+        let number = ((major_tonic * 7 + 7) % 12 + 1) as u8;
+        Self { number, minor }
+    }
+
+    /// Reads Camelot ("8A"), Open Key ("1m") and note names ("Am", "F# minor", "Bbmaj").
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let digits = text.chars().take_while(char::is_ascii_digit).count();
+        if digits > 0 {
+            let number: u8 = text[..digits].parse().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            return match text[digits..].trim().to_ascii_lowercase().as_str() {
+                "a" => Some(Self {
+                    number,
+                    minor: true,
+                }),
+                "b" => Some(Self {
+                    number,
+                    minor: false,
+                }),
+                // Open Key counts from C major = 1d, seven places round from Camelot
+                "m" => Some(Self {
+                    number: (number + 6) % 12 + 1,
+                    minor: true,
+                }),
+                "d" => Some(Self {
+                    number: (number + 6) % 12 + 1,
+                    minor: false,
+                }),
+                _ => None,
+            };
+        }
+
+        let mut chars = text.chars();
+        let letter = chars.next()?.to_ascii_uppercase();
+        let mut pitch = PITCH_NAMES
+            .iter()
+            .position(|name| name.starts_with(letter) && name.len() == 1)?;
+        let rest = chars.as_str();
+        let rest = if let Some(rest) = rest.strip_prefix(['#', '♯']) {
+            pitch += 1;
+            rest
+        } else if let Some(rest) = rest.strip_prefix(['b', '♭']) {
+            pitch += 11;
+            rest
+        } else {
+            rest
+        };
+        let mode = rest.trim().to_ascii_lowercase();
+        let minor = match mode.as_str() {
+            "m" | "min" | "minor" => true,
+            "" | "maj" | "major" => false,
+            _ => return None,
+        };
+        Some(Self::from_pitch(pitch % 12, minor))
+    }
+
+    /// Cost of mixing from `self` into `next`, 0 for the same key up to 1 for a clash,
+    /// and the move it makes on the wheel.
+    fn transition(self, next: Self) -> (f64, &'static str) {
+        let steps = (self.number as i32 - next.number as i32).rem_euclid(12);
+        let steps = steps.min(12 - steps);
+        match (steps, self.minor == next.minor) {
+            (0, true) => (0.0, "same key"),
+            (0, false) => (0.15, "relative major/minor"),
+            (1, true) => (0.15, "neighbours on the wheel"),
+            (1, false) => (0.45, "diagonal move on the wheel"),
+            (2, true) => (0.6, "two steps on the wheel"),
+            _ => (1.0, "key clash"),
+        }
+    }
+}
+
+impl fmt::Display for CamelotKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.number, if self.minor { "A" } else { "B" })
+    }
+}
+
+impl Serialize for CamelotKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Where a value came from.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Origin {
+    Tag,
+    Detected,
+}
+
+/// Key, tempo and energy of a source, for putting tracks in a musical order.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicalAnalysis {
+    pub bpm: Option<f64>,
+    pub bpm_origin: Option<Origin>,
+    pub key: Option<CamelotKey>,
+    pub key_origin: Option<Origin>,
+    // RMS level mapped to 0 (quiet) .. 1 (loud)
+    pub energy: f64,
+}
+
+/// Works out key, tempo and energy for source `key`. Tags win where they're set.
+pub fn analyze(key: &str, samples: &[f32], format: &ProjectFormat) -> MusicalAnalysis {
+    let tags = read_tags(key).unwrap_or_default();
+    let tag_key = tags.key.as_deref().and_then(CamelotKey::parse);
+    let detected = detect(samples, format);
+
+    let (bpm, bpm_origin) = match (tags.bpm, detected.bpm) {
+        (Some(bpm), _) => (Some(bpm), Some(Origin::Tag)),
+        (None, Some(bpm)) => (Some(bpm), Some(Origin::Detected)),
+        (None, None) => (None, None),
+    };
+    let (key, key_origin) = match (tag_key, detected.key) {
+        (Some(key), _) => (Some(key), Some(Origin::Tag)),
+        (None, Some(key)) => (Some(key), Some(Origin::Detected)),
+        (None, None) => (None, None),
+    };
+    MusicalAnalysis {
+        bpm,
+        bpm_origin,
+        key,
+        key_origin,
+        energy: detected.energy,
+    }
+}
+
+struct Detected {
+    bpm: Option<f64>,
+    key: Option<CamelotKey>,
+    energy: f64,
+}
+
+// Short-time spectra: spectral flux for the tempo, a finer chromagram for the key
+fn detect(samples: &[f32], format: &ProjectFormat) -> Detected {
+    let channels = format.channels.max(1) as usize;
+    let sample_rate = format.sample_rate as usize;
+    let limit = MAX_ANALYSIS_SECONDS * sample_rate * channels;
+    let mono: Vec<f32> = samples[..samples.len().min(limit)]
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    let mean_square =
+        mono.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / mono.len().max(1) as f64;
+    let rms_db = 10.0 * (mean_square + 1e-12).log10();
+    let energy = ((rms_db as f32 - QUIET_DB) / (LOUD_DB - QUIET_DB)).clamp(0.0, 1.0) as f64;
+
+    if mono.len() < FRAME_LEN * 4 {
+        return Detected {
+            bpm: None,
+            key: None,
+            energy,
+        };
+    }
+
+    let frame_rate = format.sample_rate as f32 / HOP_LEN as f32;
+    Detected {
+        bpm: estimate_tempo(&onset_strength(&mono), frame_rate),
+        key: estimate_key(&chromagram(&mono, format.sample_rate)),
+        energy,
+    }
+}
+
+fn hann(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / len as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect()
+}
+
+// Calls `visit` with the spectrum of every `frame_len` window, `hop` samples apart
+fn spectra(mono: &[f32], frame_len: usize, hop: usize, mut visit: impl FnMut(&[Complex<f32>])) {
+    if mono.len() < frame_len {
+        return;
+    }
+    let window = hann(frame_len);
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(frame_len);
+    let mut buffer = vec![Complex::new(0.0, 0.0); frame_len];
+    for start in (0..=mono.len() - frame_len).step_by(hop) {
+        for (slot, (&sample, &w)) in buffer
+            .iter_mut()
+            .zip(mono[start..start + frame_len].iter().zip(&window))
+        {
+            *slot = Complex::new(sample * w, 0.0);
+        }
+        fft.process(&mut buffer);
+        visit(&buffer[..frame_len / 2]);
+    }
+}
+
+// Spectral flux of every hop
+fn onset_strength(mono: &[f32]) -> Vec<f32> {
+    let mut onsets: Vec<f32> = Vec::with_capacity(mono.len() / HOP_LEN);
+    let mut previous = vec![0.0f32; FRAME_LEN / 2];
+    spectra(mono, FRAME_LEN, HOP_LEN, |spectrum| {
+        let mut flux = 0.0;
+        for (bin, value) in spectrum.iter().enumerate() {
+            // Log compression keeps loud bass from drowning out the hats
+            let level = (1.0 + 100.0 * value.norm()).ln();
+            flux += (level - previous[bin]).max(0.0);
+            previous[bin] = level;
+        }
+        onsets.push(flux);
+    });
+    onsets
+}
+
+// Energy per pitch class, from bins narrow enough to belong to a single semitone
+fn chromagram(mono: &[f32], sample_rate: u32) -> [f32; 12] {
+    let bin_hz = sample_rate as f32 / KEY_FRAME_LEN as f32;
+    let semitone = 2f32.powf(1.0 / 12.0) - 1.0;
+    let min_freq = MIN_FREQ.max(bin_hz / semitone);
+
+    // Pitch class of every bin in range, worked out once
+    let pitch_of_bin: Vec<Option<usize>> = (0..KEY_FRAME_LEN / 2)
+        .map(|bin| {
+            let freq = bin as f32 * bin_hz;
+            (min_freq..=MAX_FREQ).contains(&freq).then(|| {
+                let midi = 69.0 + 12.0 * (freq / 440.0).log2();
+                (midi.round() as i32).rem_euclid(12) as usize
+            })
+        })
+        .collect();
+
+    let mut chroma = [0.0f32; 12];
+    spectra(mono, KEY_FRAME_LEN, KEY_HOP_LEN, |spectrum| {
+        for (value, pitch) in spectrum.iter().zip(&pitch_of_bin) {
+            if let Some(pitch) = pitch {
+                chroma[*pitch] += value.norm();
+            }
+        }
+    });
+    chroma
+}
+
+// Autocorrelation of the onset strength, weighted towards PREFERRED_BPM
+fn estimate_tempo(onsets: &[f32], frame_rate: f32) -> Option<f64> {
+    let mean = onsets.iter().sum::<f32>() / onsets.len() as f32;
+    let centred: Vec<f32> = onsets.iter().map(|onset| onset - mean).collect();
+    let correlation = |lag: usize| -> f32 {
+        centred
+            .iter()
+            .zip(&centred[lag..])
+            .map(|(a, b)| a * b)
+            .sum()
+    };
+    let zero = correlation(0);
+    if zero <= 0.0 {
+        return None;
+    }
+
+    let min_lag = (60.0 * frame_rate / MAX_BPM).floor() as usize;
+    let max_lag = ((60.0 * frame_rate / MIN_BPM).ceil() as usize).min(centred.len() / 2);
+    if min_lag < 1 || min_lag + 2 > max_lag {
+        return None;
+    }
+    let values: Vec<f32> = (min_lag - 1..=max_lag + 1).map(correlation).collect();
+    let (best, weighted) = (1..values.len() - 1)
+        .map(|i| {
+            let bpm = 60.0 * frame_rate / (min_lag - 1 + i) as f32;
+            let octaves = (bpm / PREFERRED_BPM).log2();
+            (i, values[i] * (-0.5 * octaves * octaves).exp())
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if values[best] / zero < MIN_TEMPO_CONFIDENCE || weighted <= 0.0 {
+        return None;
+    }
+
+    // Parabolic interpolation between neighbouring lags for a tempo finer than one frame
+    let (left, centre, right) = (values[best - 1], values[best], values[best + 1]);
+    let curvature = left - 2.0 * centre + right;
+    let shift = if curvature < 0.0 {
+        (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let lag = (min_lag - 1 + best) as f32 + shift;
+    Some((60.0 * frame_rate / lag) as f64)
+}
+
+// Best correlation of the chromagram against every rotation of the key profiles
+fn estimate_key(chroma: &[f32; 12]) -> Option<CamelotKey> {
+    let (best, score) = (0..24)
+        .map(|candidate| {
+            let (profile, tonic) = if candidate < 12 {
+                (&MAJOR_PROFILE, candidate)
+            } else {
+                (&MINOR_PROFILE, candidate - 12)
+            };
+            let rotated: Vec<f32> = (0..12)
+                .map(|pitch| profile[(pitch + 12 - tonic) % 12])
+                .collect();
+            (candidate, pearson(chroma, &rotated))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    (score >= MIN_KEY_CONFIDENCE).then(|| CamelotKey::from_pitch(best % 12, best >= 12))
+}
+
+fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let (mut covariance, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return 0.0;
+    }
+    covariance / (var_a * var_b).sqrt()
+}
+
+/// How much key, tempo and energy each count towards a transition.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ArrangeWeights {
+    pub key: f64,
+    pub tempo: f64,
+    pub energy: f64,
+}
+
+impl Default for ArrangeWeights {
+    fn default() -> Self {
+        Self {
+            key: 1.0,
+            tempo: 1.0,
+            energy: 0.5,
+        }
+    }
+}
+
+/// Why one clip follows another.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinScore {
+    pub from: Uuid,
+    pub to: Uuid,
+    // Weighted cost of the join, 0 (seamless) .. 1 (clash)
+    pub cost: f64,
+    pub key_cost: f64,
+    pub tempo_cost: f64,
+    pub energy_cost: f64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipAnalysis {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub analysis: MusicalAnalysis,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarmonicOrder {
+    pub order: Vec<Uuid>,
+    pub clips: Vec<ClipAnalysis>,
+    pub joins: Vec<JoinScore>,
+    pub total_cost: f64,
+}
+
+// Key, tempo, energy and weighted costs of a join
+struct JoinCosts {
+    cost: f64,
+    key: f64,
+    tempo: f64,
+    energy: f64,
+}
+
+fn join_costs(a: &MusicalAnalysis, b: &MusicalAnalysis, weights: &ArrangeWeights) -> JoinCosts {
+    // Unknown keys and tempos are neither rewarded nor punished
+    let key = match (a.key, b.key) {
+        (Some(x), Some(y)) => x.transition(y).0,
+        _ => 0.5,
+    };
+    let tempo = match tempo_match(a.bpm, b.bpm) {
+        Some((ratio, _)) => (ratio.ln().abs() / (1.0 + MAX_TEMPO_CHANGE).ln()).min(1.0),
+        None => 0.5,
+    };
+    let energy = (a.energy - b.energy).abs();
+
+    let total = weights.key + weights.tempo + weights.energy;
+    let cost = if total > 0.0 {
+        (weights.key * key + weights.tempo * tempo + weights.energy * energy) / total
+    } else {
+        0.0
+    };
+    JoinCosts {
+        cost,
+        key,
+        tempo,
+        energy,
+    }
+}
+
+// Tempo ratio of the join and how it's played. Half and double time mix as well as the
+// same tempo
+fn tempo_match(from: Option<f64>, to: Option<f64>) -> Option<(f64, &'static str)> {
+    let (x, y) = (from?, to?);
+    if x <= 0.0 || y <= 0.0 {
+        return None;
+    }
+    [
+        (y / x, ""),
+        (y * 2.0 / x, " at half time"),
+        (y / (x * 2.0), " at double time"),
+    ]
+    .into_iter()
+    .min_by(|p, q| p.0.ln().abs().total_cmp(&q.0.ln().abs()))
+}
+
+// Only built for the joins that end up in the order, the search itself just needs the costs
+fn join_score(from: &ClipAnalysis, to: &ClipAnalysis, weights: &ArrangeWeights) -> JoinScore {
+    let (a, b) = (&from.analysis, &to.analysis);
+    let costs = join_costs(a, b, weights);
+
+    let key = match (a.key, b.key) {
+        (Some(x), Some(y)) => format!("{} → {} {}", x, y, x.transition(y).1),
+        _ => "key unknown".to_string(),
+    };
+    let tempo = match (a.bpm, b.bpm, tempo_match(a.bpm, b.bpm)) {
+        (Some(x), Some(y), Some((ratio, note))) => format!(
+            "{:.1} → {:.1} BPM ({:+.1}%{})",
+            x,
+            y,
+            (ratio - 1.0) * 100.0,
+            note
+        ),
+        _ => "tempo unknown".to_string(),
+    };
+    let energy = format!("energy {:.2} → {:.2}", a.energy, b.energy);
+
+    JoinScore {
+        from: from.id,
+        to: to.id,
+        cost: costs.cost,
+        key_cost: costs.key,
+        tempo_cost: costs.tempo,
+        energy_cost: costs.energy,
+        reason: [key, tempo, energy].join(", "),
+    }
+}
+
+fn path_cost(path: &[usize], costs: &[Vec<f64>]) -> f64 {
+    path.windows(2).map(|pair| costs[pair[0]][pair[1]]).sum()
+}
+
+/// The order of `0..n` with the lowest sum of `costs` between neighbours, starting with
+/// `first` if it's set.
+///
+/// Small sets are solved exactly, larger ones by nearest neighbour from a spread of
+/// starting points, each polished with 2-opt.
+fn cheapest_path(costs: &[Vec<f64>], first: Option<usize>) -> Vec<usize> {
+    let n = costs.len();
+    if n <= 2 {
+        let mut path: Vec<usize> = (0..n).collect();
+        if first == Some(1) {
+            path.reverse();
+        }
+        return path;
+    }
+    if n <= EXACT_LIMIT {
+        return held_karp(costs, first);
+    }
+
+    let starts: Vec<usize> = match first {
+        Some(first) => vec![first],
+        None => {
+            let tries = n.min(16);
+            (0..tries).map(|i| i * n / tries).collect()
+        }
+    };
+    starts
+        .into_iter()
+        .map(|start| {
+            let mut path = nearest_neighbour(costs, start);
+            two_opt(&mut path, costs, first.is_some());
+            path
+        })
+        .min_by(|a, b| path_cost(a, costs).total_cmp(&path_cost(b, costs)))
+        .unwrap_or_default()
+}
+
+fn nearest_neighbour(costs: &[Vec<f64>], start: usize) -> Vec<usize> {
+    let n = costs.len();
+    let mut visited = vec![false; n];
+    let mut path = Vec::with_capacity(n);
+    let mut current = start;
+    visited[current] = true;
+    path.push(current);
+    while path.len() < n {
+        let next = (0..n)
+            .filter(|&i| !visited[i])
+            .min_by(|&a, &b| costs[current][a].total_cmp(&costs[current][b]))
+            .unwrap_or(current);
+        visited[next] = true;
+        path.push(next);
+        current = next;
+    }
+    path
+}
+
+// Reverses stretches of the path while that makes it cheaper. Costs are symmetric, so a
+// reversal only changes the two joins at its ends
+fn two_opt(path: &mut [usize], costs: &[Vec<f64>], keep_first: bool) {
+    let n = path.len();
+    let from = usize::from(keep_first);
+    for _ in 0..50 {
+        let mut improved = false;
+        for i in from..n - 1 {
+            for j in i + 1..n {
+                let before = |a: usize, b: usize| costs[path[a]][path[b]];
+                let mut delta = 0.0;
+                if i > 0 {
+                    delta += costs[path[i - 1]][path[j]] - before(i - 1, i);
+                }
+                if j + 1 < n {
+                    delta += costs[path[i]][path[j + 1]] - before(j, j + 1);
+                }
+                if delta < -1e-9 {
+                    path[i..=j].reverse();
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+// Exact shortest Hamiltonian path by dynamic programming over subsets
+fn held_karp(costs: &[Vec<f64>], first: Option<usize>) -> Vec<usize> {
+    let n = costs.len();
+    let full = 1usize << n;
+    let mut best = vec![f64::INFINITY; full * n];
+    let mut previous = vec![usize::MAX; full * n];
+    for start in 0..n {
+        if first.is_none_or(|first| first == start) {
+            best[(1 << start) * n + start] = 0.0;
+        }
+    }
+    for set in 1..full {
+        for last in 0..n {
+            let cost = best[set * n + last];
+            if set & (1 << last) == 0 || cost.is_infinite() {
+                continue;
+            }
+            for next in 0..n {
+                if set & (1 << next) != 0 {
+                    continue;
+                }
+                let grown = set | (1 << next);
+                let candidate = cost + costs[last][next];
+                if candidate < best[grown * n + next] {
+                    best[grown * n + next] = candidate;
+                    previous[grown * n + next] = last;
+                }
+            }
+        }
+    }
+
+    let mut last = (0..n)
+        .min_by(|&a, &b| best[(full - 1) * n + a].total_cmp(&best[(full - 1) * n + b]))
+        .unwrap_or(0);
+    let mut set = full - 1;
+    let mut path = Vec::with_capacity(n);
+    while last != usize::MAX {
+        path.push(last);
+        let before = previous[set * n + last];
+        set &= !(1 << last);
+        last = before;
+    }
+    path.reverse();
+    path
+}
+
+/// Reorders the main lane so neighbours sit well together in key, tempo and energy.
+///
+/// `first` pins the opening clip. Key and tempo come from the tags where set and are
+/// detected from the audio otherwise, then kept on the source. The new order goes
+/// through `on_event` like any other sort, and each join comes back with its score.
+#[tauri::command]
+pub async fn arrange_harmonically(
+    weights: Option<ArrangeWeights>,
+    first: Option<Uuid>,
+    state: State<'_, Arc<AppState>>,
+    on_event: Channel<SortAudioEvent>,
+) -> Result<HarmonicOrder, Error> {
+    let state = state.inner().clone();
+    let weights = weights.unwrap_or_default();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let project_format = *state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+
+        // Analysis is worked out on first use and kept on the AudioFile afterwards
+        let pending: Vec<(String, Samples)> = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            audio_files
+                .values()
+                .filter(|file| file.analysis.is_none())
+                .map(|file| (file.path.clone(), file.samples.clone()))
+                .collect()
+        };
+        if !pending.is_empty() {
+            println!("🎼 Analysing key and tempo of {} files", pending.len());
+            let workers = thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4);
+            let chunk_len = pending.len().div_ceil(workers);
+            let computed: Vec<(String, MusicalAnalysis)> = thread::scope(|scope| {
+                let handles: Vec<_> = pending
+                    .chunks(chunk_len)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|(path, samples)| {
+                                    (path.clone(), analyze(path, samples, &project_format))
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle.join().map_err(|_| {
+                            Error::WorkerPanicked("key and tempo analysis".to_string())
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+                    .map(|chunks| chunks.into_iter().flatten().collect())
+            })?;
+            let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            for (path, analysis) in computed {
+                if let Some(file) = audio_files.get_mut(&path) {
+                    file.analysis = Some(analysis);
+                }
+            }
+        }
+
+        // Only the main lane plays in sequence, clips on the other lanes keep their place
+        let clips: Vec<ClipAnalysis> = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+            timeline
+                .iter()
+                .filter(|clip| clip.lane == MAIN_LANE)
+                .filter_map(|clip| {
+                    let source = audio_files.get(&clip.source)?;
+                    Some(ClipAnalysis {
+                        id: clip.id,
+                        analysis: source.analysis.clone()?,
+                    })
+                })
+                .collect()
+        };
+        let _ = on_event.send(SortAudioEvent::Started {
+            content_length: clips.len(),
+        });
+
+        let costs: Vec<Vec<f64>> = clips
+            .iter()
+            .map(|from| {
+                clips
+                    .iter()
+                    .map(|to| join_costs(&from.analysis, &to.analysis, &weights).cost)
+                    .collect()
+            })
+            .collect();
+        let first = first.and_then(|id| clips.iter().position(|clip| clip.id == id));
+        let path = cheapest_path(&costs, first);
+
+        let joins: Vec<JoinScore> = path
+            .windows(2)
+            .map(|pair| join_score(&clips[pair[0]], &clips[pair[1]], &weights))
+            .collect();
+        let total_cost = joins.iter().map(|join| join.cost).sum();
+        let order: Vec<Uuid> = path.iter().map(|&i| clips[i].id).collect();
+        println!(
+            "🎼 Arranged {} clips, total transition cost {:.2}",
+            order.len(),
+            total_cost
+        );
+
+        apply_order(&state, &order, "Arrange harmonically", &on_event)?;
+        let mut analyses: HashMap<Uuid, ClipAnalysis> =
+            clips.into_iter().map(|clip| (clip.id, clip)).collect();
+        Ok(HarmonicOrder {
+            clips: order.iter().filter_map(|id| analyses.remove(id)).collect(),
+            order,
+            joins,
+            total_cost,
+        })
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(text: &str) -> String {
+        CamelotKey::parse(text).map_or("none".to_string(), |key| key.to_string())
+    }
+
+    // Points on a line, so the cheapest path visits them in order
+    fn line_costs(positions: &[f64]) -> Vec<Vec<f64>> {
+        positions
+            .iter()
+            .map(|a| positions.iter().map(|b| (a - b).abs()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn keys_parse_in_every_notation() {
+        assert_eq!(key("8A"), "8A");
+        assert_eq!(key(" 12b "), "12B");
+        // Open Key 1m is A minor
+        assert_eq!(key("1m"), "8A");
+        assert_eq!(key("1d"), "8B");
+        assert_eq!(key("Am"), "8A");
+        assert_eq!(key("C"), "8B");
+        assert_eq!(key("F# minor"), "11A");
+        assert_eq!(key("Bbmaj"), "6B");
+        assert_eq!(key("E♭m"), "2A");
+    }
+
+    #[test]
+    fn nonsense_keys_are_rejected() {
+        for text in ["", "13A", "0B", "8C", "H", "Cx", "Am7"] {
+            assert_eq!(key(text), "none", "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn wheel_neighbours_are_cheap() {
+        let (a, b) = (
+            CamelotKey::parse("8A").unwrap(),
+            CamelotKey::parse("9A").unwrap(),
+        );
+        assert_eq!(a.transition(a).0, 0.0);
+        assert_eq!(a.transition(b).0, 0.15);
+        // 12 and 1 are neighbours too
+        let (c, d) = (
+            CamelotKey::parse("12B").unwrap(),
+            CamelotKey::parse("1B").unwrap(),
+        );
+        assert_eq!(c.transition(d).0, 0.15);
+        assert_eq!(a.transition(CamelotKey::parse("2A").unwrap()).0, 1.0);
+    }
+
+    #[test]
+    fn small_sets_are_solved_exactly() {
+        let positions = [5.0, 1.0, 9.0, 3.0, 7.0, 2.0, 8.0];
+        let costs = line_costs(&positions);
+        let path = cheapest_path(&costs, None);
+        assert_eq!(path.len(), positions.len());
+        assert_eq!(path_cost(&path, &costs), 8.0);
+    }
+
+    #[test]
+    fn first_clip_stays_first() {
+        let positions = [5.0, 1.0, 9.0, 3.0, 7.0];
+        let costs = line_costs(&positions);
+        let path = cheapest_path(&costs, Some(0));
+        assert_eq!(path[0], 0);
+        // Out to one end and back across to the other
+        assert_eq!(path_cost(&path, &costs), 12.0);
+        assert_eq!(cheapest_path(&line_costs(&[0.0, 1.0]), Some(1)), vec![1, 0]);
+    }
+
+    #[test]
+    fn large_sets_find_a_good_path() {
+        let positions: Vec<f64> = (0..40).map(|i| ((i * 17) % 40) as f64).collect();
+        let costs = line_costs(&positions);
+        let path = cheapest_path(&costs, None);
+        let mut visited = path.clone();
+        visited.sort_unstable();
+        assert_eq!(visited, (0..40).collect::<Vec<_>>());
+        assert_eq!(path_cost(&path, &costs), 39.0);
+    }
+}
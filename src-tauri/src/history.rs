@@ -0,0 +1,771 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tauri::{AppHandle, Emitter, State};
+use uuid::Uuid;
+
+use crate::archive::{backing_file, release_indexes};
+use crate::duplicates::refresh_duplicates;
+use crate::error::Error;
+use crate::render::{invalidate_mix, CrossfadeSettings, GapSettings};
+use crate::silence::{refresh_suggested_trims, PauseCompression, SilenceSettings};
+use crate::state::{AppState, AudioFile};
+use crate::timeline::{Clip, Lane, MAIN_LANE};
+
+// Older steps are dropped past this, each one holds a copy of the arrangement
+const HISTORY_LIMIT: usize = 100;
+
+// Older steps are also dropped once the samples only they still hold add up to more than
+// this, as removed or re-decoded sources would otherwise stay in memory for good
+const HISTORY_MEMORY_LIMIT: u64 = 1 << 30;
+
+/// The arrangement as it was at one point: order, clip settings, lanes, joins, gaps, and
+/// the silence and pause settings that shape the clips.
+///
+/// Sources are only kept by edits that add or remove them. Their samples are shared with
+/// `audio_files`, so holding on to them costs little until they're dropped from there.
+pub struct Snapshot {
+    sources: Option<BTreeMap<String, AudioFile>>,
+    // The sections being watched, kept along with the sources
+    sections: Option<BTreeSet<String>>,
+    timeline: Vec<Clip>,
+    lanes: Vec<Lane>,
+    crossfades: CrossfadeSettings,
+    gaps: GapSettings,
+    silence: SilenceSettings,
+    section_pauses: HashMap<String, PauseCompression>,
+}
+
+impl Snapshot {
+    // A copy of the arrangement, with the loaded sources if `with_sources` is set. Must be
+    // called without any of the arrangement locks held.
+    fn capture(state: &AppState, with_sources: bool) -> Result<Self, Error> {
+        let sections = match with_sources {
+            true => Some(state.folder_watcher.sections()?),
+            false => None,
+        };
+        let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?;
+        Ok(Self {
+            sources: with_sources.then(|| audio_files.clone()),
+            sections,
+            // Waveforms are redrawn by the next combine, there's no point keeping them
+            timeline: timeline
+                .iter()
+                .map(|clip| Clip {
+                    waveform_path: String::new(),
+                    ..clip.clone()
+                })
+                .collect(),
+            lanes: lanes.clone(),
+            crossfades: state
+                .crossfades
+                .lock()
+                .map_err(|_| Error::LockPoisoned)?
+                .clone(),
+            gaps: state.gaps.lock().map_err(|_| Error::LockPoisoned)?.clone(),
+            silence: *state.silence.lock().map_err(|_| Error::LockPoisoned)?,
+            section_pauses: state
+                .section_pauses
+                .lock()
+                .map_err(|_| Error::LockPoisoned)?
+                .clone(),
+        })
+    }
+
+    // Puts the arrangement back as it was. Returns the sections to watch when the sources
+    // were restored along with it.
+    fn restore(self, state: &AppState) -> Result<Option<BTreeSet<String>>, Error> {
+        let restores_sources = self.sources.is_some();
+        let silence = self.silence;
+        let silence_changed;
+        {
+            let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+            let mut lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?;
+            if let Some(sources) = self.sources {
+                *audio_files = sources;
+            }
+            // Clips that are still around keep their waveform until the next combine
+            let mut waveforms: HashMap<Uuid, String> = timeline
+                .drain(..)
+                .map(|clip| (clip.id, clip.waveform_path))
+                .collect();
+            *timeline = self
+                .timeline
+                .into_iter()
+                .map(|mut clip| {
+                    if let Some(waveform_path) = waveforms.remove(&clip.id) {
+                        clip.waveform_path = waveform_path;
+                    }
+                    clip
+                })
+                .collect();
+            *lanes = self.lanes;
+            *state.crossfades.lock().map_err(|_| Error::LockPoisoned)? = self.crossfades;
+            *state.gaps.lock().map_err(|_| Error::LockPoisoned)? = self.gaps;
+            let mut current = state.silence.lock().map_err(|_| Error::LockPoisoned)?;
+            silence_changed = *current != silence;
+            *current = silence;
+            *state
+                .section_pauses
+                .lock()
+                .map_err(|_| Error::LockPoisoned)? = self.section_pauses;
+        }
+        if restores_sources {
+            refresh_duplicates(state)?;
+        } else if silence_changed {
+            // Restored sources bring the trims found with their settings, others need new ones
+            refresh_suggested_trims(state, &silence)?;
+        }
+        invalidate_mix(state)?;
+        Ok(self.sections)
+    }
+
+    // Buffers of the samples held by the sources, with their size in bytes
+    fn buffers(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.sources.iter().flat_map(|sources| {
+            sources.values().map(|file| {
+                (
+                    file.samples.buffer_id(),
+                    (file.samples.len() * std::mem::size_of::<f32>()) as u64,
+                )
+            })
+        })
+    }
+
+    // What it takes to get from `self` to `after`
+    fn changes(&self, after: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        if let (Some(before), Some(after)) = (&self.sources, &after.sources) {
+            let added: Vec<String> = after
+                .keys()
+                .filter(|path| !before.contains_key(*path))
+                .cloned()
+                .collect();
+            let removed: Vec<String> = before
+                .keys()
+                .filter(|path| !after.contains_key(*path))
+                .cloned()
+                .collect();
+            let moved: Vec<String> = after
+                .iter()
+                .filter(|(path, file)| {
+                    before
+                        .get(*path)
+                        .is_some_and(|old| old.section != file.section)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if !added.is_empty() {
+                changes.push(Change::SourcesAdded { paths: added });
+            }
+            if !removed.is_empty() {
+                changes.push(Change::SourcesRemoved { paths: removed });
+            }
+            // Sources the folder watcher re-decoded after they changed on disk
+            let reloaded: Vec<String> = after
+                .iter()
+                .filter(|(path, file)| {
+                    before
+                        .get(*path)
+                        .is_some_and(|old| old.content_hash != file.content_hash)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if !moved.is_empty() {
+                changes.push(Change::SectionsChanged { paths: moved });
+            }
+            if !reloaded.is_empty() {
+                changes.push(Change::SourcesReloaded { paths: reloaded });
+            }
+        }
+
+        let before_ids: HashSet<Uuid> = self.timeline.iter().map(|clip| clip.id).collect();
+        let after_ids: HashSet<Uuid> = after.timeline.iter().map(|clip| clip.id).collect();
+        let added: Vec<Uuid> = after
+            .timeline
+            .iter()
+            .map(|clip| clip.id)
+            .filter(|id| !before_ids.contains(id))
+            .collect();
+        let removed: Vec<Uuid> = self
+            .timeline
+            .iter()
+            .map(|clip| clip.id)
+            .filter(|id| !after_ids.contains(id))
+            .collect();
+        if !added.is_empty() {
+            changes.push(Change::ClipsAdded { ids: added });
+        }
+        if !removed.is_empty() {
+            changes.push(Change::ClipsRemoved { ids: removed });
+        }
+
+        let moved = moved_clips(&self.timeline, &after.timeline);
+        if !moved.is_empty() {
+            changes.push(Change::ClipsReordered { ids: moved });
+        }
+
+        let before_clips: HashMap<Uuid, &Clip> =
+            self.timeline.iter().map(|clip| (clip.id, clip)).collect();
+        for clip in &after.timeline {
+            let Some(old) = before_clips.get(&clip.id) else {
+                continue;
+            };
+            let mut fields = Vec::new();
+            if old.trim != clip.trim {
+                fields.push("trim");
+            }
+            if old.envelope != clip.envelope {
+                fields.push("envelope");
+            }
+            if old.pauses != clip.pauses {
+                fields.push("pauses");
+            }
+            if old.lane != clip.lane {
+                fields.push("lane");
+            }
+            // Starts on the main lane follow from the order, which is reported above
+            if old.start != clip.start && clip.lane != MAIN_LANE {
+                fields.push("start");
+            }
+            if !fields.is_empty() {
+                changes.push(Change::ClipChanged {
+                    id: clip.id,
+                    fields,
+                });
+            }
+        }
+
+        if self.lanes != after.lanes {
+            changes.push(Change::LanesChanged);
+        }
+        if self.crossfades != after.crossfades {
+            changes.push(Change::CrossfadesChanged);
+        }
+        if self.gaps != after.gaps {
+            changes.push(Change::GapsChanged);
+        }
+        if self.silence != after.silence {
+            changes.push(Change::SilenceChanged);
+        }
+        let mut sections: Vec<String> = self
+            .section_pauses
+            .keys()
+            .chain(after.section_pauses.keys())
+            .filter(|section| {
+                self.section_pauses.get(*section) != after.section_pauses.get(*section)
+            })
+            .cloned()
+            .collect();
+        sections.sort();
+        sections.dedup();
+        if !sections.is_empty() {
+            changes.push(Change::SectionPausesChanged { sections });
+        }
+        changes
+    }
+}
+
+// Clips that changed place, leaving out the longest run that kept its relative order, so
+// dragging one clip reports that clip rather than everything it pushed along
+fn moved_clips(before: &[Clip], after: &[Clip]) -> Vec<Uuid> {
+    let old_index: HashMap<Uuid, usize> = before
+        .iter()
+        .enumerate()
+        .map(|(i, clip)| (clip.id, i))
+        .collect();
+    let kept: Vec<(Uuid, usize)> = after
+        .iter()
+        .filter_map(|clip| Some((clip.id, *old_index.get(&clip.id)?)))
+        .collect();
+
+    // Longest increasing run of old indices, by patience sorting
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; kept.len()];
+    for (i, &(_, index)) in kept.iter().enumerate() {
+        let slot = tails.partition_point(|&tail| kept[tail].1 < index);
+        if slot > 0 {
+            previous[i] = Some(tails[slot - 1]);
+        }
+        if slot == tails.len() {
+            tails.push(i);
+        } else {
+            tails[slot] = i;
+        }
+    }
+    let mut in_place = HashSet::new();
+    let mut cursor = tails.last().copied();
+    while let Some(i) = cursor {
+        in_place.insert(i);
+        cursor = previous[i];
+    }
+
+    kept.iter()
+        .enumerate()
+        .filter(|(i, _)| !in_place.contains(i))
+        .map(|(_, (id, _))| *id)
+        .collect()
+}
+
+/// One part of the arrangement an edit touched.
+#[derive(Serialize, Clone, Debug)]
+#[serde(
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    tag = "kind",
+    content = "data"
+)]
+pub enum Change {
+    SourcesAdded { paths: Vec<String> },
+    SourcesRemoved { paths: Vec<String> },
+    SectionsChanged { paths: Vec<String> },
+    SourcesReloaded { paths: Vec<String> },
+    ClipsAdded { ids: Vec<Uuid> },
+    ClipsRemoved { ids: Vec<Uuid> },
+    ClipsReordered { ids: Vec<Uuid> },
+    ClipChanged { id: Uuid, fields: Vec<&'static str> },
+    LanesChanged,
+    CrossfadesChanged,
+    GapsChanged,
+    SilenceChanged,
+    SectionPausesChanged { sections: Vec<String> },
+}
+
+/// An edit as the UI sees it in the history list.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryStep {
+    pub id: u64,
+    pub label: String,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    tag = "event",
+    content = "data"
+)]
+pub enum HistoryEvent {
+    Recorded { step: HistoryStep },
+    Undone { step: HistoryStep },
+    Redone { step: HistoryStep },
+    Cleared,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryInfo {
+    // Oldest first, the last one is what undo reverts
+    pub undo: Vec<HistoryStep>,
+    // The first one is what redo applies
+    pub redo: Vec<HistoryStep>,
+}
+
+struct Entry {
+    step: HistoryStep,
+    // The arrangement on the other side of the step
+    snapshot: Snapshot,
+}
+
+/// An arrangement edit in progress, from the arrangement before it until it's recorded.
+///
+/// Other edits, and undo and redo, wait until this one is recorded or dropped, so the step
+/// it records holds its own changes and no one else's.
+pub struct Edit<'a> {
+    state: &'a AppState,
+    before: Snapshot,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Edit<'_> {
+    /// Records the edit that led from the start of this one to the arrangement as it is now.
+    ///
+    /// Edits that didn't change anything aren't recorded. Must be called without any of
+    /// the arrangement locks held.
+    pub fn record(self, label: &str) -> Result<(), Error> {
+        self.state.history.record(self.state, label, self.before)
+    }
+}
+
+struct HistoryStacks {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    next_id: u64,
+}
+
+/// Undo and redo stacks for arrangement edits, announced as `history-changed` events.
+pub struct History {
+    stacks: Mutex<HistoryStacks>,
+    // Held for the length of an edit
+    edit: Mutex<()>,
+    app: Mutex<Option<AppHandle>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            stacks: Mutex::new(HistoryStacks {
+                undo: Vec::new(),
+                redo: Vec::new(),
+                next_id: 0,
+            }),
+            edit: Mutex::new(()),
+            app: Mutex::new(None),
+        }
+    }
+
+    /// Sends history events to the frontend from now on.
+    pub fn attach(&self, app: AppHandle) {
+        if let Ok(mut handle) = self.app.lock() {
+            *handle = Some(app);
+        }
+    }
+
+    /// Starts an edit, with a copy of the loaded sources if `with_sources` is set. Edits
+    /// that add, remove or reload sources need them to be undone.
+    ///
+    /// Waits for any edit already going on. Must be called without any of the arrangement
+    /// locks held.
+    pub fn begin<'a>(&'a self, state: &'a AppState, with_sources: bool) -> Result<Edit<'a>, Error> {
+        let guard = self.lock_edits();
+        Ok(Edit {
+            state,
+            before: Snapshot::capture(state, with_sources)?,
+            _guard: guard,
+        })
+    }
+
+    // The guard protects no data of its own, so an edit that panicked doesn't stop later ones
+    fn lock_edits(&self) -> MutexGuard<'_, ()> {
+        self.edit
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn record(&self, state: &AppState, label: &str, before: Snapshot) -> Result<(), Error> {
+        let after = Snapshot::capture(state, before.sources.is_some())?;
+        let changes = before.changes(&after);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let step = {
+            let mut stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+            let step = HistoryStep {
+                id: stacks.next_id,
+                label: label.to_string(),
+                changes,
+            };
+            stacks.next_id += 1;
+            stacks.undo.push(Entry {
+                step: step.clone(),
+                snapshot: before,
+            });
+            stacks.redo.clear();
+            step
+        };
+        self.trim(state, HISTORY_MEMORY_LIMIT)?;
+        println!("📝 {}", step.label);
+        self.emit(HistoryEvent::Recorded { step });
+        Ok(())
+    }
+
+    /// Forgets every step, for changes the history can't cross such as a new project format.
+    pub fn clear(&self) -> Result<(), Error> {
+        {
+            let mut stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+            stacks.undo.clear();
+            stacks.redo.clear();
+        }
+        self.emit(HistoryEvent::Cleared);
+        Ok(())
+    }
+
+    fn info(&self) -> Result<HistoryInfo, Error> {
+        let stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+        Ok(HistoryInfo {
+            undo: stacks.undo.iter().map(|entry| entry.step.clone()).collect(),
+            redo: stacks
+                .redo
+                .iter()
+                .rev()
+                .map(|entry| entry.step.clone())
+                .collect(),
+        })
+    }
+
+    // Moves the newest step from one stack to the other, restoring its snapshot and
+    // leaving the arrangement it replaced in its place
+    fn step(&self, state: &AppState, undo: bool) -> Result<Option<HistoryStep>, Error> {
+        let _guard = self.lock_edits();
+        let entry = {
+            let mut stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+            let stack = if undo {
+                &mut stacks.undo
+            } else {
+                &mut stacks.redo
+            };
+            match stack.pop() {
+                Some(entry) => entry,
+                None => return Ok(None),
+            }
+        };
+
+        let current = Snapshot::capture(state, entry.snapshot.sources.is_some())?;
+        let sections = entry.snapshot.restore(state)?;
+        let step = entry.step;
+        {
+            let mut stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+            let stack = if undo {
+                &mut stacks.redo
+            } else {
+                &mut stacks.undo
+            };
+            stack.push(Entry {
+                step: step.clone(),
+                snapshot: current,
+            });
+        }
+        self.trim(state, HISTORY_MEMORY_LIMIT)?;
+        if let Some(sections) = sections {
+            self.watch(state, sections);
+        }
+
+        if undo {
+            println!("↩️ Undid {}", step.label);
+            self.emit(HistoryEvent::Undone { step: step.clone() });
+        } else {
+            println!("↪️ Redid {}", step.label);
+            self.emit(HistoryEvent::Redone { step: step.clone() });
+        }
+        Ok(Some(step))
+    }
+
+    // Drops the oldest steps past the step limit, and while the samples only the history
+    // holds on to are over `memory_limit`. The newest step is always kept.
+    fn trim(&self, state: &AppState, memory_limit: u64) -> Result<(), Error> {
+        let loaded: HashSet<usize> = state
+            .audio_files
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .values()
+            .map(|file| file.samples.buffer_id())
+            .collect();
+        let mut stacks = self.stacks.lock().map_err(|_| Error::LockPoisoned)?;
+
+        // How many steps hold each buffer that's gone from `audio_files`, and its size
+        let mut held: HashMap<usize, (usize, u64)> = HashMap::new();
+        for entry in stacks.undo.iter().chain(&stacks.redo) {
+            let buffers: HashMap<usize, u64> = entry.snapshot.buffers().collect();
+            for (buffer, bytes) in buffers {
+                if !loaded.contains(&buffer) {
+                    held.entry(buffer).or_insert((0, bytes)).0 += 1;
+                }
+            }
+        }
+        let mut held_bytes: u64 = held.values().map(|(_, bytes)| bytes).sum();
+
+        let mut dropped = 0;
+        while stacks.undo.len() + stacks.redo.len() > 1
+            && (stacks.undo.len() > HISTORY_LIMIT || held_bytes > memory_limit)
+        {
+            // The step furthest from now goes first, the oldest undo or else the last redo
+            let entry = if stacks.undo.is_empty() {
+                stacks.redo.remove(0)
+            } else {
+                stacks.undo.remove(0)
+            };
+            let buffers: HashMap<usize, u64> = entry.snapshot.buffers().collect();
+            for buffer in buffers.keys() {
+                if let Some((count, bytes)) = held.get_mut(buffer) {
+                    *count -= 1;
+                    if *count == 0 {
+                        held_bytes -= *bytes;
+                    }
+                }
+            }
+            dropped += 1;
+        }
+        if dropped > 0 {
+            println!(
+                "🧹 Dropped the {} oldest history steps, {} MB still held",
+                dropped,
+                held_bytes >> 20
+            );
+        }
+        Ok(())
+    }
+
+    // Undoing or redoing an import changes which sections there are, the watches follow
+    fn watch(&self, state: &AppState, sections: BTreeSet<String>) {
+        let in_use: HashSet<&str> = sections
+            .iter()
+            .map(|section| backing_file(section))
+            .collect();
+        release_indexes(&in_use);
+        if let Some(app) = self.app.lock().ok().and_then(|app| app.clone()) {
+            if let Err(e) = state.folder_watcher.sync(&app, sections) {
+                eprintln!("⚠️ Folder watching unavailable: {}", e);
+            }
+        }
+    }
+
+    fn emit(&self, event: HistoryEvent) {
+        if let Some(app) = self.app.lock().ok().and_then(|app| app.clone()) {
+            let _ = app.emit("history-changed", event);
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_history(state: State<'_, Arc<AppState>>) -> Result<HistoryInfo, Error> {
+    state.history.info()
+}
+
+/// Reverts the last edit. Returns None when there's nothing to undo.
+#[tauri::command]
+pub fn undo(state: State<'_, Arc<AppState>>) -> Result<Option<HistoryStep>, Error> {
+    state.history.step(&state, true)
+}
+
+/// Applies the last undone edit again. Returns None when there's nothing to redo.
+#[tauri::command]
+pub fn redo(state: State<'_, Arc<AppState>>) -> Result<Option<HistoryStep>, Error> {
+    state.history.step(&state, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::audio_file;
+    use std::thread;
+    use std::time::Duration;
+
+    // Loads a source and puts a clip of it at the end of the timeline
+    fn add_source(state: &AppState, name: &str) -> Uuid {
+        let file = audio_file(&format!("/music/{}.wav", name), "/music", vec![0.1; 1000]);
+        let clip = Clip::for_source(&file);
+        state
+            .audio_files
+            .lock()
+            .unwrap()
+            .insert(file.path.clone(), file);
+        state.timeline.lock().unwrap().push(clip.clone());
+        clip.id
+    }
+
+    fn clip_ids(state: &AppState) -> Vec<Uuid> {
+        state
+            .timeline
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|clip| clip.id)
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_arrangement() {
+        let state = AppState::new();
+        let edit = state.history.begin(&state, true).unwrap();
+        let id = add_source(&state, "first");
+        edit.record("Add first").unwrap();
+
+        let step = state.history.step(&state, true).unwrap().unwrap();
+        assert_eq!(step.label, "Add first");
+        assert!(clip_ids(&state).is_empty());
+        assert!(state.audio_files.lock().unwrap().is_empty());
+
+        state.history.step(&state, false).unwrap().unwrap();
+        assert_eq!(clip_ids(&state), vec![id]);
+        assert_eq!(state.audio_files.lock().unwrap().len(), 1);
+        assert!(state.history.step(&state, false).unwrap().is_none());
+    }
+
+    #[test]
+    fn edits_that_change_nothing_are_not_recorded() {
+        let state = AppState::new();
+        let edit = state.history.begin(&state, false).unwrap();
+        edit.record("Nothing").unwrap();
+        assert!(state.history.info().unwrap().undo.is_empty());
+    }
+
+    #[test]
+    fn concurrent_edits_record_only_their_own_changes() {
+        let state = Arc::new(AppState::new());
+        let workers: Vec<_> = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    let edit = state.history.begin(&state, false).unwrap();
+                    add_source(&state, name);
+                    // Long enough for the other edit to start if nothing held it back
+                    thread::sleep(Duration::from_millis(20));
+                    edit.record(name).unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let undo = state.history.info().unwrap().undo;
+        assert_eq!(undo.len(), 2);
+        for step in undo {
+            let added: Vec<&Uuid> = step
+                .changes
+                .iter()
+                .flat_map(|change| match change {
+                    Change::ClipsAdded { ids } => ids.iter().collect(),
+                    _ => Vec::new(),
+                })
+                .collect();
+            assert_eq!(added.len(), 1, "{} recorded {:?}", step.label, step.changes);
+        }
+    }
+
+    #[test]
+    fn steps_past_the_limit_are_dropped() {
+        let state = AppState::new();
+        for i in 0..HISTORY_LIMIT + 5 {
+            let edit = state.history.begin(&state, false).unwrap();
+            add_source(&state, &i.to_string());
+            edit.record(&i.to_string()).unwrap();
+        }
+        let undo = state.history.info().unwrap().undo;
+        assert_eq!(undo.len(), HISTORY_LIMIT);
+        assert_eq!(undo[0].label, "5");
+    }
+
+    #[test]
+    fn steps_holding_unloaded_samples_are_dropped_past_the_memory_limit() {
+        let state = AppState::new();
+        // Each edit swaps the loaded source for a new one, so the step before it is the only
+        // one left holding the old samples
+        for i in 0..3 {
+            let edit = state.history.begin(&state, true).unwrap();
+            state.audio_files.lock().unwrap().clear();
+            state.timeline.lock().unwrap().clear();
+            add_source(&state, &i.to_string());
+            edit.record(&i.to_string()).unwrap();
+        }
+        assert_eq!(state.history.info().unwrap().undo.len(), 3);
+
+        // Two unloaded sources of 4000 bytes each, one of them fits
+        state.history.trim(&state, 5000).unwrap();
+        let undo = state.history.info().unwrap().undo;
+        assert_eq!(undo.len(), 1);
+        assert_eq!(undo[0].label, "2");
+
+        // The newest step stays whatever it holds
+        state.history.trim(&state, 0).unwrap();
+        assert_eq!(state.history.info().unwrap().undo.len(), 1);
+    }
+}
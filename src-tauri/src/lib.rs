@@ -1,69 +1,96 @@
-use log;
 use rodio::{Decoder, OutputStream, Sink};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{metadata, File};
 use std::io::BufReader;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
 use std::time::{Duration, Instant};
-use std::{fs, thread};
 use tauri::Listener;
 use tauri::{AppHandle, Emitter, Manager, State};
 
 use crate::error::Error;
 use crate::metadata::get_metadata;
+use crate::scan::{Detection, ScanOptions};
 use crate::state::AppState;
+mod archive;
+mod cache;
 mod combine;
+mod duplicates;
 mod encoder;
 mod error;
+mod fingerprint;
+mod harmony;
+mod history;
 mod metadata;
+mod playlist;
+mod render;
+mod resample;
+mod sample_store;
+mod scan;
+mod silence;
 mod sorting;
+mod source;
 mod state;
+mod timeline;
+mod watch;
+
+#[cfg(test)]
+mod test_support;
 
 pub struct Song {
     pub title: String,
 }
 
 #[tauri::command]
-fn get_file_paths_in_folder(
+async fn get_file_paths_in_folder(
     folder_paths: Vec<String>,
-) -> Result<HashMap<String, Vec<String>>, Error> {
-    let mut all_paths: HashMap<String, Vec<String>> = HashMap::new();
-
-    for folder_path in folder_paths {
-        let mut valid_files = Vec::new();
-        let entries = std::fs::read_dir(&folder_path)?;
-
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-                    if file_name.starts_with("._") {
-                        continue;
-                    }
-                }
+    options: Option<ScanOptions>,
+) -> Result<BTreeMap<String, Vec<String>>, Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let options = options.unwrap_or_default();
+        let mut all_paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for folder_path in folder_paths {
+            // Playlists and CUE sheets become one section each, in the order they list
+            if playlist::is_playlist(Path::new(&folder_path)) {
+                let sources = playlist::playlist_sources(&folder_path)?;
+                all_paths.insert(folder_path, sources);
+                continue;
+            }
 
-                if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                    let ext = ext.to_lowercase();
-                    if [
-                        "mp3", "wav", "flac", "ogg", "m4a", "aac", "aiff", "alac", "aif",
-                    ]
-                    .contains(&ext.as_str())
-                    {
-                        let path_str = path.to_str().ok_or(Error::InvalidPath)?;
-                        valid_files.push(path_str.to_string());
+            let groups = scan::scan_folder(&folder_path, &options)?;
+            let total: usize = groups.values().map(|files| files.len()).sum();
+            println!(
+                "{}: {} files in {} folders",
+                folder_path,
+                total,
+                groups.len()
+            );
+
+            // A folder without any matches still gets an entry so the UI can show it as empty
+            if groups.is_empty() {
+                all_paths.insert(folder_path, Vec::new());
+            }
+            for (folder, files) in groups {
+                for file in &files {
+                    if let Detection::Unsupported { reason } = &file.detection {
+                        println!("Skipping {}: {}", file.path, reason);
                     }
                 }
+                let paths: Vec<String> = files
+                    .into_iter()
+                    .filter(|file| file.detection.is_supported())
+                    .map(|file| file.path)
+                    .collect();
+                all_paths.entry(folder).or_default().extend(paths);
             }
         }
 
-        println!("{}: {} files", folder_path, valid_files.len());
-        all_paths.insert(folder_path, valid_files);
-    }
-
-    Ok(all_paths)
+        Ok(all_paths)
+    })
+    .await?
 }
 
 // #[tauri::command]
@@ -105,15 +132,21 @@ fn get_file_paths_in_folder(
 // }
 
 #[tauri::command]
-fn clear_audio_files(state: State<'_, Arc<AppState>>, app: AppHandle) {
-    let mut audio_files = state.audio_files.lock().unwrap();
-    audio_files.clear();
-    let mut combined_audio = state.combined_audio.lock().unwrap();
-    *combined_audio = None;
-    let mut custom_order = state.custom_order.lock().unwrap();
-    custom_order.clear();
+fn clear_audio_files(state: State<'_, Arc<AppState>>, app: AppHandle) -> Result<(), Error> {
+    let edit = state.history.begin(&state, true)?;
+    {
+        let mut audio_files = state.audio_files.lock().unwrap();
+        audio_files.clear();
+        let mut combined_audio = state.combined_audio.lock().unwrap();
+        *combined_audio = None;
+        let mut timeline = state.timeline.lock().unwrap();
+        timeline.clear();
+    }
+    edit.record("Clear")?;
+    archive::release_indexes(&HashSet::new());
     let _ = app.emit("buffering-progress", 0.);
     println!("🗑️  All audio files have been cleared.");
+    Ok(())
 }
 
 #[tauri::command]
@@ -190,10 +223,9 @@ fn play_song(title: String, state: State<'_, Arc<AppState>>, app: AppHandle) {
         let app_clone = app.clone();
 
         thread::spawn(move || {
-            let mut done_emitted = false;
             // let cancel_flag = state.cancel_playback.load(Ordering::Relaxed); // pass into the thread
             // println!("{}", cancel_flag);
-            while !sink_clone.empty() && !done_emitted && !sink_clone.is_paused() {
+            while !sink_clone.empty() && !sink_clone.is_paused() {
                 // println!("{}", cancel_flag);
                 let elapsed = start.elapsed();
                 let elapsed_secs = elapsed.as_secs_f32();
@@ -203,7 +235,6 @@ fn play_song(title: String, state: State<'_, Arc<AppState>>, app: AppHandle) {
                     let _ = app_clone.emit("song-progress", progress);
 
                     if progress >= 1.0 {
-                        done_emitted = true;
                         break;
                     }
                 }
@@ -222,7 +253,7 @@ fn play_song(title: String, state: State<'_, Arc<AppState>>, app: AppHandle) {
 
 #[tauri::command]
 fn pause_song(state: State<'_, Arc<AppState>>) {
-    let mut current_song = state.current_song.lock().unwrap();
+    let current_song = state.current_song.lock().unwrap();
     if let Some(ref sink) = *current_song {
         println!("PAUSING!!!!");
         sink.pause();
@@ -235,14 +266,14 @@ fn pause_song(state: State<'_, Arc<AppState>>) {
 
 #[tauri::command]
 fn set_volume(vol: f32, state: State<'_, Arc<AppState>>) {
-    let mut current_song = state.current_song.lock().unwrap();
+    let current_song = state.current_song.lock().unwrap();
     if let Some(ref sink) = *current_song {
         sink.set_volume(vol);
     }
 }
 
 #[tauri::command]
-fn open_in_explorer(state: State<'_, Arc<AppState>>, file_to_open: String) {
+fn open_in_explorer(file_to_open: String) {
     println!("SHOWING IN EXP");
     showfile::show_path_in_file_manager(file_to_open);
 }
@@ -256,27 +287,34 @@ pub fn run() {
         .plugin(tauri_plugin_clipboard::init())
         .setup(|app| {
             {
-                let window = app.get_webview_window("main").unwrap();
+                let _window = app.get_webview_window("main").unwrap();
                 // window.open_devtools();
                 // window.close_devtools();
-                app.listen("download-started", |event| {});
+                app.listen("download-started", |_event| {});
+            }
+            {
+                let state = app.state::<Arc<AppState>>();
+                state.history.attach(app.app_handle().clone());
+                match app.path().app_cache_dir() {
+                    Ok(dir) => {
+                        if let Err(e) = state.sample_cache.open(dir.join("decoded")) {
+                            eprintln!("⚠️ Sample cache disabled: {}", e);
+                        }
+                        if let Err(e) = state.sample_store.open(dir.join("spill")) {
+                            eprintln!("⚠️ Sample spilling disabled, keeping samples in RAM: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("⚠️ No cache directory, sample cache disabled: {}", e),
+                }
             }
             Ok(())
         })
-        .manage(Arc::new(AppState {
-            current_song: Mutex::new(None),
-            audio_files: Mutex::new(std::collections::BTreeMap::new()),
-            combined_audio: Mutex::new(None),
-            cancel_playback: AtomicBool::new(false),
-            buffering_samples: AtomicBool::new(false),
-            svg_path: Mutex::new(None),
-            cancel_token: AtomicU64::new(0),
-            combine_process: Arc::new(Mutex::new(0)),
-            custom_order: Mutex::new(Vec::new()),
-        }))
+        .manage(Arc::new(AppState::new()))
         .invoke_handler(tauri::generate_handler![
             set_volume,
             get_file_paths_in_folder,
+            scan::scan_folders,
+            source::get_audio_tracks,
             play_song,
             pause_song,
             get_metadata,
@@ -285,6 +323,40 @@ pub fn run() {
             combine::combine_all_cached_samples,
             combine::combine_all_cached_samples_with_custom_order,
             combine::get_custom_order,
+            combine::get_project_format,
+            combine::get_waveform_peaks,
+            combine::get_decode_report,
+            duplicates::get_duplicate_groups,
+            duplicates::set_duplicate_policy,
+            fingerprint::find_similar_files,
+            combine::set_project_format,
+            render::get_crossfades,
+            render::set_crossfade,
+            render::set_join_crossfade,
+            render::set_trim,
+            render::set_envelope,
+            render::get_gaps,
+            render::set_gap,
+            render::set_edge_gaps,
+            render::set_clip_gap,
+            silence::get_silence_settings,
+            silence::set_silence_settings,
+            silence::get_suggested_trims,
+            silence::set_clip_pauses,
+            silence::get_section_pauses,
+            silence::set_section_pauses,
+            timeline::get_timeline,
+            timeline::add_clip,
+            timeline::duplicate_clip,
+            timeline::remove_clip,
+            timeline::move_clip,
+            timeline::get_lanes,
+            timeline::add_lane,
+            timeline::set_lane,
+            timeline::remove_lane,
+            history::get_history,
+            history::undo,
+            history::redo,
             combine::play_combined_audio,
             combine::cancel_combine,
             combine::pause_combined_audio,
@@ -293,7 +365,14 @@ pub fn run() {
             clear_audio_files,
             encoder::export_audio,
             open_in_explorer,
+            cache::get_cache_info,
+            cache::clear_cache,
+            cache::set_cache_limit,
+            sample_store::get_sample_store_info,
+            sample_store::set_ram_budget,
             sorting::update_sorting,
+            sorting::sort_timeline,
+            harmony::arrange_harmonically,
         ])
         .plugin(
             tauri_plugin_log::Builder::new()
@@ -1,44 +1,22 @@
-use std::path::Path;
-
-use lofty::file::AudioFile;
+use lofty::file::{AudioFile, TaggedFile, TaggedFileExt};
 use lofty::probe::Probe;
 use lofty::read_from_path;
-use serde::Deserialize;
+use lofty::tag::ItemKey;
 use serde::Serialize;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-use symphonia::core::probe::ProbeResult;
-use symphonia::default::get_probe;
-
-use lofty;
-use uuid::Uuid;
+use symphonia::core::formats::FormatReader;
 
+use crate::archive::{entry_size, open_entry, split_entry_path};
 use crate::error::Error;
+use crate::source::{open_format, select_track, SourceRef};
 
 pub fn get_duration(path: &str) -> Option<f32> {
-    let file = std::fs::File::open(path).ok()?;
-    // let mreader = std::io::BufReader::new(file);
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-    let mut hint = Hint::new();
-    let path = Path::new(&path);
-    if let Some(extension) = path.extension() {
-        hint.with_extension(&extension.to_string_lossy());
-    };
-
-    let probed: ProbeResult = get_probe()
-        .format(
-            &hint,
-            mss,
-            &FormatOptions::default(),
-            &MetadataOptions::default(),
-        )
-        .ok()?;
+    let format = open_format(path).ok()?;
+    format_duration(format.as_ref())
+}
 
-    let format = probed.format;
-    let track = format.default_track().or_else(|| format.tracks().get(0))?;
+/// Length in seconds of the default track of an opened container.
+pub fn format_duration(format: &dyn FormatReader) -> Option<f32> {
+    let track = format.default_track().or_else(|| format.tracks().first())?;
 
     let duration = track.codec_params.n_frames?;
     let sample_rate = track.codec_params.sample_rate?;
@@ -50,16 +28,17 @@ pub fn get_duration(path: &str) -> Option<f32> {
         sample_rate,
         length
     );
-    Some(duration as f32 / sample_rate as f32)
+    Some(length)
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct FileMetadata {
     pub path: String,
     pub size: Option<u64>,
-    pub bitRate: Option<u32>,
+    pub bit_rate: Option<u32>,
     pub channels: Option<u8>,
-    pub bitDepth: Option<u8>,
+    pub bit_depth: Option<u8>,
     pub duration: u128,
 }
 
@@ -91,19 +70,8 @@ pub fn get_metadata(titles: Vec<String>) -> Result<Vec<FileMetadata>, Error> {
     let mut results = Vec::new();
 
     for title in titles {
-        match read_from_path(&title) {
-            Ok(tagged_file) => {
-                let props = tagged_file.properties();
-                // log::info!("✅ Successfully retrieved metadata for: {}", title);
-                results.push(FileMetadata {
-                    path: title.clone(),
-                    size: get_file_size(title.clone()),
-                    bitRate: props.audio_bitrate(),
-                    channels: props.channels(),
-                    bitDepth: props.bit_depth(),
-                    duration: props.duration().as_millis(),
-                });
-            }
+        match source_metadata(&title) {
+            Ok(metadata) => results.push(metadata),
             Err(e) => {
                 eprintln!("⚠️ Failed to get metadata for {}: {}", title, e);
                 // Optional: skip or return Err here
@@ -114,6 +82,116 @@ pub fn get_metadata(titles: Vec<String>) -> Result<Vec<FileMetadata>, Error> {
     Ok(results)
 }
 
+/// Metadata of source `key`: a file, an archive entry or one track of a container.
+fn source_metadata(key: &str) -> Result<FileMetadata, Error> {
+    let source = SourceRef::parse(key);
+    // lofty only knows the main stream of a container, a chosen track is probed on its own
+    let mut metadata = match source.track {
+        Some(track) => probe_metadata(&source.path, Some(track))?,
+        None => match read_tagged_file(&source.path) {
+            Ok(tagged_file) => {
+                let props = tagged_file.properties();
+                FileMetadata {
+                    path: String::new(),
+                    size: None,
+                    bit_rate: props.audio_bitrate(),
+                    channels: props.channels(),
+                    bit_depth: props.bit_depth(),
+                    duration: props.duration().as_millis(),
+                }
+            }
+            // Formats lofty doesn't read, video containers among them, still decode fine
+            Err(_) => probe_metadata(&source.path, None)?,
+        },
+    };
+
+    metadata.path = key.to_string();
+    metadata.size = get_file_size(&source.path);
+    Ok(metadata)
+}
+
+// What the container says about a track, for files lofty can't read
+fn probe_metadata(path: &str, track: Option<u32>) -> Result<FileMetadata, Error> {
+    let format = open_format(path)?;
+    let params = &select_track(format.as_ref(), track)?.codec_params;
+    let duration = match (params.n_frames, params.sample_rate) {
+        (Some(frames), Some(rate)) if rate > 0 => frames as u128 * 1000 / rate as u128,
+        _ => 0,
+    };
+    Ok(FileMetadata {
+        path: path.to_string(),
+        size: None,
+        bit_rate: None,
+        channels: params.channels.map(|channels| channels.count() as u8),
+        bit_depth: params.bits_per_sample.map(|bits| bits as u8),
+        duration,
+    })
+}
+
+// Reads tags and properties of a file on disk or an entry in an archive
+fn read_tagged_file(path: &str) -> Result<TaggedFile, Error> {
+    match split_entry_path(path) {
+        Some((archive, entry)) => Probe::new(open_entry(archive, entry)?)
+            .guess_file_type()?
+            .read(),
+        None => read_from_path(path),
+    }
+    .map_err(|e| Error::Tag(e.to_string()))
+}
+
+/// The tags the timeline can be sorted and arranged by.
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub bpm: Option<f64>,
+    // Musical key as written in the file, like "Am" or "8A"
+    pub key: Option<String>,
+}
+
+/// Tags of the file behind source `key`.
+pub fn read_tags(key: &str) -> Result<Tags, Error> {
+    let tagged_file = read_tagged_file(&SourceRef::parse(key).path)?;
+    let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    else {
+        return Ok(Tags::default());
+    };
+
+    let text = |key: ItemKey| {
+        tag.get_string(&key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let number = |key: ItemKey| text(key).and_then(|value| leading_number(&value));
+    Ok(Tags {
+        title: text(ItemKey::TrackTitle),
+        artist: text(ItemKey::TrackArtist),
+        album: text(ItemKey::AlbumTitle),
+        genre: text(ItemKey::Genre),
+        year: number(ItemKey::Year)
+            .or_else(|| number(ItemKey::RecordingDate))
+            .map(|year| year as u32),
+        track: number(ItemKey::TrackNumber).map(|track| track as u32),
+        bpm: number(ItemKey::Bpm).filter(|bpm| *bpm > 0.0),
+        key: text(ItemKey::InitialKey),
+    })
+}
+
+// "3/12" track numbers, full dates and "128 BPM" all start with the number we want
+fn leading_number(value: &str) -> Option<f64> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
 // #[tauri::command]
 // pub fn load_samples_into_state(
 //     samplePaths: Vec<String>,
@@ -121,14 +199,13 @@ pub fn get_metadata(titles: Vec<String>) -> Result<Vec<FileMetadata>, Error> {
 // ) -> Result<FileMetadata, Error> {
 // }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-struct GetFileSizeResponse {
-    file_size: Option<u64>,
-}
-fn get_file_size(path: String) -> Option<u64> {
+fn get_file_size(path: &str) -> Option<u64> {
+    // An entry's size is what it unpacks to, the archive itself holds many of them
+    if let Some((archive, entry)) = split_entry_path(path) {
+        return entry_size(archive, entry).ok();
+    }
     if let Ok(metadata) = std::fs::metadata(path) {
         return Some(metadata.len());
     }
-    return None;
+    None
 }
@@ -0,0 +1,400 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::render::Trim;
+use crate::scan::{detect_format, Detection};
+
+// CUE index times are in CD frames
+const CUE_FRAMES_PER_SECOND: f64 = 75.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistKind {
+    M3u,
+    Pls,
+    Cue,
+}
+
+impl PlaylistKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "cue" => Some(Self::Cue),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` is a playlist or CUE sheet that can be imported in place of a folder.
+pub fn is_playlist(path: &Path) -> bool {
+    PlaylistKind::from_path(path).is_some() && path.is_file()
+}
+
+/// One track of a CUE sheet, as a span of the file it points at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub file: String,
+    // Seconds into `file`
+    pub start: f64,
+    // None runs to the end of the file
+    pub end: Option<f64>,
+}
+
+impl CueTrack {
+    /// The track as in and out points of a clip on its file.
+    pub fn trim(&self) -> Trim {
+        Trim {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// The sources a playlist lists, in its own order, as `audio_files` keys.
+///
+/// Entries that are missing or not audio are skipped. A CUE sheet gives the files its tracks
+/// are cut from, each once, and the tracks become clips on them.
+pub fn playlist_sources(path: &str) -> Result<Vec<String>, Error> {
+    let playlist = Path::new(path);
+    let kind = PlaylistKind::from_path(playlist)
+        .ok_or_else(|| Error::InvalidPlaylist(format!("{} is not a playlist", path)))?;
+    let base = playlist.parent().unwrap_or(Path::new(""));
+    let text = read_text(playlist)?;
+
+    let entries = match kind {
+        PlaylistKind::M3u => parse_m3u(&text, base),
+        PlaylistKind::Pls => parse_pls(&text, base),
+        PlaylistKind::Cue => {
+            let mut files: Vec<PathBuf> = Vec::new();
+            for track in parse_cue(&text, base)? {
+                let file = PathBuf::from(track.file);
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+            files
+        }
+    };
+
+    let mut sources = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let Some(entry_str) = entry.to_str() else {
+            println!("Skipping {}: not a valid path", entry.display());
+            continue;
+        };
+        if !entry.is_file() {
+            println!("Skipping {}: listed in {} but not found", entry_str, path);
+            continue;
+        }
+        if let Detection::Unsupported { reason } = detect_format(&entry) {
+            println!("Skipping {}: {}", entry_str, reason);
+            continue;
+        }
+        sources.push(entry_str.to_string());
+    }
+    println!("{}: {} entries", path, sources.len());
+    Ok(sources)
+}
+
+/// The audio tracks of the CUE sheet at `cue_path`, in the order it lists them.
+pub fn cue_tracks(cue_path: &str) -> Result<Vec<CueTrack>, Error> {
+    let cue = Path::new(cue_path);
+    let base = cue.parent().unwrap_or(Path::new(""));
+    parse_cue(&read_text(cue)?, base)
+}
+
+// Older playlists are often Latin-1 or carry a BOM, neither should stop the import
+fn read_text(path: &Path) -> Result<String, Error> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Relative entries are relative to the playlist, URLs other than file:// are streams we can't import
+fn resolve_entry(base: &Path, entry: &str) -> Option<PathBuf> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return None;
+    }
+    let entry = match entry.strip_prefix("file://") {
+        Some(local) => percent_decode(local),
+        None if entry.contains("://") => return None,
+        None => entry.to_string(),
+    };
+    // Playlists written on Windows use backslashes
+    let entry = if cfg!(windows) {
+        entry
+    } else {
+        entry.replace('\\', "/")
+    };
+    Some(base.join(entry))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%' && i + 2 < bytes.len())
+            .then(|| std::str::from_utf8(&bytes[i + 1..i + 3]).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(byte) = escaped {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn parse_m3u(text: &str, base: &Path) -> Vec<PathBuf> {
+    // #EXTM3U, #EXTINF and friends only describe the entries, the paths are the other lines
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| resolve_entry(base, line))
+        .collect()
+}
+
+fn parse_pls(text: &str, base: &Path) -> Vec<PathBuf> {
+    // Entries are numbered File1, File2... and the numbers, not the line order, give the order
+    let mut entries: Vec<(u32, PathBuf)> = text
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let key = key.trim().to_lowercase();
+            let number = key.strip_prefix("file")?.parse().ok()?;
+            Some((number, resolve_entry(base, value)?))
+        })
+        .collect();
+    entries.sort_by_key(|(number, _)| *number);
+    entries.into_iter().map(|(_, path)| path).collect()
+}
+
+fn parse_cue(text: &str, base: &Path) -> Result<Vec<CueTrack>, Error> {
+    let mut tracks: Vec<CueTrack> = Vec::new();
+    let mut file: Option<String> = None;
+    // Data tracks on enhanced CDs are listed too, their commands are ignored
+    let mut in_audio_track = false;
+
+    for line in text.lines() {
+        let words = split_cue_line(line);
+        let Some(command) = words.first() else {
+            continue;
+        };
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                let name = words
+                    .get(1)
+                    .ok_or_else(|| Error::InvalidPlaylist("FILE without a name".to_string()))?;
+                let path = resolve_entry(base, name).ok_or_else(|| {
+                    Error::InvalidPlaylist(format!("Unsupported FILE entry {}", name))
+                })?;
+                file = Some(path.to_string_lossy().to_string());
+                in_audio_track = false;
+            }
+            "TRACK" => {
+                let number = words
+                    .get(1)
+                    .and_then(|number| number.parse().ok())
+                    .ok_or_else(|| Error::InvalidPlaylist(format!("Bad TRACK line: {}", line)))?;
+                in_audio_track = words
+                    .get(2)
+                    .is_some_and(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                if !in_audio_track {
+                    continue;
+                }
+                let file = file.clone().ok_or_else(|| {
+                    Error::InvalidPlaylist(format!("Track {} comes before any FILE", number))
+                })?;
+                tracks.push(CueTrack {
+                    number,
+                    file,
+                    start: f64::NAN,
+                    end: None,
+                });
+            }
+            // INDEX 00 marks the pregap, which stays with the track before it
+            "INDEX" if in_audio_track && words.get(1).map(String::as_str) == Some("01") => {
+                let time = words
+                    .get(2)
+                    .and_then(|time| parse_cue_time(time))
+                    .ok_or_else(|| Error::InvalidPlaylist(format!("Bad INDEX line: {}", line)))?;
+                if let Some(track) = tracks.last_mut() {
+                    track.start = time;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(track) = tracks.iter().find(|track| track.start.is_nan()) {
+        return Err(Error::InvalidPlaylist(format!(
+            "Track {} has no INDEX 01",
+            track.number
+        )));
+    }
+    // Each track runs up to the next one in the same file, the last one to the end of it
+    for i in 0..tracks.len().saturating_sub(1) {
+        if tracks[i + 1].file == tracks[i].file {
+            tracks[i].end = Some(tracks[i + 1].start);
+        }
+    }
+    Ok(tracks)
+}
+
+// Splits a CUE line into words, keeping quoted names with spaces together
+fn split_cue_line(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            words.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    words
+}
+
+// mm:ss:ff, where minutes can go past 59 on long discs
+fn parse_cue_time(time: &str) -> Option<f64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((minutes * 60 + seconds) as f64 + frames as f64 / CUE_FRAMES_PER_SECOND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_wav, TempDir};
+
+    #[test]
+    fn pls_is_ordered_by_entry_number() {
+        let text = "[playlist]\nFile2=b.flac\nTitle2=B\nfile1=a.flac\nFile3=http://radio.example/stream\nNumberOfEntries=3\n";
+        let entries = parse_pls(text, Path::new("/music"));
+        assert_eq!(
+            entries,
+            vec![
+                PathBuf::from("/music/a.flac"),
+                PathBuf::from("/music/b.flac")
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_decodes_file_urls() {
+        let entries = parse_pls("File1=file:///music/My%20Song.wav", Path::new("/other"));
+        assert_eq!(entries, vec![PathBuf::from("/music/My Song.wav")]);
+    }
+
+    #[test]
+    fn cue_tracks_run_to_the_next_index() {
+        let text = r#"
+REM GENRE Rock
+FILE "Album Image.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "One"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 03:59:00
+    INDEX 01 04:00:37
+FILE "bonus.wav" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+"#;
+        let tracks = parse_cue(text, Path::new("/music")).unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].file, "/music/Album Image.flac");
+        assert_eq!(tracks[0].start, 0.0);
+        // The pregap of track 2 stays with track 1
+        assert_eq!(tracks[0].end, Some(240.0 + 37.0 / 75.0));
+        assert_eq!(tracks[1].start, 240.0 + 37.0 / 75.0);
+        // Last track of a file runs to its end
+        assert_eq!(tracks[1].end, None);
+        assert_eq!(tracks[2].number, 3);
+        assert_eq!(tracks[2].file, "/music/bonus.wav");
+    }
+
+    #[test]
+    fn cue_skips_data_tracks() {
+        let text = "FILE \"disc.bin\" BINARY\nTRACK 01 MODE1/2352\nINDEX 01 00:00:00\nFILE \"audio.wav\" WAVE\nTRACK 02 AUDIO\nINDEX 01 00:02:00\n";
+        let tracks = parse_cue(text, Path::new("")).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].number, 2);
+        assert_eq!(tracks[0].start, 2.0);
+    }
+
+    #[test]
+    fn cue_rejects_tracks_without_an_index() {
+        let text = "FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nTITLE \"No index\"\n";
+        assert!(parse_cue(text, Path::new("")).is_err());
+        assert!(parse_cue("TRACK 01 AUDIO\nINDEX 01 00:00:00\n", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn cue_times_are_in_cd_frames() {
+        assert_eq!(parse_cue_time("00:01:00"), Some(1.0));
+        assert_eq!(
+            parse_cue_time("74:59:74"),
+            Some(74.0 * 60.0 + 59.0 + 74.0 / 75.0)
+        );
+        assert_eq!(parse_cue_time("01:02"), None);
+        assert_eq!(parse_cue_time("00:00:00:00"), None);
+    }
+
+    #[test]
+    fn cue_lists_its_file_once_and_tracks_trim_it() {
+        let dir = TempDir::new("playlist");
+        write_wav(&dir.join("album.wav"), 44100, 2, 44100 * 3);
+        let cue = dir.join("album.cue");
+        fs::write(
+            &cue,
+            "FILE \"album.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nTRACK 02 AUDIO\nINDEX 01 00:01:30\nFILE \"missing.wav\" WAVE\nTRACK 03 AUDIO\nINDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        let cue = cue.to_string_lossy().to_string();
+        let album = dir.join("album.wav").to_string_lossy().to_string();
+
+        assert_eq!(playlist_sources(&cue).unwrap(), vec![album.clone()]);
+        let tracks = cue_tracks(&cue).unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].file, album);
+        assert_eq!(
+            tracks[0].trim(),
+            Trim {
+                start: 0.0,
+                end: Some(1.4)
+            }
+        );
+        assert_eq!(
+            tracks[1].trim(),
+            Trim {
+                start: 1.4,
+                end: None
+            }
+        );
+    }
+}
@@ -0,0 +1,997 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::resample::ProjectFormat;
+use crate::sample_store::{SampleStore, Samples};
+use crate::state::AppState;
+use crate::timeline::{ClipView, Lane, MAIN_LANE};
+
+/// How the level moves across a crossfade.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FadeCurve {
+    Linear,
+    // Keeps the summed power constant, so uncorrelated material doesn't dip in the middle
+    #[default]
+    EqualPower,
+    // Raised cosine, slow at both ends
+    SCurve,
+}
+
+impl FadeCurve {
+    /// Gain of the incoming clip `t` of the way through the fade.
+    pub fn fade_in(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EqualPower => (t * FRAC_PI_2).sin(),
+            Self::SCurve => 0.5 - 0.5 * (t * std::f32::consts::PI).cos(),
+        }
+    }
+
+    /// Gain of the outgoing clip `t` of the way through the fade.
+    pub fn fade_out(self, t: f32) -> f32 {
+        match self {
+            Self::EqualPower => (t.clamp(0.0, 1.0) * FRAC_PI_2).cos(),
+            _ => 1.0 - self.fade_in(t),
+        }
+    }
+}
+
+/// A fade over `duration_ms`, for a crossfade at a join or a clip's own fade in or out.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Fade {
+    pub duration_ms: f64,
+    pub curve: FadeCurve,
+}
+
+/// The crossfade used at every join, and overrides for single joins.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CrossfadeSettings {
+    pub default: Option<Fade>,
+    // Keyed by the clip that fades in, a zero duration forces a hard cut
+    pub joins: HashMap<Uuid, Fade>,
+}
+
+impl CrossfadeSettings {
+    fn for_join(&self, incoming: Uuid) -> Option<Fade> {
+        self.joins.get(&incoming).copied().or(self.default)
+    }
+}
+
+/// In and out points of a clip, in seconds into its source so they survive a change of
+/// project sample rate. The source samples themselves are never cut.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Trim {
+    pub start: f64,
+    // None plays to the end of the source
+    pub end: Option<f64>,
+}
+
+impl Trim {
+    /// The part of `samples` between the in and out points, clamped to what's there.
+    pub fn apply<'a>(&self, samples: &'a [f32], format: &ProjectFormat) -> &'a [f32] {
+        let channels = format.channels.max(1) as usize;
+        let frames = samples.len() / channels;
+        let to_frame = |seconds: f64| {
+            ((seconds.max(0.0) * format.sample_rate as f64).round() as usize).min(frames)
+        };
+        let start = to_frame(self.start);
+        let end = self.end.map_or(frames, to_frame).max(start);
+        &samples[start * channels..end * channels]
+    }
+}
+
+/// A clip's level: a gain plus optional fades at either end of the trimmed clip.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub gain_db: f32,
+    pub fade_in: Option<Fade>,
+    pub fade_out: Option<Fade>,
+}
+
+impl Envelope {
+    /// The envelope laid over a clip `frames` long.
+    pub fn shape(&self, frames: usize, sample_rate: u32) -> EnvelopeShape {
+        let to_frames = |fade: Option<Fade>| {
+            fade.map_or(0, |fade| {
+                (fade.duration_ms.max(0.0) * sample_rate as f64 / 1000.0).round() as usize
+            })
+        };
+        // Fades longer than the clip are shortened, the fade in wins
+        let fade_in = to_frames(self.fade_in).min(frames);
+        let fade_out = to_frames(self.fade_out).min(frames - fade_in);
+        EnvelopeShape {
+            gain: 10f32.powf(self.gain_db / 20.0),
+            fade_in,
+            fade_out,
+            in_curve: self.fade_in.map(|fade| fade.curve).unwrap_or_default(),
+            out_curve: self.fade_out.map(|fade| fade.curve).unwrap_or_default(),
+            frames,
+        }
+    }
+}
+
+/// An [`Envelope`] worked out for one clip, ready to be applied frame by frame.
+pub struct EnvelopeShape {
+    gain: f32,
+    fade_in: usize,
+    fade_out: usize,
+    in_curve: FadeCurve,
+    out_curve: FadeCurve,
+    frames: usize,
+}
+
+impl EnvelopeShape {
+    pub fn gain_at(&self, frame: usize) -> f32 {
+        let mut gain = self.gain;
+        if frame < self.fade_in {
+            gain *= self
+                .in_curve
+                .fade_in((frame as f32 + 0.5) / self.fade_in as f32);
+        }
+        let out_start = self.frames - self.fade_out;
+        if frame >= out_start {
+            gain *= self
+                .out_curve
+                .fade_out(((frame - out_start) as f32 + 0.5) / self.fade_out as f32);
+        }
+        gain
+    }
+
+    fn is_unity(&self) -> bool {
+        self.gain == 1.0 && self.fade_in == 0 && self.fade_out == 0
+    }
+}
+
+/// A length of silence, in whichever unit the delivery spec uses.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    tag = "unit",
+    content = "value"
+)]
+pub enum GapLength {
+    Milliseconds(f64),
+    // Per channel, so the same number works for mono and stereo projects
+    Samples(u64),
+    Beats { beats: f64, bpm: f64 },
+}
+
+impl GapLength {
+    pub fn to_frames(self, sample_rate: u32) -> usize {
+        let seconds = match self {
+            Self::Milliseconds(ms) => ms / 1000.0,
+            Self::Samples(frames) => return frames as usize,
+            Self::Beats { beats, bpm } if bpm > 0.0 => beats * 60.0 / bpm,
+            Self::Beats { .. } => 0.0,
+        };
+        (seconds.max(0.0) * sample_rate as f64).round() as usize
+    }
+}
+
+/// Silence between clips, and optionally before the first and after the last.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GapSettings {
+    pub between: Option<GapLength>,
+    pub before_first: Option<GapLength>,
+    pub after_last: Option<GapLength>,
+    // The gap before a clip, taking over from `between` (or `before_first` for the first clip)
+    pub clips: HashMap<Uuid, GapLength>,
+}
+
+impl GapSettings {
+    fn before(&self, index: usize, clip: Uuid) -> Option<GapLength> {
+        let fallback = if index == 0 {
+            self.before_first
+        } else {
+            self.between
+        };
+        self.clips.get(&clip).copied().or(fallback)
+    }
+}
+
+/// Where each clip of the timeline lands in the rendered output.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    // Sample index each clip starts at
+    pub starts: Vec<usize>,
+    // Silence before each clip, in frames
+    pub gaps: Vec<usize>,
+    // Overlap with the clip before, in frames. Always 0 for the first clip
+    pub overlaps: Vec<usize>,
+    pub curves: Vec<FadeCurve>,
+    // Silence after the last clip, in frames
+    pub trailing_gap: usize,
+    // Length of the whole render in samples
+    pub total: usize,
+}
+
+impl Layout {
+    /// Works out the joins between `clips`, played in order.
+    ///
+    /// A join with a gap is never crossfaded. A crossfade never takes more than half of
+    /// either clip, so a fade in and a fade out can't run into each other on a short clip.
+    pub fn new(
+        clips: &[ClipView],
+        crossfades: &CrossfadeSettings,
+        gaps: &GapSettings,
+        format: &ProjectFormat,
+    ) -> Self {
+        let channels = format.channels.max(1) as usize;
+        let frames_per_ms = format.sample_rate as f64 / 1000.0;
+
+        let mut layout = Layout::default();
+        let mut position = 0;
+        for (i, clip) in clips.iter().enumerate() {
+            let frames = clip.samples(format).len() / channels;
+            let gap = gaps
+                .before(i, clip.id())
+                .map_or(0, |gap| gap.to_frames(format.sample_rate));
+            let crossfade = crossfades.for_join(clip.id()).filter(|_| gap == 0);
+            let (overlap, curve) = match (i.checked_sub(1), crossfade) {
+                (Some(previous), Some(crossfade)) => {
+                    let previous_frames = clips[previous].samples(format).len() / channels;
+                    let requested = (crossfade.duration_ms.max(0.0) * frames_per_ms).round();
+                    let overlap = (requested as usize)
+                        .min(previous_frames / 2)
+                        .min(frames / 2);
+                    (overlap, crossfade.curve)
+                }
+                _ => (0, FadeCurve::default()),
+            };
+
+            position += gap * channels;
+            position -= overlap * channels;
+            layout.starts.push(position);
+            layout.gaps.push(gap);
+            layout.overlaps.push(overlap);
+            layout.curves.push(curve);
+            position += frames * channels;
+        }
+        if !clips.is_empty() {
+            layout.trailing_gap = gaps
+                .after_last
+                .map_or(0, |gap| gap.to_frames(format.sample_rate));
+            position += layout.trailing_gap * channels;
+        }
+        layout.total = position;
+        layout
+    }
+}
+
+/// Writes clips one after another, mixing each join across its crossfade.
+///
+/// Only the tail waiting for the next clip is held back, everything else goes straight
+/// to `out`, so a render can stream into a spill file.
+pub struct Mixdown {
+    format: ProjectFormat,
+    tail: Vec<f32>,
+    scratch: Vec<f32>,
+}
+
+impl Mixdown {
+    pub fn new(format: ProjectFormat) -> Self {
+        Self {
+            format,
+            tail: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Adds clip `index` of `layout`, trimmed and with its envelope applied.
+    pub fn push(
+        &mut self,
+        clip: &ClipView,
+        layout: &Layout,
+        index: usize,
+        out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let channels = self.format.channels.max(1) as usize;
+        let samples = clip.samples(&self.format);
+        let shape = clip.shape(&self.format);
+        let frames = samples.len() / channels;
+        let overlap = layout.overlaps[index];
+        let hold = layout.overlaps.get(index + 1).copied().unwrap_or(0);
+        let curve = layout.curves[index];
+
+        write_silence(layout.gaps[index] * channels, out)?;
+        if overlap > 0 {
+            self.scratch.clear();
+            apply_envelope(samples, channels, 0..overlap, &shape, &mut self.scratch);
+            for (frame, (outgoing, incoming)) in self
+                .tail
+                .chunks_exact_mut(channels)
+                .zip(self.scratch.chunks_exact(channels))
+                .enumerate()
+            {
+                let t = (frame as f32 + 0.5) / overlap as f32;
+                let (gain_out, gain_in) = (curve.fade_out(t), curve.fade_in(t));
+                for (a, b) in outgoing.iter_mut().zip(incoming) {
+                    *a = *a * gain_out + b * gain_in;
+                }
+            }
+            out(&self.tail)?;
+        }
+
+        let body = overlap..frames - hold;
+        if shape.is_unity() {
+            out(&samples[body.start * channels..body.end * channels])?;
+        } else {
+            // A block at a time, so long clips aren't copied whole
+            for start in body.clone().step_by(BLOCK_FRAMES) {
+                self.scratch.clear();
+                let end = (start + BLOCK_FRAMES).min(body.end);
+                apply_envelope(samples, channels, start..end, &shape, &mut self.scratch);
+                out(&self.scratch)?;
+            }
+        }
+
+        self.tail.clear();
+        apply_envelope(
+            samples,
+            channels,
+            frames - hold..frames,
+            &shape,
+            &mut self.tail,
+        );
+        Ok(())
+    }
+
+    /// Writes the silence after the last clip.
+    pub fn finish(
+        self,
+        layout: &Layout,
+        out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        write_silence(
+            layout.trailing_gap * self.format.channels.max(1) as usize,
+            out,
+        )
+    }
+}
+
+const BLOCK_FRAMES: usize = 4096;
+
+// Appends `frames` of `samples` to `dst` with the clip's envelope applied
+fn apply_envelope(
+    samples: &[f32],
+    channels: usize,
+    frames: std::ops::Range<usize>,
+    shape: &EnvelopeShape,
+    dst: &mut Vec<f32>,
+) {
+    let start = frames.start;
+    let slice = &samples[frames.start * channels..frames.end * channels];
+    for (i, frame) in slice.chunks_exact(channels).enumerate() {
+        let gain = shape.gain_at(start + i);
+        dst.extend(frame.iter().map(|sample| sample * gain));
+    }
+}
+
+fn write_silence(
+    len: usize,
+    out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    // Long gaps go out a block at a time rather than as one big allocation
+    const BLOCK: [f32; 4096] = [0.0; 4096];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(BLOCK.len());
+        out(&BLOCK[..n])?;
+        remaining -= n;
+    }
+    Ok(())
+}
+
+/// Where every clip lands once the lanes are laid out.
+///
+/// Clips on the main lane play one after another with the joins and gaps of [`Layout`].
+/// Clips on the other lanes sit at their own start and are mixed over the top.
+pub struct Arrangement<'a> {
+    pub main: Vec<ClipView<'a>>,
+    pub layout: Layout,
+    pub overlays: Vec<ClipView<'a>>,
+    // Length of the whole render in samples, the longest lane wins
+    pub total: usize,
+}
+
+impl<'a> Arrangement<'a> {
+    pub fn new(
+        clips: &[ClipView<'a>],
+        crossfades: &CrossfadeSettings,
+        gaps: &GapSettings,
+        format: &ProjectFormat,
+    ) -> Self {
+        let channels = format.channels.max(1) as usize;
+        let (main, overlays): (Vec<ClipView>, Vec<ClipView>) =
+            clips.iter().partition(|clip| clip.clip.lane == MAIN_LANE);
+        let layout = Layout::new(&main, crossfades, gaps, format);
+        let total = overlays
+            .iter()
+            .map(|clip| clip.clip.start as usize * channels + clip.samples(format).len())
+            .fold(layout.total, usize::max);
+        Self {
+            main,
+            layout,
+            overlays,
+            total,
+        }
+    }
+
+    /// Every clip with the sample index it starts at, main lane first.
+    pub fn starts(&self, format: &ProjectFormat) -> Vec<(ClipView<'a>, usize)> {
+        let channels = format.channels.max(1) as usize;
+        self.main
+            .iter()
+            .copied()
+            .zip(self.layout.starts.iter().copied())
+            .chain(
+                self.overlays
+                    .iter()
+                    .map(|clip| (*clip, clip.clip.start as usize * channels)),
+            )
+            .collect()
+    }
+}
+
+// A clip on one of the other lanes, with its start as a sample index
+struct Overlay<'a> {
+    samples: &'a [f32],
+    shape: EnvelopeShape,
+    start: usize,
+    gain: f32,
+}
+
+// How long the limiter takes to let the level back up after a peak
+const LIMIT_RELEASE_SECONDS: f32 = 0.05;
+
+/// Sums the overlay lanes into the main lane as it streams past, with each lane's gain.
+///
+/// Overlapping lanes and lane gain can add up past full scale, so the sum goes through a
+/// limiter. It only turns the level down when a frame would go over 1.0, right at that
+/// frame, and lets it back up over [`LIMIT_RELEASE_SECONDS`]. Anything that never goes over
+/// comes out exactly as it went in.
+pub struct LaneMixer<'a> {
+    channels: usize,
+    main_gain: f32,
+    overlays: Vec<Overlay<'a>>,
+    position: usize,
+    total: usize,
+    buffer: Vec<f32>,
+    // Gain the limiter applies right now, and how much of the way back to 1.0 is left after
+    // each frame
+    limit_gain: f32,
+    release: f32,
+    limited: usize,
+}
+
+impl<'a> LaneMixer<'a> {
+    pub fn new(arrangement: &Arrangement<'a>, lanes: &[Lane], format: &ProjectFormat) -> Self {
+        let channels = format.channels.max(1) as usize;
+        let lane_gain = |id: Uuid| {
+            lanes
+                .iter()
+                .find(|lane| lane.id == id)
+                .map_or(0.0, Lane::gain)
+        };
+        let overlays = arrangement
+            .overlays
+            .iter()
+            .map(|clip| Overlay {
+                samples: clip.samples(format),
+                shape: clip.shape(format),
+                start: clip.clip.start as usize * channels,
+                gain: lane_gain(clip.clip.lane),
+            })
+            // Muted lanes still take up time but don't need mixing
+            .filter(|overlay| overlay.gain > 0.0)
+            .collect();
+        Self {
+            channels,
+            main_gain: lane_gain(MAIN_LANE),
+            overlays,
+            position: 0,
+            total: arrangement.total,
+            buffer: Vec::new(),
+            limit_gain: 1.0,
+            release: (-1.0 / (LIMIT_RELEASE_SECONDS * format.sample_rate.max(1) as f32)).exp(),
+            limited: 0,
+        }
+    }
+
+    /// Mixes the next stretch of the main lane with whatever overlaps it and writes it out.
+    pub fn write(
+        &mut self,
+        main: &[f32],
+        out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let range = self.position..self.position + main.len();
+        self.buffer.clear();
+        self.buffer
+            .extend(main.iter().map(|sample| sample * self.main_gain));
+
+        for overlay in &self.overlays {
+            let end = overlay.start + overlay.samples.len();
+            if end <= range.start || overlay.start >= range.end {
+                continue;
+            }
+            for i in range.start.max(overlay.start)..range.end.min(end) {
+                let frame = (i - overlay.start) / self.channels;
+                self.buffer[i - range.start] += overlay.samples[i - overlay.start]
+                    * overlay.shape.gain_at(frame)
+                    * overlay.gain;
+            }
+        }
+
+        self.limit();
+        self.position = range.end;
+        out(&self.buffer)
+    }
+
+    /// Writes the overlays that run on past the end of the main lane.
+    pub fn finish(
+        mut self,
+        out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        const BLOCK: [f32; 4096] = [0.0; 4096];
+        while self.position < self.total {
+            let n = (self.total - self.position).min(BLOCK.len());
+            self.write(&BLOCK[..n], out)?;
+        }
+        if self.limited > 0 {
+            println!("🔊 Limiter turned down {} frames", self.limited);
+        }
+        Ok(())
+    }
+
+    // Frame by frame, so every channel of a frame gets the same gain and the stereo image holds
+    fn limit(&mut self) {
+        for frame in self.buffer.chunks_mut(self.channels) {
+            let peak = frame
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            if peak > 1.0 {
+                self.limit_gain = self.limit_gain.min(1.0 / peak);
+            }
+            if self.limit_gain < 1.0 {
+                for sample in frame.iter_mut() {
+                    *sample *= self.limit_gain;
+                }
+                self.limited += 1;
+                self.limit_gain = 1.0 - (1.0 - self.limit_gain) * self.release;
+                if self.limit_gain > 0.9999 {
+                    self.limit_gain = 1.0;
+                }
+            }
+        }
+    }
+}
+
+/// Renders `clips` into the sample store, spilling to disk past the RAM budget like the
+/// combined preview does.
+pub fn render_to_store(
+    clips: &[ClipView],
+    lanes: &[Lane],
+    crossfades: &CrossfadeSettings,
+    gaps: &GapSettings,
+    format: &ProjectFormat,
+    store: &SampleStore,
+) -> Result<Samples, Error> {
+    let arrangement = Arrangement::new(clips, crossfades, gaps, format);
+    let mut rendered = store.writer(arrangement.total)?;
+    let mut out = |samples: &[f32]| rendered.extend_from_slice(samples);
+    let mut mixer = LaneMixer::new(&arrangement, lanes, format);
+    let mut mixdown = Mixdown::new(*format);
+    let mut write = |samples: &[f32]| mixer.write(samples, &mut out);
+    for (index, clip) in arrangement.main.iter().enumerate() {
+        mixdown.push(clip, &arrangement.layout, index, &mut write)?;
+    }
+    mixdown.finish(&arrangement.layout, &mut write)?;
+    mixer.finish(&mut out)?;
+    rendered.finish()
+}
+
+#[tauri::command]
+pub fn get_crossfades(state: State<'_, Arc<AppState>>) -> Result<CrossfadeSettings, Error> {
+    let crossfades = state.crossfades.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(crossfades.clone())
+}
+
+/// Sets the crossfade for every join, or None for hard cuts.
+#[tauri::command]
+pub fn set_crossfade(
+    crossfade: Option<Fade>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    println!("Crossfade set to {:?}", crossfade);
+    state
+        .crossfades
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .default = crossfade;
+    edit.record("Set crossfade")?;
+    invalidate_mix(&state)
+}
+
+/// Overrides the crossfade into clip `id`, or goes back to the global one with None.
+#[tauri::command]
+pub fn set_join_crossfade(
+    id: Uuid,
+    crossfade: Option<Fade>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut crossfades = state.crossfades.lock().map_err(|_| Error::LockPoisoned)?;
+        match crossfade {
+            Some(crossfade) => crossfades.joins.insert(id, crossfade),
+            None => crossfades.joins.remove(&id),
+        };
+    }
+    edit.record("Set join crossfade")?;
+    invalidate_mix(&state)
+}
+
+/// Sets the gain and fades of clip `id`.
+#[tauri::command]
+pub fn set_envelope(
+    id: Uuid,
+    envelope: Envelope,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Envelope, Error> {
+    if !envelope.gain_db.is_finite() {
+        return Err(Error::InvalidGain);
+    }
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let clip = timeline
+            .iter_mut()
+            .find(|clip| clip.id == id)
+            .ok_or(Error::ClipNotFound(id))?;
+        println!("🎚️ Envelope of {} set to {:?}", clip.source, envelope);
+        clip.envelope = envelope;
+    }
+    edit.record("Set envelope")?;
+    invalidate_mix(&state)?;
+    Ok(envelope)
+}
+
+/// Sets the in and out points of clip `id`, in seconds. `end` None plays to the end.
+#[tauri::command]
+pub fn set_trim(
+    id: Uuid,
+    start: f64,
+    end: Option<f64>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Trim, Error> {
+    if !start.is_finite() || end.is_some_and(|end| !end.is_finite() || end < start) {
+        return Err(Error::InvalidTrim);
+    }
+    let trim = Trim {
+        start: start.max(0.0),
+        end,
+    };
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let clip = timeline
+            .iter_mut()
+            .find(|clip| clip.id == id)
+            .ok_or(Error::ClipNotFound(id))?;
+        println!("✂️ Trimmed {} to {:?}", clip.source, trim);
+        clip.trim = trim;
+    }
+    edit.record("Trim clip")?;
+    invalidate_mix(&state)?;
+    Ok(trim)
+}
+
+#[tauri::command]
+pub fn get_gaps(state: State<'_, Arc<AppState>>) -> Result<GapSettings, Error> {
+    let gaps = state.gaps.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(gaps.clone())
+}
+
+/// Sets the silence between every pair of clips, or None to butt them together.
+#[tauri::command]
+pub fn set_gap(gap: Option<GapLength>, state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    println!("Gap set to {:?}", gap);
+    state.gaps.lock().map_err(|_| Error::LockPoisoned)?.between = gap;
+    edit.record("Set gap")?;
+    invalidate_mix(&state)
+}
+
+/// Sets the silence before the first clip and after the last.
+#[tauri::command]
+pub fn set_edge_gaps(
+    before_first: Option<GapLength>,
+    after_last: Option<GapLength>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut gaps = state.gaps.lock().map_err(|_| Error::LockPoisoned)?;
+        gaps.before_first = before_first;
+        gaps.after_last = after_last;
+    }
+    edit.record("Set edge gaps")?;
+    invalidate_mix(&state)
+}
+
+/// Overrides the silence before clip `id`, or goes back to the global gap with None.
+#[tauri::command]
+pub fn set_clip_gap(
+    id: Uuid,
+    gap: Option<GapLength>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut gaps = state.gaps.lock().map_err(|_| Error::LockPoisoned)?;
+        match gap {
+            Some(gap) => gaps.clips.insert(id, gap),
+            None => gaps.clips.remove(&id),
+        };
+    }
+    edit.record("Set clip gap")?;
+    invalidate_mix(&state)
+}
+
+/// Drops the rendered mix after anything that changes how it sounds.
+pub fn invalidate_mix(state: &AppState) -> Result<(), Error> {
+    *state
+        .combined_audio
+        .lock()
+        .map_err(|_| Error::LockPoisoned)? = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resample::SourceSpec;
+    use crate::sample_store::SampleStore;
+    use crate::state::AudioFile;
+    use crate::timeline::Clip;
+
+    // One frame per millisecond keeps the numbers readable
+    const FORMAT: ProjectFormat = ProjectFormat {
+        sample_rate: 1000,
+        channels: 1,
+    };
+
+    fn source(samples: Vec<f32>) -> AudioFile {
+        AudioFile {
+            samples: SampleStore::new().insert(samples).unwrap(),
+            id: Uuid::new_v4(),
+            path: String::new(),
+            original_spec: SourceSpec {
+                sample_rate: FORMAT.sample_rate,
+                channels: FORMAT.channels,
+                channel_mask: 0,
+                depth: None,
+            },
+            peaks: Vec::new(),
+            decode_report: Default::default(),
+            section: String::new(),
+            content_hash: 0,
+            duplicate_of: None,
+            fingerprint: None,
+            analysis: None,
+            suggested_trim: Trim::default(),
+        }
+    }
+
+    fn fade(duration_ms: f64, curve: FadeCurve) -> Fade {
+        Fade { duration_ms, curve }
+    }
+
+    fn crossfade(duration_ms: f64, curve: FadeCurve) -> CrossfadeSettings {
+        CrossfadeSettings {
+            default: Some(fade(duration_ms, curve)),
+            joins: HashMap::new(),
+        }
+    }
+
+    // Lays out and mixes `sources` in order, returning the layout and the rendered samples
+    fn render(
+        sources: &[AudioFile],
+        crossfades: &CrossfadeSettings,
+        gaps: &GapSettings,
+    ) -> (Layout, Vec<f32>) {
+        let clips: Vec<Clip> = sources.iter().map(Clip::for_source).collect();
+        let views: Vec<ClipView> = clips
+            .iter()
+            .zip(sources)
+            .map(|(clip, source)| ClipView::new(clip, source, false))
+            .collect();
+        let layout = Layout::new(&views, crossfades, gaps, &FORMAT);
+        let mut rendered = Vec::new();
+        let mut out = |samples: &[f32]| {
+            rendered.extend_from_slice(samples);
+            Ok(())
+        };
+        let mut mixdown = Mixdown::new(FORMAT);
+        for (index, view) in views.iter().enumerate() {
+            mixdown.push(view, &layout, index, &mut out).unwrap();
+        }
+        mixdown.finish(&layout, &mut out).unwrap();
+        (layout, rendered)
+    }
+
+    #[test]
+    fn fade_curves_meet_at_the_ends() {
+        for curve in [FadeCurve::Linear, FadeCurve::EqualPower, FadeCurve::SCurve] {
+            assert!(curve.fade_in(0.0).abs() < 1e-6);
+            assert!((curve.fade_in(1.0) - 1.0).abs() < 1e-6);
+            assert!((curve.fade_out(0.0) - 1.0).abs() < 1e-6);
+            assert!(curve.fade_out(1.0).abs() < 1e-6);
+        }
+        let (a, b) = (
+            FadeCurve::EqualPower.fade_out(0.3),
+            FadeCurve::EqualPower.fade_in(0.3),
+        );
+        assert!((a * a + b * b - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn envelope_applies_gain_and_fades() {
+        let envelope = Envelope {
+            gain_db: -6.0,
+            fade_in: Some(fade(10.0, FadeCurve::Linear)),
+            fade_out: Some(fade(10.0, FadeCurve::Linear)),
+        };
+        let shape = envelope.shape(100, FORMAT.sample_rate);
+        let gain = 10f32.powf(-6.0 / 20.0);
+        assert!((shape.gain_at(50) - gain).abs() < 1e-6);
+        assert!((shape.gain_at(0) - gain * 0.05).abs() < 1e-6);
+        assert!((shape.gain_at(99) - gain * 0.05).abs() < 1e-6);
+        assert!(shape.gain_at(5) < shape.gain_at(9));
+        assert!(!shape.is_unity());
+        assert!(Envelope::default().shape(100, 1000).is_unity());
+    }
+
+    #[test]
+    fn envelope_fades_are_cut_to_the_clip() {
+        let envelope = Envelope {
+            gain_db: 0.0,
+            fade_in: Some(fade(80.0, FadeCurve::Linear)),
+            fade_out: Some(fade(80.0, FadeCurve::Linear)),
+        };
+        let shape = envelope.shape(100, FORMAT.sample_rate);
+        // The fade in wins, the fade out gets what's left
+        assert_eq!((shape.fade_in, shape.fade_out), (80, 20));
+    }
+
+    #[test]
+    fn layout_overlaps_crossfaded_joins() {
+        let sources = [source(vec![1.0; 100]), source(vec![1.0; 100])];
+        let (layout, rendered) = render(
+            &sources,
+            &crossfade(20.0, FadeCurve::Linear),
+            &GapSettings::default(),
+        );
+        assert_eq!(layout.starts, vec![0, 80]);
+        assert_eq!(layout.overlaps, vec![0, 20]);
+        assert_eq!(layout.total, 180);
+        assert_eq!(rendered.len(), layout.total);
+        // Linear gains add up to one across the join
+        assert!(rendered.iter().all(|sample| (sample - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn layout_caps_crossfades_at_half_a_clip() {
+        let sources = [source(vec![1.0; 100]), source(vec![1.0; 40])];
+        let (layout, _) = render(
+            &sources,
+            &crossfade(500.0, FadeCurve::EqualPower),
+            &GapSettings::default(),
+        );
+        assert_eq!(layout.overlaps, vec![0, 20]);
+        assert_eq!(layout.total, 120);
+    }
+
+    #[test]
+    fn gaps_replace_crossfades() {
+        let sources = [source(vec![1.0; 100]), source(vec![0.5; 100])];
+        let gaps = GapSettings {
+            between: Some(GapLength::Milliseconds(10.0)),
+            before_first: Some(GapLength::Samples(5)),
+            after_last: Some(GapLength::Beats {
+                beats: 1.0,
+                bpm: 3000.0,
+            }),
+            clips: HashMap::new(),
+        };
+        let (layout, rendered) = render(&sources, &crossfade(20.0, FadeCurve::Linear), &gaps);
+        assert_eq!(layout.starts, vec![5, 115]);
+        assert_eq!(layout.overlaps, vec![0, 0]);
+        assert_eq!(layout.trailing_gap, 20);
+        assert_eq!(rendered.len(), 235);
+        assert!(rendered[..5].iter().all(|&sample| sample == 0.0));
+        assert!(rendered[105..115].iter().all(|&sample| sample == 0.0));
+        assert!(rendered[115..215].iter().all(|&sample| sample == 0.5));
+        assert!(rendered[215..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn trim_is_clamped_to_the_source() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let trim = Trim {
+            start: 0.01,
+            end: Some(0.02),
+        };
+        assert_eq!(trim.apply(&samples, &FORMAT), &samples[10..20]);
+        let past_end = Trim {
+            start: 0.5,
+            end: Some(0.2),
+        };
+        assert!(past_end.apply(&samples, &FORMAT).is_empty());
+    }
+
+    // Renders `main` on the main lane with `overlay` on a second lane from its first sample
+    fn mix(main: Vec<f32>, overlay: Option<Vec<f32>>) -> Vec<f32> {
+        let lanes = [
+            Lane::main(),
+            Lane {
+                id: Uuid::new_v4(),
+                ..Lane::main()
+            },
+        ];
+        let mut sources = vec![source(main)];
+        sources.extend(overlay.map(source));
+        let mut clips: Vec<Clip> = sources.iter().map(Clip::for_source).collect();
+        if let Some(clip) = clips.get_mut(1) {
+            clip.lane = lanes[1].id;
+        }
+        let views: Vec<ClipView> = clips
+            .iter()
+            .zip(&sources)
+            .map(|(clip, source)| ClipView::new(clip, source, false))
+            .collect();
+        let rendered = render_to_store(
+            &views,
+            &lanes,
+            &CrossfadeSettings::default(),
+            &GapSettings::default(),
+            &FORMAT,
+            &SampleStore::new(),
+        )
+        .unwrap();
+        rendered.to_vec()
+    }
+
+    #[test]
+    fn a_single_lane_under_full_scale_is_untouched() {
+        let main: Vec<f32> = (0..500)
+            .map(|i| if i % 2 == 0 { 0.95 } else { -0.95 })
+            .collect();
+        assert_eq!(mix(main.clone(), None), main);
+    }
+
+    #[test]
+    fn overlapping_lanes_are_limited_and_recover() {
+        let rendered = mix(vec![0.8; 1000], Some(vec![0.8; 100]));
+        assert!(rendered.iter().all(|sample| sample.abs() <= 1.0));
+        // Held at full scale while the lanes overlap, not squashed further
+        assert!((rendered[50] - 1.0).abs() < 1e-6);
+        // Back to the main lane as it is once the release is over
+        assert!(rendered[100..].iter().all(|&sample| sample <= 0.8));
+        assert!(rendered[600..].iter().all(|&sample| sample == 0.8));
+    }
+}
@@ -0,0 +1,432 @@
+use rubato::{
+    Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+};
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::sample::SampleFormat;
+
+use crate::error::Error;
+
+// Frames fed to the sinc resampler per call
+const RESAMPLE_CHUNK: usize = 1024;
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Sample rate and channel layout every decoded file is converted to on ingest.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for ProjectFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+}
+
+/// How a lossless source stored its samples. Lossy codecs have no meaningful depth.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "format", content = "bits")]
+pub enum SampleDepth {
+    Int(u32),
+    Float(u32),
+}
+
+impl SampleDepth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            SampleDepth::Int(bits) | SampleDepth::Float(bits) => *bits,
+        }
+    }
+
+    /// Reads the stored depth from a track's codec parameters.
+    pub fn from_codec_params(params: &CodecParameters) -> Option<SampleDepth> {
+        match (params.sample_format, params.bits_per_sample) {
+            (Some(SampleFormat::F32), _) => Some(SampleDepth::Float(32)),
+            (Some(SampleFormat::F64), _) => Some(SampleDepth::Float(64)),
+            (_, Some(bits)) => Some(SampleDepth::Int(bits)),
+            // Lossy decoders don't report one
+            (_, None) => None,
+        }
+    }
+
+    /// The smallest depth that loses nothing from any of `depths`, 16-bit if none are known.
+    pub fn widest(depths: impl IntoIterator<Item = Option<SampleDepth>>) -> SampleDepth {
+        depths
+            .into_iter()
+            .flatten()
+            .fold(SampleDepth::Int(16), |widest, depth| {
+                match (widest, depth) {
+                    (SampleDepth::Float(_), _) | (_, SampleDepth::Float(_)) => {
+                        SampleDepth::Float(32)
+                    }
+                    (SampleDepth::Int(a), SampleDepth::Int(b)) => {
+                        SampleDepth::Int(a.max(b).min(32))
+                    }
+                }
+            })
+    }
+}
+
+/// The spec a file was decoded with, before conversion to the project format.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub channel_mask: u32,
+    pub depth: Option<SampleDepth>,
+}
+
+impl SourceSpec {
+    pub fn layout(&self) -> Channels {
+        Channels::from_bits_truncate(self.channel_mask)
+    }
+}
+
+/// Converts interleaved samples in `spec` to the project sample rate and channel layout.
+pub fn convert_to_project(
+    samples: Vec<f32>,
+    spec: &SourceSpec,
+    target: &ProjectFormat,
+) -> Result<Vec<f32>, Error> {
+    let remixed = remix(samples, spec, target.channels);
+    resample(
+        remixed,
+        target.channels,
+        spec.sample_rate,
+        target.sample_rate,
+    )
+}
+
+/// Maps interleaved samples from the source layout onto `out_channels` channels.
+///
+/// Mono is duplicated to every output channel, anything wider is folded down to
+/// stereo first (ITU-R BS.775 coefficients, LFE dropped) and then to mono if needed.
+pub fn remix(samples: Vec<f32>, spec: &SourceSpec, out_channels: u16) -> Vec<f32> {
+    let in_channels = spec.channels.max(1) as usize;
+    let out_channels = out_channels.max(1) as usize;
+
+    if in_channels == out_channels {
+        return samples;
+    }
+
+    let frames = samples.len() / in_channels;
+    let mut out = Vec::with_capacity(frames * out_channels);
+
+    if in_channels == 1 {
+        for &s in &samples {
+            out.extend(std::iter::repeat_n(s, out_channels));
+        }
+        return out;
+    }
+
+    let weights = stereo_downmix_weights(spec);
+    for frame in samples.chunks_exact(in_channels) {
+        let (mut left, mut right) = (0.0, 0.0);
+        for (sample, (wl, wr)) in frame.iter().zip(&weights) {
+            left += sample * wl;
+            right += sample * wr;
+        }
+
+        match out_channels {
+            1 => out.push((left + right) * 0.5),
+            2 => {
+                out.push(left);
+                out.push(right);
+            }
+            n => {
+                // Wider targets get the stereo pair up front and silence elsewhere
+                out.push(left);
+                out.push(right);
+                out.extend(std::iter::repeat_n(0.0, n - 2));
+            }
+        }
+    }
+
+    out
+}
+
+// Per input channel (left, right) gains, normalised so a full-scale signal on
+// every channel cannot exceed full scale after the fold-down.
+fn stereo_downmix_weights(spec: &SourceSpec) -> Vec<(f32, f32)> {
+    let layout = spec.layout();
+    let mut weights: Vec<(f32, f32)> = if layout.count() == spec.channels as usize {
+        layout
+            .iter()
+            .map(|channel| {
+                if channel == Channels::FRONT_LEFT {
+                    (1.0, 0.0)
+                } else if channel == Channels::FRONT_RIGHT {
+                    (0.0, 1.0)
+                } else if channel == Channels::FRONT_CENTRE
+                    || channel == Channels::REAR_CENTRE
+                    || channel == Channels::TOP_CENTRE
+                    || channel == Channels::TOP_FRONT_CENTRE
+                    || channel == Channels::TOP_REAR_CENTRE
+                    || channel == Channels::FRONT_CENTRE_HIGH
+                {
+                    (MINUS_3DB, MINUS_3DB)
+                } else if channel == Channels::LFE1 || channel == Channels::LFE2 {
+                    (0.0, 0.0)
+                } else if (channel.bits() & LEFT_CHANNELS) != 0 {
+                    (MINUS_3DB, 0.0)
+                } else {
+                    (0.0, MINUS_3DB)
+                }
+            })
+            .collect()
+    } else {
+        // Unknown layout: alternate channels between left and right
+        (0..spec.channels)
+            .map(|i| if i % 2 == 0 { (1.0, 0.0) } else { (0.0, 1.0) })
+            .collect()
+    };
+
+    let left_sum: f32 = weights.iter().map(|(l, _)| l).sum();
+    let right_sum: f32 = weights.iter().map(|(_, r)| r).sum();
+    let norm = left_sum.max(right_sum).max(1.0);
+    for (l, r) in weights.iter_mut() {
+        *l /= norm;
+        *r /= norm;
+    }
+    weights
+}
+
+const LEFT_CHANNELS: u32 = Channels::REAR_LEFT.bits()
+    | Channels::FRONT_LEFT_CENTRE.bits()
+    | Channels::SIDE_LEFT.bits()
+    | Channels::TOP_FRONT_LEFT.bits()
+    | Channels::TOP_REAR_LEFT.bits()
+    | Channels::REAR_LEFT_CENTRE.bits()
+    | Channels::FRONT_LEFT_WIDE.bits()
+    | Channels::FRONT_LEFT_HIGH.bits();
+
+/// Band-limited sinc resampling of interleaved samples from `from_rate` to `to_rate`.
+pub fn resample(
+    samples: Vec<f32>,
+    channels: u16,
+    from_rate: u32,
+    to_rate: u32,
+) -> Result<Vec<f32>, Error> {
+    if from_rate == to_rate || from_rate == 0 || samples.is_empty() {
+        return Ok(samples);
+    }
+    let mut resampled =
+        Vec::with_capacity(resampled_len(samples.len(), channels, from_rate, to_rate));
+    resample_into(&samples, channels, from_rate, to_rate, |block| {
+        resampled.extend_from_slice(block);
+        Ok(())
+    })?;
+    Ok(resampled)
+}
+
+/// Number of interleaved samples `resample_into` produces for `len` input samples.
+pub fn resampled_len(len: usize, channels: u16, from_rate: u32, to_rate: u32) -> usize {
+    if from_rate == to_rate || from_rate == 0 {
+        return len;
+    }
+    let channels = channels.max(1) as usize;
+    let frames = len / channels;
+    (frames as f64 * to_rate as f64 / from_rate as f64).round() as usize * channels
+}
+
+/// Resamples like `resample`, handing the interleaved output to `out` one chunk at a time
+/// so neither the input nor the output has to be copied whole.
+pub fn resample_into(
+    samples: &[f32],
+    channels: u16,
+    from_rate: u32,
+    to_rate: u32,
+    mut out: impl FnMut(&[f32]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let channels = channels.max(1) as usize;
+    if from_rate == to_rate || from_rate == 0 || samples.is_empty() {
+        return out(samples);
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let params = SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        interpolation: SincInterpolationType::Cubic,
+        oversampling_factor: 256,
+        window: WindowFunction::BlackmanHarris2,
+    };
+    let mut resampler = SincFixedIn::<f32>::new(ratio, 1.0, params, RESAMPLE_CHUNK, channels)
+        .map_err(|e| Error::Resample(e.to_string()))?;
+
+    // SincFixedIn starts half a filter length early, so its output is already aligned with
+    // the input and only needs flushing and truncating to the expected length
+    let frames = samples.len() / channels;
+    let expected_frames = (frames as f64 * ratio).round() as usize;
+    let mut emitter = Emitter {
+        remaining: expected_frames,
+        interleaved: Vec::new(),
+    };
+    let mut planar: Vec<Vec<f32>> = vec![Vec::with_capacity(RESAMPLE_CHUNK); channels];
+
+    let mut pos = 0;
+    while pos + RESAMPLE_CHUNK <= frames {
+        deinterleave(
+            &samples[pos * channels..(pos + RESAMPLE_CHUNK) * channels],
+            &mut planar,
+        );
+        let block = resampler
+            .process(&planar, None)
+            .map_err(|e| Error::Resample(e.to_string()))?;
+        emitter.emit(&block, &mut out)?;
+        pos += RESAMPLE_CHUNK;
+    }
+
+    // Remaining input, then flush until the filter tail has been pushed out
+    deinterleave(&samples[pos * channels..frames * channels], &mut planar);
+    let block = resampler
+        .process_partial(Some(&planar), None)
+        .map_err(|e| Error::Resample(e.to_string()))?;
+    emitter.emit(&block, &mut out)?;
+    while emitter.remaining > 0 {
+        let block = resampler
+            .process_partial::<&[f32]>(None, None)
+            .map_err(|e| Error::Resample(e.to_string()))?;
+        if block[0].is_empty() {
+            break;
+        }
+        emitter.emit(&block, &mut out)?;
+    }
+    Ok(())
+}
+
+fn deinterleave(samples: &[f32], planar: &mut [Vec<f32>]) {
+    let channels = planar.len();
+    for ch in planar.iter_mut() {
+        ch.clear();
+    }
+    for frame in samples.chunks_exact(channels) {
+        for (ch, &s) in frame.iter().enumerate() {
+            planar[ch].push(s);
+        }
+    }
+}
+
+// Interleaves resampled blocks into a reused buffer, dropping anything past the expected length
+struct Emitter {
+    remaining: usize,
+    interleaved: Vec<f32>,
+}
+
+impl Emitter {
+    fn emit(
+        &mut self,
+        block: &[Vec<f32>],
+        out: &mut impl FnMut(&[f32]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let available = block[0].len().min(self.remaining);
+        if available == 0 {
+            return Ok(());
+        }
+        self.interleaved.clear();
+        for i in 0..available {
+            for ch in block {
+                self.interleaved.push(ch[i]);
+            }
+        }
+        self.remaining -= available;
+        out(&self.interleaved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(channels: u16, channel_mask: u32) -> SourceSpec {
+        SourceSpec {
+            sample_rate: 44100,
+            channels,
+            channel_mask,
+            depth: None,
+        }
+    }
+
+    fn sine(frequency: f32, sample_rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn remix_duplicates_mono() {
+        let stereo = remix(vec![0.1, 0.2], &spec(1, 0), 2);
+        assert_eq!(stereo, vec![0.1, 0.1, 0.2, 0.2]);
+    }
+
+    #[test]
+    fn remix_folds_stereo_to_mono() {
+        let stereo = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+        let mono = remix(vec![1.0, 0.0, 0.5, 0.5], &spec(2, stereo.bits()), 1);
+        assert_eq!(mono, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn remix_folds_surround_without_clipping_or_lfe() {
+        let layout = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let spec = spec(6, layout.bits());
+        let full = remix(vec![1.0; 6], &spec, 2);
+        assert!((full[0] - 1.0).abs() < 1e-6 && (full[1] - 1.0).abs() < 1e-6);
+        let lfe_only = remix(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0], &spec, 2);
+        assert_eq!(lfe_only, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn resample_keeps_matching_rates() {
+        let samples = vec![0.25; 100];
+        assert_eq!(resample(samples.clone(), 2, 48000, 48000).unwrap(), samples);
+    }
+
+    #[test]
+    fn resample_scales_length_and_keeps_pitch() {
+        let frames = 44100;
+        let input = sine(441.0, 44100, frames);
+        let output = resample(input, 1, 44100, 48000).unwrap();
+        assert_eq!(output.len(), resampled_len(frames, 1, 44100, 48000));
+        assert_eq!(output.len(), 48000);
+
+        // Still a full-scale 441 Hz sine once the filter has settled
+        let expected = sine(441.0, 48000, 48000);
+        let error = output[1000..47000]
+            .iter()
+            .zip(&expected[1000..47000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0f32, f32::max);
+        assert!(error < 0.01, "largest error {}", error);
+    }
+
+    #[test]
+    fn resample_into_streams_the_same_samples() {
+        let input: Vec<f32> = sine(1000.0, 48000, 5000)
+            .into_iter()
+            .flat_map(|s| [s, -s])
+            .collect();
+        let whole = resample(input.clone(), 2, 48000, 44100).unwrap();
+        let mut streamed = Vec::new();
+        resample_into(&input, 2, 48000, 44100, |block| {
+            assert_eq!(block.len() % 2, 0);
+            streamed.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(streamed, whole);
+        assert_eq!(whole.len(), resampled_len(input.len(), 2, 48000, 44100));
+    }
+}
@@ -0,0 +1,485 @@
+use memmap2::Mmap;
+use rodio::Source;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::State;
+
+use crate::error::Error;
+use crate::state::AppState;
+
+const DEFAULT_BUDGET_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+const SAMPLE_BYTES: u64 = std::mem::size_of::<f32>() as u64;
+
+struct StoreShared {
+    budget_bytes: AtomicU64,
+    resident_bytes: AtomicU64,
+    spilled_bytes: AtomicU64,
+    next_id: AtomicU64,
+    spill_dir: Mutex<Option<PathBuf>>,
+}
+
+impl StoreShared {
+    // Claims `bytes` of the RAM budget, or returns false if they should go to disk instead
+    fn try_reserve(&self, bytes: u64) -> bool {
+        if self
+            .spill_dir
+            .lock()
+            .map(|dir| dir.is_none())
+            .unwrap_or(true)
+        {
+            // Nowhere to spill to, so everything stays in memory
+            self.resident_bytes.fetch_add(bytes, Ordering::SeqCst);
+            return true;
+        }
+        let budget = self.budget_bytes.load(Ordering::SeqCst);
+        self.resident_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |resident| {
+                (resident + bytes <= budget).then_some(resident + bytes)
+            })
+            .is_ok()
+    }
+
+    fn spill_path(&self) -> Result<PathBuf, Error> {
+        let dir = self.spill_dir.lock().map_err(|_| Error::LockPoisoned)?;
+        let dir = dir.as_ref().ok_or(Error::NoSpillDirectory)?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        Ok(dir.join(format!("{}-{}.pcm", std::process::id(), id)))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleStoreInfo {
+    pub spill_dir: Option<String>,
+    pub budget_bytes: u64,
+    pub resident_bytes: u64,
+    pub spilled_bytes: u64,
+}
+
+/// Owns every decoded and combined sample buffer in the session.
+///
+/// Buffers stay in memory while they fit in the RAM budget, anything past it is written
+/// to a temp file and memory-mapped so the OS can page it in and out as it's read.
+pub struct SampleStore {
+    shared: Arc<StoreShared>,
+}
+
+impl SampleStore {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(StoreShared {
+                budget_bytes: AtomicU64::new(DEFAULT_BUDGET_BYTES),
+                resident_bytes: AtomicU64::new(0),
+                spilled_bytes: AtomicU64::new(0),
+                next_id: AtomicU64::new(0),
+                spill_dir: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Spills buffers over the budget into `dir`. Until this is called everything stays in RAM.
+    pub fn open(&self, dir: PathBuf) -> Result<(), Error> {
+        fs::create_dir_all(&dir)?;
+        // Spill files never outlive the session that made them, clear out any a crash left behind
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pcm") {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        *self
+            .shared
+            .spill_dir
+            .lock()
+            .map_err(|_| Error::LockPoisoned)? = Some(dir);
+        Ok(())
+    }
+
+    /// Takes ownership of `samples`, moving them to disk if they don't fit in the budget.
+    pub fn insert(&self, samples: Vec<f32>) -> Result<Samples, Error> {
+        let bytes = samples.len() as u64 * SAMPLE_BYTES;
+        if self.shared.try_reserve(bytes) {
+            // The buffer is kept as it is, the reservation becomes its resident size
+            return Ok(Samples {
+                inner: Arc::new(SamplesInner {
+                    storage: Storage::Memory(samples),
+                    shared: Arc::clone(&self.shared),
+                }),
+            });
+        }
+        let mut writer = self.spill_writer()?;
+        writer.extend_from_slice(&samples)?;
+        drop(samples);
+        writer.finish()
+    }
+
+    /// A writer for a buffer of roughly `expected_len` samples, built up piece by piece so
+    /// large buffers go straight to disk without ever being held in memory.
+    pub fn writer(&self, expected_len: usize) -> Result<SamplesWriter, Error> {
+        let bytes = expected_len as u64 * SAMPLE_BYTES;
+        if !self.shared.try_reserve(bytes) {
+            return self.spill_writer();
+        }
+        Ok(SamplesWriter {
+            target: Some(WriterTarget::Memory {
+                samples: Vec::with_capacity(expected_len),
+                reserved: bytes,
+            }),
+            len: 0,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    fn spill_writer(&self) -> Result<SamplesWriter, Error> {
+        let path = self.shared.spill_path()?;
+        let file = File::create(&path)?;
+        Ok(SamplesWriter {
+            target: Some(WriterTarget::Spill {
+                writer: BufWriter::new(file),
+                path,
+            }),
+            len: 0,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    pub fn set_budget(&self, budget_bytes: u64) {
+        // Only applies to new buffers, existing ones stay where they are
+        self.shared
+            .budget_bytes
+            .store(budget_bytes, Ordering::SeqCst);
+    }
+
+    pub fn info(&self) -> Result<SampleStoreInfo, Error> {
+        let spill_dir = self
+            .shared
+            .spill_dir
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .as_ref()
+            .map(|dir| dir.to_string_lossy().to_string());
+        Ok(SampleStoreInfo {
+            spill_dir,
+            budget_bytes: self.shared.budget_bytes.load(Ordering::SeqCst),
+            resident_bytes: self.shared.resident_bytes.load(Ordering::SeqCst),
+            spilled_bytes: self.shared.spilled_bytes.load(Ordering::SeqCst),
+        })
+    }
+}
+
+impl Default for SampleStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum WriterTarget {
+    Memory {
+        samples: Vec<f32>,
+        reserved: u64,
+    },
+    Spill {
+        writer: BufWriter<File>,
+        path: PathBuf,
+    },
+}
+
+pub struct SamplesWriter {
+    // Taken by finish(), anything left here when dropped is released
+    target: Option<WriterTarget>,
+    len: usize,
+    shared: Arc<StoreShared>,
+}
+
+impl SamplesWriter {
+    pub fn extend_from_slice(&mut self, samples: &[f32]) -> Result<(), Error> {
+        match &mut self.target {
+            Some(WriterTarget::Memory { samples: buf, .. }) => buf.extend_from_slice(samples),
+            Some(WriterTarget::Spill { writer, .. }) => {
+                // Native byte order, the file is only ever read back by this process
+                for sample in samples {
+                    writer.write_all(&sample.to_ne_bytes())?;
+                }
+            }
+            None => {}
+        }
+        self.len += samples.len();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Samples, Error> {
+        let shared = Arc::clone(&self.shared);
+        let len = self.len;
+
+        let storage = match self.target.take() {
+            Some(WriterTarget::Memory {
+                mut samples,
+                reserved,
+            }) => {
+                // Settle the reservation to what was actually written
+                samples.shrink_to_fit();
+                let bytes = samples.len() as u64 * SAMPLE_BYTES;
+                shared.resident_bytes.fetch_add(bytes, Ordering::SeqCst);
+                shared.resident_bytes.fetch_sub(reserved, Ordering::SeqCst);
+                Storage::Memory(samples)
+            }
+            Some(WriterTarget::Spill { writer, path }) => {
+                let file = writer.into_inner().map_err(|e| e.into_error());
+                drop(file?);
+                if len == 0 {
+                    // Empty files can't be mapped on every platform
+                    let _ = fs::remove_file(&path);
+                    Storage::Memory(Vec::new())
+                } else {
+                    let file = File::open(&path)?;
+                    // Safety: the file is private to this store and is never written again
+                    let map = unsafe { Mmap::map(&file)? };
+                    shared
+                        .spilled_bytes
+                        .fetch_add(len as u64 * SAMPLE_BYTES, Ordering::SeqCst);
+                    Storage::Mapped { map, path, len }
+                }
+            }
+            None => Storage::Memory(Vec::new()),
+        };
+
+        Ok(Samples {
+            inner: Arc::new(SamplesInner { storage, shared }),
+        })
+    }
+}
+
+impl Drop for SamplesWriter {
+    fn drop(&mut self) {
+        match self.target.take() {
+            Some(WriterTarget::Memory { reserved, .. }) => {
+                self.shared
+                    .resident_bytes
+                    .fetch_sub(reserved, Ordering::SeqCst);
+            }
+            Some(WriterTarget::Spill { writer, path }) => {
+                drop(writer);
+                let _ = fs::remove_file(path);
+            }
+            None => {}
+        }
+    }
+}
+
+enum Storage {
+    Memory(Vec<f32>),
+    Mapped {
+        map: Mmap,
+        path: PathBuf,
+        len: usize,
+    },
+}
+
+struct SamplesInner {
+    storage: Storage,
+    shared: Arc<StoreShared>,
+}
+
+impl Drop for SamplesInner {
+    fn drop(&mut self) {
+        match &self.storage {
+            Storage::Memory(samples) => {
+                self.shared
+                    .resident_bytes
+                    .fetch_sub(samples.len() as u64 * SAMPLE_BYTES, Ordering::SeqCst);
+            }
+            Storage::Mapped { path, len, .. } => {
+                self.shared
+                    .spilled_bytes
+                    .fetch_sub(*len as u64 * SAMPLE_BYTES, Ordering::SeqCst);
+                // Unix keeps the mapping valid after unlinking, elsewhere this may fail
+                // while the map is open and the file is cleared on the next start instead
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// A shared, read-only sample buffer from the [`SampleStore`]. Cloning is cheap.
+#[derive(Clone)]
+pub struct Samples {
+    inner: Arc<SamplesInner>,
+}
+
+impl Samples {
+    pub fn as_slice(&self) -> &[f32] {
+        match &self.inner.storage {
+            Storage::Memory(samples) => samples,
+            Storage::Mapped { map, len, .. } => {
+                // Safety: the map is page aligned, exactly `len` samples long and read-only
+                unsafe { std::slice::from_raw_parts(map.as_ptr() as *const f32, *len) }
+            }
+        }
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.inner.storage, Storage::Mapped { .. })
+    }
+
+    /// Identifies the buffer behind these samples, the same for every clone of it.
+    pub fn buffer_id(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+}
+
+impl Deref for Samples {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        self.as_slice()
+    }
+}
+
+/// Streams a [`Samples`] buffer to rodio without copying it into a `SamplesBuffer` first.
+pub struct SamplesSource {
+    samples: Samples,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl SamplesSource {
+    pub fn new(samples: Samples, start: usize, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            samples,
+            position: start,
+            channels,
+            sample_rate,
+        }
+    }
+}
+
+impl Iterator for SamplesSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.samples.len().saturating_sub(self.position);
+        (remaining, Some(remaining))
+    }
+}
+
+impl Source for SamplesSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = self.samples.len() / self.channels.max(1) as usize;
+        Some(Duration::from_secs_f64(
+            frames as f64 / self.sample_rate.max(1) as f64,
+        ))
+    }
+}
+
+#[tauri::command]
+pub fn get_sample_store_info(state: State<'_, Arc<AppState>>) -> Result<SampleStoreInfo, Error> {
+    state.sample_store.info()
+}
+
+#[tauri::command]
+pub fn set_ram_budget(budget_bytes: u64, state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+    println!("🧠 RAM budget set to {} bytes", budget_bytes);
+    state.sample_store.set_budget(budget_bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn spill_files(dir: &TempDir) -> usize {
+        fs::read_dir(dir.path()).unwrap().count()
+    }
+
+    #[test]
+    fn buffers_stay_in_memory_without_a_spill_dir() {
+        let store = SampleStore::new();
+        store.set_budget(0);
+        let samples = store.insert(vec![0.5; 100]).unwrap();
+        assert!(!samples.is_spilled());
+        assert_eq!(store.info().unwrap().resident_bytes, 400);
+        drop(samples);
+        assert_eq!(store.info().unwrap().resident_bytes, 0);
+    }
+
+    #[test]
+    fn buffers_past_the_budget_are_spilled() {
+        let dir = TempDir::new("spill");
+        let store = SampleStore::new();
+        store.open(dir.path().to_path_buf()).unwrap();
+        store.set_budget(1000);
+
+        let resident = store.insert(vec![0.25; 200]).unwrap();
+        let spilled = store.insert((0..300).map(|i| i as f32).collect()).unwrap();
+        assert!(!resident.is_spilled());
+        assert!(spilled.is_spilled());
+        assert_eq!(spilled.len(), 300);
+        assert_eq!(spilled[299], 299.0);
+        let info = store.info().unwrap();
+        assert_eq!((info.resident_bytes, info.spilled_bytes), (800, 1200));
+        assert_eq!(spill_files(&dir), 1);
+
+        drop(spilled);
+        assert_eq!(store.info().unwrap().spilled_bytes, 0);
+        assert_eq!(spill_files(&dir), 0);
+    }
+
+    #[test]
+    fn writers_settle_or_release_their_reservation() {
+        let dir = TempDir::new("spill");
+        let store = SampleStore::new();
+        store.open(dir.path().to_path_buf()).unwrap();
+        store.set_budget(1000);
+
+        // Reserved for 100 samples, settled at the 50 written
+        let mut writer = store.writer(100).unwrap();
+        writer.extend_from_slice(&[1.0; 50]).unwrap();
+        assert_eq!(store.info().unwrap().resident_bytes, 400);
+        let samples = writer.finish().unwrap();
+        assert_eq!(store.info().unwrap().resident_bytes, 200);
+        assert_eq!(samples.as_slice(), &[1.0; 50]);
+
+        // Too big for what's left, so it streams to disk, and a dropped writer leaves nothing
+        let mut writer = store.writer(1000).unwrap();
+        writer.extend_from_slice(&[1.0; 10]).unwrap();
+        assert_eq!(spill_files(&dir), 1);
+        drop(writer);
+        assert_eq!(spill_files(&dir), 0);
+        assert_eq!(store.info().unwrap().resident_bytes, 200);
+    }
+
+    #[test]
+    fn opening_clears_spill_files_left_behind() {
+        let dir = TempDir::new("spill");
+        let leftover = dir.join("1234-0.pcm");
+        fs::write(&leftover, [0u8; 8]).unwrap();
+        SampleStore::new().open(dir.path().to_path_buf()).unwrap();
+        assert!(!leftover.exists());
+    }
+}
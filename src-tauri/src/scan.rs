@@ -0,0 +1,617 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadBytes, SeekBuffered};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::{Descriptor, Instantiate, QueryDescriptor};
+use symphonia::default::formats::{
+    AdtsReader, AiffReader, CafReader, FlacReader, IsoMp4Reader, MkvReader, MpaReader, OggReader,
+    WavReader,
+};
+use symphonia::default::{get_codecs, get_probe};
+
+use crate::archive::{buffer_entry, entry_folder, entry_path, is_archive, visit_entries};
+use crate::error::Error;
+use crate::metadata::format_duration;
+use crate::source::{list_audio_tracks, select_track, AudioTrackInfo};
+
+// Deep enough for Kicks/Acoustic/Dry/… style libraries without walking a whole drive
+const DEFAULT_MAX_DEPTH: usize = 8;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScanOptions {
+    pub recursive: bool,
+    // Levels below the selected folder to descend into, None for no limit
+    pub max_depth: Option<usize>,
+    // Globs matched against the path relative to the selected folder
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // Seconds, checked with the same probe used for playback progress
+    pub min_duration: Option<f32>,
+    pub max_duration: Option<f32>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_size: None,
+            max_size: None,
+            min_duration: None,
+            max_duration: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    tag = "status"
+)]
+pub enum Detection {
+    Supported {
+        container: String,
+        // Codec of the track imported when no track is chosen
+        codec: String,
+        tracks: Vec<AudioTrackInfo>,
+    },
+    Unsupported {
+        reason: String,
+    },
+}
+
+impl Detection {
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Detection::Supported { .. })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScannedFile {
+    pub path: String,
+    #[serde(flatten)]
+    pub detection: Detection,
+}
+
+struct Filters<'a> {
+    options: &'a ScanOptions,
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| Error::InvalidPattern(e.to_string()))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::InvalidPattern(e.to_string()))
+}
+
+/// Probes every file under `folder_path`, grouped by the sub-folder it lives in.
+pub fn scan_folder(
+    folder_path: &str,
+    options: &ScanOptions,
+) -> Result<BTreeMap<String, Vec<ScannedFile>>, Error> {
+    let filters = Filters {
+        options,
+        include: build_glob_set(&options.include)?,
+        exclude: build_glob_set(&options.exclude)?,
+    };
+
+    let root = Path::new(folder_path);
+    let mut groups: BTreeMap<String, Vec<ScannedFile>> = BTreeMap::new();
+    if is_archive(root) {
+        scan_archive(root, root, 0, &filters, &mut groups)?;
+    } else {
+        scan_dir(root, root, 0, &filters, &mut groups)?;
+    }
+
+    for files in groups.values_mut() {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    Ok(groups)
+}
+
+fn scan_dir(
+    root: &Path,
+    dir: &Path,
+    depth: usize,
+    filters: &Filters,
+    groups: &mut BTreeMap<String, Vec<ScannedFile>>,
+) -> Result<(), Error> {
+    let options = filters.options;
+    let mut valid_files = Vec::new();
+    let mut sub_dirs = Vec::new();
+    let mut archives = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        // Skip metadata files like "._track.mp3"
+        if file_name.starts_with("._") {
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if filters.exclude.is_match(relative) {
+            continue;
+        }
+
+        // file_type() does not follow symlinks, which keeps linked folders from looping
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // Hidden folders like .git or .Trashes never hold a library's samples
+            if file_name.starts_with('.') {
+                continue;
+            }
+            if options.recursive && options.max_depth.is_none_or(|max| depth < max) {
+                sub_dirs.push(path);
+            }
+            continue;
+        }
+
+        if !path.is_file() {
+            continue;
+        }
+
+        // Archives are browsed like the sub-folders they'd unpack to
+        if is_archive(&path) {
+            if options.recursive && options.max_depth.is_none_or(|max| depth < max) {
+                archives.push(path);
+            }
+            continue;
+        }
+
+        let matches = filters.include.is_empty() || filters.include.is_match(relative);
+        if !matches || !passes_size_filter(&path, options)? {
+            continue;
+        }
+
+        // The file's contents decide whether it's audio, not its extension
+        let path_str = path.to_str().ok_or(Error::InvalidPath)?;
+        let (detection, duration) = probe_file(&path);
+        if detection.is_supported() && !passes_duration_filter(duration, options) {
+            continue;
+        }
+        valid_files.push(ScannedFile {
+            path: path_str.to_string(),
+            detection,
+        });
+    }
+
+    if !valid_files.is_empty() {
+        let dir_str = dir.to_str().ok_or(Error::InvalidPath)?;
+        println!("{}: {} files", dir_str, valid_files.len());
+        groups.insert(dir_str.to_string(), valid_files);
+    }
+
+    for sub_dir in sub_dirs {
+        scan_dir(root, &sub_dir, depth + 1, filters, groups)?;
+    }
+    for archive in archives {
+        // One unreadable archive shouldn't stop the rest of the folder from importing
+        if let Err(e) = scan_archive(root, &archive, depth + 1, filters, groups) {
+            eprintln!("⚠️ Skipping archive {}: {}", archive.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+// Lists an archive's entries like a folder tree, grouped by the folder inside the archive
+fn scan_archive(
+    root: &Path,
+    archive: &Path,
+    depth: usize,
+    filters: &Filters,
+    groups: &mut BTreeMap<String, Vec<ScannedFile>>,
+) -> Result<(), Error> {
+    let options = filters.options;
+    let archive_str = archive.to_str().ok_or(Error::InvalidPath)?;
+    let relative_archive = archive.strip_prefix(root).unwrap_or(Path::new(""));
+    let mut found: BTreeMap<String, Vec<ScannedFile>> = BTreeMap::new();
+
+    visit_entries(archive_str, |name, size, reader| {
+        let (folder, _) = name.rsplit_once('/').unwrap_or(("", name));
+        let nesting = if folder.is_empty() {
+            0
+        } else {
+            folder.split('/').count()
+        };
+        if options.max_depth.is_some_and(|max| depth + nesting > max) {
+            return Ok(());
+        }
+
+        let relative = relative_archive.join(name);
+        if filters.exclude.is_match(&relative)
+            || !(filters.include.is_empty() || filters.include.is_match(&relative))
+        {
+            return Ok(());
+        }
+        if options.min_size.is_some_and(|min| size < min)
+            || options.max_size.is_some_and(|max| size > max)
+        {
+            return Ok(());
+        }
+
+        let path = entry_path(archive_str, name);
+        let (detection, duration) = probe_entry(&path, size, reader);
+        if detection.is_supported() && !passes_duration_filter(duration, options) {
+            return Ok(());
+        }
+
+        found
+            .entry(entry_folder(archive_str, name))
+            .or_default()
+            .push(ScannedFile { path, detection });
+        Ok(())
+    })?;
+
+    for (folder, files) in found {
+        println!("{}: {} files", folder, files.len());
+        groups.entry(folder).or_default().extend(files);
+    }
+    Ok(())
+}
+
+/// Detects the format of an archive entry being read, and measures how long it is.
+///
+/// Measured from the same copy, so the entry isn't decompressed a second time. Big entries go
+/// to a spool file rather than memory, and ones too big to import are listed as such.
+pub fn probe_entry(
+    path: &str,
+    size: u64,
+    reader: &mut dyn std::io::Read,
+) -> (Detection, Option<f32>) {
+    match buffer_entry(path, size, reader) {
+        Ok(data) => probe_media(Box::new(data), path),
+        Err(e) => (
+            Detection::Unsupported {
+                reason: e.to_string(),
+            },
+            None,
+        ),
+    }
+}
+
+/// Asks the symphonia probe which container `path` holds and whether its audio can be decoded.
+pub fn detect_format(path: &Path) -> Detection {
+    probe_file(path).0
+}
+
+fn probe_file(path: &Path) -> (Detection, Option<f32>) {
+    match File::open(path) {
+        Ok(file) => probe_media(Box::new(file), &path.to_string_lossy()),
+        Err(e) => (
+            Detection::Unsupported {
+                reason: e.to_string(),
+            },
+            None,
+        ),
+    }
+}
+
+// The detection and the length in seconds, from one pass over the container.
+// `path` is only used to name the tracks, the contents come from `source`
+fn probe_media(source: Box<dyn MediaSource>, path: &str) -> (Detection, Option<f32>) {
+    let unsupported = |reason: String| (Detection::Unsupported { reason }, None);
+    let mut mss = MediaSourceStream::new(source, Default::default());
+
+    // Same loop as Probe::format, done by hand so we know which reader matched
+    let probe = get_probe();
+    let (container, format) = loop {
+        match probe.next(&mut mss) {
+            Ok(Instantiate::Format(instantiate)) => {
+                let container = container_name(&mut mss);
+                match instantiate(mss, &FormatOptions::default()) {
+                    Ok(format) => break (container, format),
+                    Err(e) => {
+                        return unsupported(format!("Unreadable {} container: {}", container, e))
+                    }
+                }
+            }
+            Ok(Instantiate::Metadata(instantiate)) => {
+                let mut reader = instantiate(&MetadataOptions::default());
+                if let Err(e) = reader.read_all(&mut mss) {
+                    return unsupported(format!("Unreadable metadata: {}", e));
+                }
+            }
+            Err(_) => return unsupported("Not a recognised audio container".to_string()),
+        }
+    };
+
+    (
+        describe_audio_tracks(path, container, format.as_ref()),
+        format_duration(format.as_ref()),
+    )
+}
+
+fn describe_audio_tracks(path: &str, container: &str, format: &dyn FormatReader) -> Detection {
+    let Ok(track) = select_track(format, None) else {
+        return Detection::Unsupported {
+            reason: format!("No decodable audio track in {} container", container),
+        };
+    };
+
+    match get_codecs().get_codec(track.codec_params.codec) {
+        Some(descriptor) => Detection::Supported {
+            container: container.to_string(),
+            codec: descriptor.short_name.to_string(),
+            tracks: list_audio_tracks(path, format),
+        },
+        None => Detection::Unsupported {
+            reason: format!("No decoder for the codec in this {} file", container),
+        },
+    }
+}
+
+// The probe leaves the stream at the start of the marker it matched, so reading the marker
+// back finds the descriptor, and with it the name, of the reader it picked
+fn container_name(mss: &mut MediaSourceStream) -> &'static str {
+    let mut context = [0u8; 16];
+    if mss.read_buf_exact(&mut context).is_err() {
+        return "unknown";
+    }
+    mss.seek_buffered_rev(context.len());
+
+    let descriptors: [&[Descriptor]; 9] = [
+        WavReader::query(),
+        AiffReader::query(),
+        FlacReader::query(),
+        MpaReader::query(),
+        AdtsReader::query(),
+        OggReader::query(),
+        IsoMp4Reader::query(),
+        MkvReader::query(),
+        CafReader::query(),
+    ];
+
+    descriptors
+        .iter()
+        .flat_map(|descriptors| descriptors.iter())
+        .filter(|descriptor| matches!(descriptor.inst, Instantiate::Format(_)))
+        .find(|descriptor| {
+            descriptor
+                .markers
+                .iter()
+                .any(|marker| context.starts_with(marker))
+        })
+        .map(|descriptor| descriptor.short_name)
+        .unwrap_or("unknown")
+}
+
+fn passes_size_filter(path: &Path, options: &ScanOptions) -> Result<bool, Error> {
+    if options.min_size.is_none() && options.max_size.is_none() {
+        return Ok(true);
+    }
+    let size = fs::metadata(path)?.len();
+    Ok(options.min_size.is_none_or(|min| size >= min)
+        && options.max_size.is_none_or(|max| size <= max))
+}
+
+fn passes_duration_filter(duration: Option<f32>, options: &ScanOptions) -> bool {
+    // Files the probe can't measure are kept rather than silently dropped
+    let Some(duration) = duration else {
+        return true;
+    };
+    options.min_duration.is_none_or(|min| duration >= min)
+        && options.max_duration.is_none_or(|max| duration <= max)
+}
+
+#[tauri::command]
+pub async fn scan_folders(
+    folder_paths: Vec<String>,
+    options: Option<ScanOptions>,
+) -> Result<BTreeMap<String, Vec<ScannedFile>>, Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let options = options.unwrap_or_default();
+        let mut all_files: BTreeMap<String, Vec<ScannedFile>> = BTreeMap::new();
+        for folder_path in folder_paths {
+            all_files.extend(scan_folder(&folder_path, &options)?);
+        }
+        Ok(all_files)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_wav, TempDir};
+
+    fn scan(dir: &TempDir, options: &ScanOptions) -> BTreeMap<String, Vec<String>> {
+        let root = dir.path().to_str().unwrap();
+        scan_folder(root, options)
+            .unwrap()
+            .into_iter()
+            .map(|(folder, files)| {
+                let relative = |path: &str| path[root.len()..].trim_start_matches('/').to_string();
+                (
+                    relative(&folder),
+                    files.iter().map(|file| relative(&file.path)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn library() -> TempDir {
+        let dir = TempDir::new("scan");
+        write_wav(&dir.join("top.wav"), 8000, 1, 800);
+        write_wav(&dir.join("Kicks/kick.wav"), 8000, 1, 800);
+        write_wav(&dir.join("Kicks/Acoustic/Dry/dry.wav"), 8000, 1, 8000);
+        dir
+    }
+
+    #[test]
+    fn default_scan_descends_and_groups_by_folder() {
+        let dir = library();
+        let groups = scan(&dir, &ScanOptions::default());
+        assert_eq!(groups[""], vec!["top.wav"]);
+        assert_eq!(groups["Kicks"], vec!["Kicks/kick.wav"]);
+        assert_eq!(
+            groups["Kicks/Acoustic/Dry"],
+            vec!["Kicks/Acoustic/Dry/dry.wav"]
+        );
+    }
+
+    #[test]
+    fn depth_limits_how_far_the_scan_goes() {
+        let dir = library();
+        let top_only = ScanOptions {
+            recursive: false,
+            ..Default::default()
+        };
+        assert_eq!(scan(&dir, &top_only).len(), 1);
+        let one_level = ScanOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let groups = scan(&dir, &one_level);
+        assert!(groups.contains_key("Kicks"));
+        assert!(!groups.contains_key("Kicks/Acoustic/Dry"));
+    }
+
+    #[test]
+    fn archives_are_scanned_like_folders() {
+        let dir = TempDir::new("scan");
+        let wav = dir.join("kick.wav");
+        write_wav(&wav, 8000, 1, 800);
+        let mut zip = zip::ZipWriter::new(fs::File::create(dir.join("pack.zip")).unwrap());
+        for name in ["kick.wav", "Kicks/kick.wav", "notes.txt"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            let contents = if name.ends_with(".wav") {
+                fs::read(&wav).unwrap()
+            } else {
+                b"not audio".to_vec()
+            };
+            std::io::Write::write_all(&mut zip, &contents).unwrap();
+        }
+        zip.finish().unwrap();
+        fs::remove_file(&wav).unwrap();
+
+        let root = dir.path().to_str().unwrap();
+        let groups = scan_folder(root, &ScanOptions::default()).unwrap();
+        let pack = format!("{}/pack.zip", root);
+        let files = |folder: &str| -> Vec<(String, bool)> {
+            groups[folder]
+                .iter()
+                .map(|file| (file.path.clone(), file.detection.is_supported()))
+                .collect()
+        };
+        assert_eq!(
+            files(&pack),
+            vec![
+                (format!("{}!/kick.wav", pack), true),
+                (format!("{}!/notes.txt", pack), false)
+            ]
+        );
+        assert_eq!(
+            files(&format!("{}!/Kicks", pack)),
+            vec![(format!("{}!/Kicks/kick.wav", pack), true)]
+        );
+    }
+
+    #[test]
+    fn resource_forks_and_hidden_folders_are_skipped() {
+        let dir = TempDir::new("scan");
+        write_wav(&dir.join("._kick.wav"), 8000, 1, 800);
+        write_wav(&dir.join(".git/objects/kick.wav"), 8000, 1, 800);
+        write_wav(&dir.join(".take.wav"), 8000, 1, 800);
+        let groups = scan(&dir, &ScanOptions::default());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[""], vec![".take.wav"]);
+    }
+
+    #[test]
+    fn patterns_size_and_duration_filter_files() {
+        let dir = library();
+        let excluded = ScanOptions {
+            exclude: vec!["Kicks/Acoustic".to_string()],
+            ..Default::default()
+        };
+        assert!(!scan(&dir, &excluded).contains_key("Kicks/Acoustic/Dry"));
+        let included = ScanOptions {
+            include: vec!["**/kick.*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            scan(&dir, &included)
+                .into_values()
+                .flatten()
+                .collect::<Vec<_>>(),
+            vec!["Kicks/kick.wav"]
+        );
+        // Only dry.wav runs a full second, and only it is over 10 kB
+        let long = ScanOptions {
+            min_duration: Some(0.5),
+            ..Default::default()
+        };
+        let large = ScanOptions {
+            min_size: Some(10_000),
+            ..Default::default()
+        };
+        for options in [long, large] {
+            assert_eq!(
+                scan(&dir, &options)
+                    .into_values()
+                    .flatten()
+                    .collect::<Vec<_>>(),
+                vec!["Kicks/Acoustic/Dry/dry.wav"]
+            );
+        }
+    }
+
+    #[test]
+    fn missing_options_default_to_a_recursive_scan() {
+        let options: ScanOptions = serde_json::from_str("{}").unwrap();
+        assert!(options.recursive);
+        assert_eq!(options.max_depth, Some(DEFAULT_MAX_DEPTH));
+    }
+
+    #[test]
+    fn contents_decide_the_format_not_the_extension() {
+        let dir = TempDir::new("detect");
+        let no_extension = dir.join("take");
+        write_wav(&no_extension, 8000, 2, 800);
+        let notes = dir.join("notes.wav");
+        fs::write(&notes, "not audio at all").unwrap();
+
+        match detect_format(&no_extension) {
+            Detection::Supported {
+                container,
+                codec,
+                tracks,
+            } => {
+                assert_eq!(container, "wave");
+                assert_eq!(codec, "pcm_s16le");
+                assert_eq!(tracks.len(), 1);
+            }
+            Detection::Unsupported { reason } => panic!("not detected: {}", reason),
+        }
+        assert!(!detect_format(&notes).is_supported());
+        // Unsupported files are still listed, with the reason
+        let groups = scan_folder(dir.path().to_str().unwrap(), &ScanOptions::default()).unwrap();
+        assert_eq!(groups.values().flatten().count(), 2);
+    }
+}
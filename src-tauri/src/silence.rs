@@ -0,0 +1,659 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::render::{invalidate_mix, Trim};
+use crate::resample::ProjectFormat;
+use crate::sample_store::Samples;
+use crate::state::AppState;
+use crate::timeline::{self, ClipView};
+
+/// How leading and trailing silence is found, and whether it's cut from the mix.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SilenceSettings {
+    // Anything quieter than this counts as silence, in dBFS
+    pub threshold_db: f32,
+    // Shorter stretches of silence are left alone
+    pub min_length_ms: f64,
+    // Silence kept next to the sound so attacks and tails aren't clipped
+    pub hold_ms: f64,
+    // Clips without a trim of their own use the suggested one in combine and export
+    pub auto_trim: bool,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        Self {
+            threshold_db: -60.0,
+            min_length_ms: 20.0,
+            hold_ms: 10.0,
+            auto_trim: false,
+        }
+    }
+}
+
+impl SilenceSettings {
+    fn is_valid(&self) -> bool {
+        self.threshold_db.is_finite()
+            && self.threshold_db <= 0.0
+            && self.min_length_ms.is_finite()
+            && self.min_length_ms >= 0.0
+            && self.hold_ms.is_finite()
+            && self.hold_ms >= 0.0
+    }
+}
+
+/// The trim that cuts the silence off either end of `samples`.
+///
+/// An end is only cut when its silence is at least `min_length_ms` long, and a file that's
+/// silent throughout is left as it is.
+pub fn suggest_trim(samples: &[f32], format: &ProjectFormat, settings: &SilenceSettings) -> Trim {
+    let channels = format.channels.max(1) as usize;
+    let sample_rate = format.sample_rate as f64;
+    let threshold = 10f32.powf(settings.threshold_db / 20.0);
+    let loud = |frame: &[f32]| frame.iter().any(|sample| sample.abs() > threshold);
+
+    let frames = samples.len() / channels;
+    let Some(first) = samples.chunks_exact(channels).position(loud) else {
+        return Trim::default();
+    };
+    // `first` found a loud frame, so there is a last one
+    let last = samples
+        .chunks_exact(channels)
+        .rposition(loud)
+        .unwrap_or(first);
+
+    let to_frames = |ms: f64| (ms * sample_rate / 1000.0).round() as usize;
+    let min_length = to_frames(settings.min_length_ms).max(1);
+    let hold = to_frames(settings.hold_ms);
+    let trailing = frames - 1 - last;
+
+    let start = if first >= min_length {
+        first.saturating_sub(hold)
+    } else {
+        0
+    };
+    let end = (trailing >= min_length).then(|| (last + 1 + hold).min(frames));
+    Trim {
+        start: start as f64 / sample_rate,
+        end: end.map(|end| end as f64 / sample_rate),
+    }
+}
+
+/// The suggested trim of a source and how much silence it cuts.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestedTrim {
+    pub id: Uuid,
+    pub path: String,
+    pub trim: Trim,
+    pub leading_ms: f64,
+    pub trailing_ms: f64,
+}
+
+fn suggested_trims(state: &AppState) -> Result<Vec<SuggestedTrim>, Error> {
+    let project_format = *state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(audio_files
+        .values()
+        .map(|file| {
+            let frames = file.samples.len() / project_format.channels.max(1) as usize;
+            let duration = frames as f64 / project_format.sample_rate as f64;
+            let trim = file.suggested_trim;
+            SuggestedTrim {
+                id: file.id,
+                path: file.path.clone(),
+                trim,
+                leading_ms: trim.start * 1000.0,
+                trailing_ms: trim.end.map_or(0.0, |end| (duration - end) * 1000.0),
+            }
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub fn get_silence_settings(state: State<'_, Arc<AppState>>) -> Result<SilenceSettings, Error> {
+    let settings = state.silence.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(*settings)
+}
+
+#[tauri::command]
+pub fn get_suggested_trims(state: State<'_, Arc<AppState>>) -> Result<Vec<SuggestedTrim>, Error> {
+    suggested_trims(&state)
+}
+
+/// Changes how silence is detected, and looks for it again in every loaded source.
+#[tauri::command]
+pub async fn set_silence_settings(
+    settings: SilenceSettings,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<SuggestedTrim>, Error> {
+    if !settings.is_valid() {
+        return Err(Error::InvalidSilenceSettings);
+    }
+    let state = state.inner().clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let edit = state.history.begin(&state, false)?;
+        *state.silence.lock().map_err(|_| Error::LockPoisoned)? = settings;
+        refresh_suggested_trims(&state, &settings)?;
+
+        let suggestions = suggested_trims(&state)?;
+        println!(
+            "🔇 Silence below {} dB: {} of {} files would be trimmed{}",
+            settings.threshold_db,
+            suggestions
+                .iter()
+                .filter(|suggestion| suggestion.trim != Trim::default())
+                .count(),
+            suggestions.len(),
+            if settings.auto_trim {
+                ", trimming automatically"
+            } else {
+                ""
+            }
+        );
+        edit.record("Set silence detection")?;
+        invalidate_mix(&state)?;
+        Ok(suggestions)
+    })
+    .await?
+}
+
+/// Looks for silence again in every loaded source, with `settings`.
+pub fn refresh_suggested_trims(state: &AppState, settings: &SilenceSettings) -> Result<(), Error> {
+    let project_format = *state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    // Detection runs without the lock, the sample buffers are shared
+    let sources: Vec<(String, Samples)> = {
+        let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        audio_files
+            .values()
+            .map(|file| (file.path.clone(), file.samples.clone()))
+            .collect()
+    };
+    let trims: Vec<(String, Trim)> = sources
+        .into_iter()
+        .map(|(path, samples)| {
+            let trim = suggest_trim(&samples, &project_format, settings);
+            (path, trim)
+        })
+        .collect();
+    let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    for (path, trim) in trims {
+        if let Some(file) = audio_files.get_mut(&path) {
+            file.suggested_trim = trim;
+        }
+    }
+    Ok(())
+}
+
+// Pauses are found in blocks this long, so a single quiet sample inside a word isn't one
+const BLOCK_MS: f64 = 10.0;
+
+/// Shortens the pauses inside a clip, for speech.
+///
+/// A pause is a stretch below `threshold_db` with sound on both sides. Any longer than
+/// `max_pause_ms` is cut down to `keep_ms`, the two sides of the cut crossfaded over
+/// `crossfade_ms`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PauseCompression {
+    pub threshold_db: f32,
+    pub max_pause_ms: f64,
+    pub keep_ms: f64,
+    pub crossfade_ms: f64,
+}
+
+impl Default for PauseCompression {
+    fn default() -> Self {
+        Self {
+            // Room tone of a voice recording sits well above digital silence
+            threshold_db: -45.0,
+            max_pause_ms: 500.0,
+            keep_ms: 250.0,
+            crossfade_ms: 10.0,
+        }
+    }
+}
+
+impl PauseCompression {
+    fn is_valid(&self) -> bool {
+        self.threshold_db.is_finite()
+            && self.threshold_db <= 0.0
+            && self.keep_ms.is_finite()
+            && self.keep_ms >= 0.0
+            && self.max_pause_ms.is_finite()
+            && self.max_pause_ms >= self.keep_ms
+            && self.crossfade_ms.is_finite()
+            && self.crossfade_ms >= 0.0
+    }
+}
+
+/// Whether a clip's pauses are compressed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum PauseMode {
+    // Whatever its section is set to
+    #[default]
+    Section,
+    Off,
+    Compress(PauseCompression),
+}
+
+/// One shortened pause: the clip plays up to `fade_out`, crossfades over `crossfade` frames
+/// into `fade_in` and carries on from there. All in samples per channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PauseCut {
+    pub fade_out: usize,
+    pub fade_in: usize,
+    pub crossfade: usize,
+}
+
+impl PauseCut {
+    pub fn saved_frames(&self) -> usize {
+        self.fade_in - self.fade_out
+    }
+}
+
+/// Where `samples` would be cut to shorten every pause longer than `settings.max_pause_ms`.
+pub fn find_pauses(
+    samples: &[f32],
+    format: &ProjectFormat,
+    settings: &PauseCompression,
+) -> Vec<PauseCut> {
+    let channels = format.channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let to_frames = |ms: f64| (ms * format.sample_rate as f64 / 1000.0).round() as usize;
+    let block = to_frames(BLOCK_MS).max(1);
+    let max_pause = to_frames(settings.max_pause_ms);
+    let keep = to_frames(settings.keep_ms);
+    let crossfade = to_frames(settings.crossfade_ms).min(keep);
+    let threshold = 10f32.powf(settings.threshold_db / 20.0);
+
+    // Runs of quiet blocks as frame ranges
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for start in (0..frames).step_by(block) {
+        let end = (start + block).min(frames);
+        let quiet = samples[start * channels..end * channels]
+            .iter()
+            .all(|sample| sample.abs() <= threshold);
+        match (quiet, run_start) {
+            (true, None) => run_start = Some(start),
+            (false, Some(from)) => {
+                runs.push((from, start));
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    runs.into_iter()
+        // Silence at the very start is a trim, not a pause, and the two fades can't overlap
+        .filter(|&(from, to)| from > 0 && to - from > max_pause && to - from >= keep + crossfade)
+        .map(|(from, to)| {
+            // Half of what's kept comes from each side of the pause, joined by the crossfade
+            let head = (keep - crossfade) / 2;
+            let tail = keep - crossfade - head;
+            PauseCut {
+                fade_out: from + head,
+                fade_in: to - tail - crossfade,
+                crossfade,
+            }
+        })
+        .collect()
+}
+
+/// Streams `samples` with `cuts` applied to `out`, a piece at a time.
+pub fn write_compressed(
+    samples: &[f32],
+    channels: usize,
+    cuts: &[PauseCut],
+    mut out: impl FnMut(&[f32]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let frames = samples.len() / channels;
+    let mut copied = 0;
+    let mut fade = Vec::new();
+    for cut in cuts {
+        out(&samples[copied * channels..cut.fade_out * channels])?;
+        fade.clear();
+        for i in 0..cut.crossfade {
+            let gain = (i as f32 + 0.5) / cut.crossfade as f32;
+            for channel in 0..channels {
+                let outgoing = samples[(cut.fade_out + i) * channels + channel];
+                let incoming = samples[(cut.fade_in + i) * channels + channel];
+                fade.push(outgoing * (1.0 - gain) + incoming * gain);
+            }
+        }
+        out(&fade)?;
+        copied = cut.fade_in + cut.crossfade;
+    }
+    out(&samples[copied * channels..frames * channels])
+}
+
+// What a clip's compressed samples were made from, so they're only redone when it changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PauseKey {
+    settings: PauseCompression,
+    trim: Trim,
+    content_hash: u64,
+}
+
+impl PauseKey {
+    fn of(view: &ClipView, sections: &HashMap<String, PauseCompression>) -> Option<Self> {
+        let settings = match view.clip.pauses {
+            PauseMode::Section => *sections.get(&view.source.section)?,
+            PauseMode::Off => return None,
+            PauseMode::Compress(settings) => settings,
+        };
+        Some(Self {
+            settings,
+            trim: view.trim,
+            content_hash: view.source.content_hash,
+        })
+    }
+}
+
+/// A clip with its long pauses cut down.
+pub struct Compressed {
+    key: PauseKey,
+    // Held in the sample store, so long takes spill to disk like any other buffer. None
+    // when there was no pause long enough to cut
+    pub samples: Option<Samples>,
+    pub pauses: usize,
+    // In samples per channel
+    pub saved_frames: usize,
+}
+
+/// Pause compression of every clip on the timeline that has it, by clip id.
+///
+/// Clips keep what was worked out for them last time until their settings, trim or source
+/// change. Must be called without any of the arrangement locks held.
+pub fn compress_timeline(state: &AppState) -> Result<HashMap<Uuid, Arc<Compressed>>, Error> {
+    let project_format = *state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    let auto_trim = state
+        .silence
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .auto_trim;
+    let sections = state
+        .section_pauses
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .clone();
+
+    // What needs doing is worked out under the locks, the compression itself runs without them
+    let (mut current, jobs) = {
+        let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let cache = state.pause_cache.lock().map_err(|_| Error::LockPoisoned)?;
+        let mut current = HashMap::new();
+        let mut jobs = Vec::new();
+        for view in timeline::resolve(&timeline, &audio_files, auto_trim) {
+            let Some(key) = PauseKey::of(&view, &sections) else {
+                continue;
+            };
+            match cache.get(&view.id()) {
+                Some(cached) if cached.key == key => {
+                    current.insert(view.id(), Arc::clone(cached));
+                }
+                _ => jobs.push((view.id(), key, view.source.samples.clone())),
+            }
+        }
+        (current, jobs)
+    };
+
+    let channels = project_format.channels.max(1) as usize;
+    for (id, key, source) in jobs {
+        let samples = key.trim.apply(&source, &project_format);
+        let cuts = find_pauses(samples, &project_format, &key.settings);
+        let saved_frames: usize = cuts.iter().map(PauseCut::saved_frames).sum();
+        let compressed = if cuts.is_empty() {
+            None
+        } else {
+            let mut writer = state
+                .sample_store
+                .writer(samples.len() - saved_frames * channels)?;
+            write_compressed(samples, channels, &cuts, |piece| {
+                writer.extend_from_slice(piece)
+            })?;
+            Some(writer.finish()?)
+        };
+        current.insert(
+            id,
+            Arc::new(Compressed {
+                key,
+                samples: compressed,
+                pauses: cuts.len(),
+                saved_frames,
+            }),
+        );
+    }
+
+    // Clips that are gone or no longer compressed drop out of the cache here
+    *state.pause_cache.lock().map_err(|_| Error::LockPoisoned)? = current.clone();
+    Ok(current)
+}
+
+/// `views` playing their compressed samples where there are any.
+///
+/// A view whose clip changed since `compressed` was worked out plays as it is.
+pub fn with_compressed<'a>(
+    views: Vec<ClipView<'a>>,
+    compressed: &'a HashMap<Uuid, Arc<Compressed>>,
+    sections: &HashMap<String, PauseCompression>,
+) -> Vec<ClipView<'a>> {
+    views
+        .into_iter()
+        .map(|view| {
+            let current = compressed
+                .get(&view.id())
+                .filter(|compressed| Some(compressed.key) == PauseKey::of(&view, sections));
+            match current.and_then(|compressed| compressed.samples.as_ref()) {
+                Some(samples) => view.with_samples(samples),
+                None => view,
+            }
+        })
+        .collect()
+}
+
+/// Seconds the compressed pauses take off `views`.
+pub fn time_saved(
+    views: &[ClipView],
+    compressed: &HashMap<Uuid, Arc<Compressed>>,
+    format: &ProjectFormat,
+) -> f64 {
+    let frames: usize = views
+        .iter()
+        .filter(|view| view.compressed.is_some())
+        .filter_map(|view| compressed.get(&view.id()))
+        .map(|clip| clip.saved_frames)
+        .sum();
+    frames as f64 / format.sample_rate as f64
+}
+
+/// Sets how the pauses of clip `id` are compressed.
+#[tauri::command]
+pub fn set_clip_pauses(
+    id: Uuid,
+    pauses: PauseMode,
+    state: State<'_, Arc<AppState>>,
+) -> Result<PauseMode, Error> {
+    if let PauseMode::Compress(settings) = &pauses {
+        if !settings.is_valid() {
+            return Err(Error::InvalidPauseCompression);
+        }
+    }
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let clip = timeline
+            .iter_mut()
+            .find(|clip| clip.id == id)
+            .ok_or(Error::ClipNotFound(id))?;
+        println!("🗣️ Pauses of {} set to {:?}", clip.source, pauses);
+        clip.pauses = pauses;
+    }
+    edit.record("Set pause compression")?;
+    invalidate_mix(&state)?;
+    Ok(pauses)
+}
+
+#[tauri::command]
+pub fn get_section_pauses(
+    state: State<'_, Arc<AppState>>,
+) -> Result<HashMap<String, PauseCompression>, Error> {
+    let sections = state
+        .section_pauses
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    Ok(sections.clone())
+}
+
+/// Compresses the pauses of every clip in `section` that follows its section, or stops
+/// with None.
+#[tauri::command]
+pub fn set_section_pauses(
+    section: String,
+    pauses: Option<PauseCompression>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), Error> {
+    if pauses.is_some_and(|settings| !settings.is_valid()) {
+        return Err(Error::InvalidPauseCompression);
+    }
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut sections = state
+            .section_pauses
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+        println!("🗣️ Pauses in section {} set to {:?}", section, pauses);
+        match pauses {
+            Some(settings) => sections.insert(section, settings),
+            None => sections.remove(&section),
+        };
+    }
+    edit.record("Set section pause compression")?;
+    invalidate_mix(&state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One frame per millisecond keeps the numbers readable
+    const FORMAT: ProjectFormat = ProjectFormat {
+        sample_rate: 1000,
+        channels: 1,
+    };
+
+    // Sound and silence alternating, lengths in frames
+    fn signal(parts: &[(usize, bool)]) -> Vec<f32> {
+        parts
+            .iter()
+            .flat_map(|&(frames, loud)| std::iter::repeat_n(if loud { 0.5 } else { 0.0 }, frames))
+            .collect()
+    }
+
+    #[test]
+    fn trim_cuts_silence_and_keeps_the_hold() {
+        let samples = signal(&[(100, false), (100, true), (100, false)]);
+        let trim = suggest_trim(&samples, &FORMAT, &SilenceSettings::default());
+        assert_eq!(trim.start, 0.09);
+        assert_eq!(trim.end, Some(0.21));
+    }
+
+    #[test]
+    fn trim_leaves_short_silence_and_silent_files() {
+        let samples = signal(&[(10, false), (100, true)]);
+        assert_eq!(
+            suggest_trim(&samples, &FORMAT, &SilenceSettings::default()),
+            Trim::default()
+        );
+        let silent = signal(&[(500, false)]);
+        assert_eq!(
+            suggest_trim(&silent, &FORMAT, &SilenceSettings::default()),
+            Trim::default()
+        );
+    }
+
+    #[test]
+    fn trim_looks_at_every_channel() {
+        let stereo = ProjectFormat {
+            sample_rate: 1000,
+            channels: 2,
+        };
+        // Only the right channel has sound, from frame 50
+        let samples: Vec<f32> = (0..200)
+            .flat_map(|frame| [0.0, if frame >= 50 { 0.5 } else { 0.0 }])
+            .collect();
+        let trim = suggest_trim(&samples, &stereo, &SilenceSettings::default());
+        assert_eq!(trim.start, 0.04);
+        assert_eq!(trim.end, None);
+    }
+
+    #[test]
+    fn pauses_are_cut_down_to_what_is_kept() {
+        let samples = signal(&[(100, true), (1000, false), (100, true)]);
+        let cuts = find_pauses(&samples, &FORMAT, &PauseCompression::default());
+        // 240 of the 250 ms kept are split across the sides, the crossfade makes up the rest
+        assert_eq!(
+            cuts,
+            vec![PauseCut {
+                fade_out: 220,
+                fade_in: 970,
+                crossfade: 10,
+            }]
+        );
+        assert_eq!(cuts[0].saved_frames(), 750);
+    }
+
+    #[test]
+    fn short_and_edge_pauses_are_left_alone() {
+        let samples = signal(&[
+            (300, false),
+            (100, true),
+            (400, false),
+            (100, true),
+            (2000, false),
+        ]);
+        assert!(find_pauses(&samples, &FORMAT, &PauseCompression::default()).is_empty());
+    }
+
+    #[test]
+    fn compressed_samples_are_spliced_at_the_cuts() {
+        let channels = 2;
+        let samples: Vec<f32> = (0..1200 * channels).map(|i| i as f32).collect();
+        let cut = PauseCut {
+            fade_out: 220,
+            fade_in: 970,
+            crossfade: 10,
+        };
+        let mut compressed = Vec::new();
+        write_compressed(&samples, channels, &[cut], |piece| {
+            compressed.extend_from_slice(piece);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(compressed.len(), (1200 - cut.saved_frames()) * channels);
+        assert_eq!(compressed[..220 * channels], samples[..220 * channels]);
+        assert_eq!(compressed[230 * channels..], samples[980 * channels..]);
+        // The crossfade moves from one side of the cut to the other
+        let first = compressed[220 * channels];
+        assert!(first > samples[220 * channels] && first < samples[970 * channels]);
+    }
+}
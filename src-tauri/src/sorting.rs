@@ -1,11 +1,25 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::sync::{Arc, Mutex};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use tauri::ipc::Channel;
 use tauri::State;
 use uuid::Uuid;
 
-use crate::state::{get_app_state, AppState, AudioFile};
+use crate::archive::backing_file;
+use crate::error::Error;
+use crate::metadata::{read_tags, Tags};
+use crate::render::{invalidate_mix, Arrangement};
+use crate::resample::ProjectFormat;
+use crate::silence::{compress_timeline, with_compressed};
+use crate::source::SourceRef;
+use crate::state::{AppState, AudioFile};
+use crate::timeline::{self, ClipView};
 
 #[derive(Clone, Serialize)]
 #[serde(
@@ -22,125 +36,492 @@ pub enum SortAudioEvent {
         progress: f64,
         id: Uuid,
         start_offset: f64,
+        // In samples per channel
+        start: u64,
     },
     Finished,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct SortUpdate {
-    pub id: Uuid,     // UUID of the AudioFile
+    pub id: Uuid,     // UUID of the clip
     pub index: usize, // new order in timeline
 }
 
 #[tauri::command]
-pub fn update_sorting(
+pub async fn update_sorting(
     updates: Vec<SortUpdate>,
     state: State<'_, Arc<AppState>>,
     on_event: Channel<SortAudioEvent>,
-) -> Result<Vec<(Uuid, usize)>, String> {
-    println!("STARTING SORT");
-    let _ = on_event.send(SortAudioEvent::Started {
-        content_length: (10),
-    });
-    let mut audio_files = state.audio_files.lock().map_err(|_| "Lock poisoned")?;
+) -> Result<Vec<(Uuid, usize)>, Error> {
+    let state = state.inner().clone();
 
-    // Print initial BTreeMap order
-    println!("Initial BTreeMap order:");
-    for (key, file) in audio_files.iter() {
-        println!("{} -> {}", key, file.id);
-    }
+    // Compressing the timeline can mean finding pauses, which is no job for the main thread
+    tauri::async_runtime::spawn_blocking(move || {
+        let _ = on_event.send(SortAudioEvent::Started {
+            content_length: (10),
+        });
+
+        // Sort updates by index to get the new order
+        let mut ordered_updates = updates;
+        ordered_updates.sort_by_key(|u| u.index);
 
-    // println!("update0{}", updates[0].id);
-    // println!("update1{}", updates[1].id);
+        let order: Vec<Uuid> = ordered_updates.iter().map(|u| u.id).collect();
+        apply_order(&state, &order, "Reorder clips", &on_event)?;
+
+        let result: Vec<(Uuid, usize)> = ordered_updates.iter().map(|u| (u.id, u.index)).collect();
+        Ok(result)
+    })
+    .await?
+}
 
-    // Print order before sorting
-    println!("Order before sorting (by input order):");
-    for (i, update) in updates.iter().enumerate() {
-        println!("  {}: ID {} -> index {}", i, update.id, update.index);
+/// A tag field to sort by.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Track,
+    Bpm,
+    Key,
+}
+
+/// What a sort key looks at.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", rename_all_fields = "camelCase", tag = "by")]
+pub enum SortBy {
+    // File name with numbers compared by value, so "kick 2" comes before "kick 10"
+    Name,
+    Section,
+    // Of the trimmed clip
+    Duration,
+    Peak,
+    Rms,
+    // Of the file on disk, the archive for an entry
+    Modified,
+    Tag {
+        field: TagField,
+    },
+    // The same seed gives the same order for the same sources
+    Shuffle {
+        seed: u64,
+    },
+    // A capture group of `regex` matched against the file name, by name or number.
+    // Group 1 when there is one, otherwise the whole match
+    Pattern {
+        regex: String,
+        group: Option<String>,
+        #[serde(default)]
+        numeric: bool,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SortKey {
+    #[serde(flatten)]
+    pub by: SortBy,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// Sorts the timeline by `keys`, the first key deciding and each next one breaking ties.
+///
+/// Clips that tie on every key keep their current order. Clips a key has no value for,
+/// like an untagged file under a tag key, go after the rest whichever way it sorts.
+#[tauri::command]
+pub async fn sort_timeline(
+    keys: Vec<SortKey>,
+    state: State<'_, Arc<AppState>>,
+    on_event: Channel<SortAudioEvent>,
+) -> Result<Vec<Uuid>, Error> {
+    let state = state.inner().clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        // A bad pattern fails before anything gets read
+        let patterns = keys
+            .iter()
+            .map(|key| match &key.by {
+                SortBy::Pattern { regex, .. } => Regex::new(regex)
+                    .map(Some)
+                    .map_err(|e| Error::InvalidPattern(e.to_string())),
+                _ => Ok(None),
+            })
+            .collect::<Result<Vec<Option<Regex>>, Error>>()?;
+        let project_format = *state
+            .project_format
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?;
+        let auto_trim = state
+            .silence
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .auto_trim;
+
+        // Reading tags and measuring levels happens on copies, without holding the locks
+        let (clips, sources) = {
+            let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+            let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+            let sources: HashMap<String, AudioFile> = timeline
+                .iter()
+                .filter_map(|clip| {
+                    Some((clip.source.clone(), audio_files.get(&clip.source)?.clone()))
+                })
+                .collect();
+            (timeline.clone(), sources)
+        };
+        println!("🔀 Sorting {} clips by {:?}", clips.len(), keys);
+        let _ = on_event.send(SortAudioEvent::Started {
+            content_length: clips.len(),
+        });
+
+        let mut tags: HashMap<String, Tags> = HashMap::new();
+        let mut occurrences: HashMap<&str, u64> = HashMap::new();
+        let mut rows: Vec<(Uuid, Vec<SortValue>)> = Vec::with_capacity(clips.len());
+        for clip in &clips {
+            let Some(source) = sources.get(&clip.source) else {
+                rows.push((clip.id, vec![SortValue::Missing; keys.len()]));
+                continue;
+            };
+            let occurrence = occurrences.entry(clip.source.as_str()).or_default();
+            let view = ClipView::new(clip, source, auto_trim);
+            let values = keys
+                .iter()
+                .zip(&patterns)
+                .map(|(key, pattern)| {
+                    sort_value(
+                        &key.by,
+                        pattern.as_ref(),
+                        &view,
+                        *occurrence,
+                        &project_format,
+                        &mut tags,
+                    )
+                })
+                .collect();
+            *occurrence += 1;
+            rows.push((clip.id, values));
+        }
+
+        // Stable, so ties keep the order they had
+        rows.sort_by(|(_, a), (_, b)| {
+            keys.iter()
+                .zip(a.iter().zip(b))
+                .map(|(key, (a, b))| a.compare(b, key.descending))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        let order: Vec<Uuid> = rows.into_iter().map(|(id, _)| id).collect();
+        apply_order(&state, &order, "Sort clips", &on_event)?;
+        Ok(order)
+    })
+    .await?
+}
+
+#[derive(Clone, Debug)]
+enum SortValue {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+impl SortValue {
+    fn compare(&self, other: &Self, descending: bool) -> Ordering {
+        let ordering = match (self, other) {
+            // Missing values stay at the end in either direction
+            (Self::Missing, Self::Missing) => return Ordering::Equal,
+            (Self::Missing, _) => return Ordering::Greater,
+            (_, Self::Missing) => return Ordering::Less,
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Text(a), Self::Text(b)) => natural_cmp(a, b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => Ordering::Greater,
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+fn sort_value(
+    by: &SortBy,
+    pattern: Option<&Regex>,
+    clip: &ClipView,
+    occurrence: u64,
+    format: &ProjectFormat,
+    tags: &mut HashMap<String, Tags>,
+) -> SortValue {
+    let key = clip.source.path.as_str();
+    let name = file_name(key);
+    match by {
+        SortBy::Name => SortValue::Text(name.to_string()),
+        SortBy::Section => SortValue::Text(clip.source.section.clone()),
+        SortBy::Duration => {
+            let frames = clip.samples(format).len() / format.channels.max(1) as usize;
+            SortValue::Number(frames as f64 / format.sample_rate as f64)
+        }
+        SortBy::Peak => SortValue::Number(
+            clip.samples(format)
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs())) as f64,
+        ),
+        SortBy::Rms => {
+            let samples = clip.samples(format);
+            if samples.is_empty() {
+                return SortValue::Missing;
+            }
+            let sum: f64 = samples.iter().map(|&sample| (sample as f64).powi(2)).sum();
+            SortValue::Number((sum / samples.len() as f64).sqrt())
+        }
+        SortBy::Modified => modified(key).map_or(SortValue::Missing, SortValue::Number),
+        SortBy::Tag { field } => {
+            let tags = tags.entry(key.to_string()).or_insert_with(|| {
+                read_tags(key).unwrap_or_else(|e| {
+                    eprintln!("⚠️ No tags for {}: {}", key, e);
+                    Tags::default()
+                })
+            });
+            let text = |value: &Option<String>| value.clone().map(SortValue::Text);
+            let number = |value: Option<f64>| value.map(SortValue::Number);
+            match field {
+                TagField::Title => text(&tags.title),
+                TagField::Artist => text(&tags.artist),
+                TagField::Album => text(&tags.album),
+                TagField::Genre => text(&tags.genre),
+                TagField::Year => number(tags.year.map(f64::from)),
+                TagField::Track => number(tags.track.map(f64::from)),
+                TagField::Bpm => number(tags.bpm),
+                TagField::Key => text(&tags.key),
+            }
+            .unwrap_or(SortValue::Missing)
+        }
+        SortBy::Shuffle { seed } => {
+            SortValue::Number((shuffle_key(*seed, key, occurrence) >> 11) as f64)
+        }
+        SortBy::Pattern { group, numeric, .. } => {
+            let Some(captures) = pattern.and_then(|pattern| pattern.captures(name)) else {
+                return SortValue::Missing;
+            };
+            let found = match group {
+                Some(group) => match group.parse::<usize>() {
+                    Ok(index) => captures.get(index),
+                    Err(_) => captures.name(group),
+                },
+                None => captures.get(1).or_else(|| captures.get(0)),
+            };
+            match found {
+                Some(found) if *numeric => found
+                    .as_str()
+                    .trim()
+                    .parse()
+                    .map_or(SortValue::Missing, SortValue::Number),
+                Some(found) => SortValue::Text(found.as_str().to_string()),
+                None => SortValue::Missing,
+            }
+        }
     }
+}
 
-    // Sort updates by index to get the new order
-    let mut ordered_updates = updates.clone();
-    ordered_updates.sort_by_key(|u| u.index);
+// The last part of a source key: the file name or the entry name inside an archive, with
+// the track fragment of a chosen track
+fn file_name(key: &str) -> &str {
+    key.rsplit(['/', '\\']).next().unwrap_or(key)
+}
 
-    // Print order after sorting
-    println!("Order after sorting (by index):");
-    for (i, update) in ordered_updates.iter().enumerate() {
-        println!("  {}: ID {} -> index {}", i, update.id, update.index);
+// Seconds since the epoch the file behind `key` was last written
+fn modified(key: &str) -> Option<f64> {
+    let source = SourceRef::parse(key);
+    let modified = fs::metadata(backing_file(&source.path))
+        .ok()?
+        .modified()
+        .ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs_f64())
+}
+
+/// Compares names so runs of digits go by value: "take 2" < "take 10" < "Take 11".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering.is_ne() {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
     }
+}
 
-    // Calculate total samples in the new order
-    let total_samples: usize = ordered_updates
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+// FNV-1a of the source mixed with the seed through SplitMix64, stable across runs
+fn shuffle_key(seed: u64, source: &str, occurrence: u64) -> u64 {
+    let hash = source.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    let mut z = (seed ^ hash ^ occurrence.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Puts the clips in `order` first, the rest after them as they were, and sends the new
+// starts through `on_event`
+pub fn apply_order(
+    state: &AppState,
+    order: &[Uuid],
+    label: &str,
+    on_event: &Channel<SortAudioEvent>,
+) -> Result<(), Error> {
+    let edit = state.history.begin(state, false)?;
+    // Compression doesn't depend on the order, and mostly comes straight from the cache
+    let compressed = compress_timeline(state)?;
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+
+    // An order naming a clip that's gone was made for another timeline, it's left as it is
+    if let Some(id) = order
         .iter()
-        .filter_map(|u| audio_files.values().find(|f| f.id == u.id))
-        .map(|file| file.samples.len())
-        .sum();
-
-    // if total_samples == 0 {
-    //     return Ok(()); // nothing to update
-    // }
-
-    let mut current_sample_offset = 0;
-    println!("Current AudioFile IDs in BTreeMap:");
-    for file in audio_files.values() {
-        println!("{}", file.id);
-        println!("{}", file.path);
+        .find(|id| !timeline.iter().any(|clip| clip.id == **id))
+    {
+        return Err(Error::ClipNotFound(*id));
     }
-    // Update start_offset for each file according to new order
-    for update in &ordered_updates {
-        if let Some(file) = audio_files.values_mut().find(|f| f.id == update.id) {
-            file.start_offset = current_sample_offset as f64 / total_samples as f64;
-            current_sample_offset += file.samples.len();
+
+    // Clips in the order given, then any the update doesn't mention in their old order
+    let mut remaining = std::mem::take(&mut *timeline);
+    for id in order {
+        if let Some(index) = remaining.iter().position(|clip| clip.id == *id) {
+            timeline.push(remaining.remove(index));
         }
     }
+    timeline.extend(remaining);
 
-    // Reorder BTreeMap to reflect new timeline order
-    let mut new_map: BTreeMap<String, AudioFile> = BTreeMap::new();
-    let num_updates = ordered_updates.len();
-    for (i, update) in ordered_updates.iter().enumerate() {
-        if let Some(file) = audio_files.values().find(|f| f.id == update.id) {
-            new_map.insert(file.path.clone(), file.clone());
-
-            // Send progress as a float between 0.0 and 1.0
-            let progress = (i + 1) as f64 / num_updates as f64;
-            on_event.send(SortAudioEvent::Progress {
-                progress,
-                start_offset: file.start_offset,
-                id: file.id,
-            });
+    // Starts come out of the same arrangement combine uses, joins, gaps and lanes included
+    let project_format = *state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+    let crossfades = state
+        .crossfades
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .clone();
+    let gaps = state.gaps.lock().map_err(|_| Error::LockPoisoned)?.clone();
+    let auto_trim = state
+        .silence
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .auto_trim;
+    let section_pauses = state
+        .section_pauses
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .clone();
+    let channels = project_format.channels.max(1) as usize;
+    let offsets: Vec<(Uuid, usize, f64)> = {
+        let views = timeline::resolve(&timeline, &audio_files, auto_trim);
+        let views = with_compressed(views, &compressed, &section_pauses);
+        let arrangement = Arrangement::new(&views, &crossfades, &gaps, &project_format);
+        let total_samples = arrangement.total.max(1) as f64;
+        arrangement
+            .starts(&project_format)
+            .into_iter()
+            .map(|(view, start)| (view.id(), start, start as f64 / total_samples))
+            .collect()
+    };
 
-            if let Err(e) = on_event.send(SortAudioEvent::Progress {
-                progress,
-                start_offset: file.start_offset,
-                id: file.id,
-            }) {
-                eprintln!("⚠️ Failed to send progress event: {}", e);
-            }
-        } else {
-            eprintln!("NOT FOUND");
+    let num_clips = offsets.len();
+    for (i, (id, start, start_offset)) in offsets.into_iter().enumerate() {
+        let start = (start / channels) as u64;
+        if let Some(clip) = timeline.iter_mut().find(|clip| clip.id == id) {
+            clip.start = start;
+        }
+
+        // Send progress as a float between 0.0 and 1.0
+        let progress = (i + 1) as f64 / num_clips as f64;
+        if let Err(e) = on_event.send(SortAudioEvent::Progress {
+            progress,
+            start_offset,
+            start,
+            id,
+        }) {
+            eprintln!("⚠️ Failed to send progress event: {}", e);
         }
     }
 
-    *audio_files = new_map;
+    drop(timeline);
+    drop(audio_files);
+    edit.record(label)?;
+
+    // The order is part of how the mix sounds
+    invalidate_mix(state)?;
+    let _ = on_event.send(SortAudioEvent::Finished);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Store the custom order in the app state
-    let ordered_ids: Vec<Uuid> = ordered_updates.iter().map(|u| u.id).collect();
-    let mut custom_order = state.custom_order.lock().map_err(|_| "Lock poisoned")?;
-    *custom_order = ordered_ids.clone();
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
 
-    // Print the stored custom order
-    println!("Stored custom order:");
-    for (i, id) in ordered_ids.iter().enumerate() {
-        println!("  {}: {}", i, id);
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(
+            sorted(&["take 10", "Take 11", "take 2", "take 1"]),
+            vec!["take 1", "take 2", "take 10", "Take 11"]
+        );
+        assert_eq!(
+            sorted(&["track 010.wav", "track 9.wav", "track 0100.wav"]),
+            vec!["track 9.wav", "track 010.wav", "track 0100.wav"]
+        );
     }
 
-    // Print final BTreeMap order
-    println!("Final BTreeMap order:");
-    for (key, file) in audio_files.iter() {
-        println!("{} -> {}", key, file.id);
+    #[test]
+    fn letters_ignore_case() {
+        assert_eq!(natural_cmp("Kick", "kick"), Ordering::Equal);
+        assert_eq!(natural_cmp("apple", "Banana"), Ordering::Less);
+        assert_eq!(natural_cmp("take", "take 1"), Ordering::Less);
+        assert_eq!(natural_cmp("a2b", "a2a"), Ordering::Greater);
     }
 
-    let result: Vec<(Uuid, usize)> = ordered_updates.iter().map(|u| (u.id, u.index)).collect();
-    // println!(get_app_state(state)
-    Ok(result)
+    #[test]
+    fn long_numbers_do_not_overflow() {
+        assert_eq!(
+            natural_cmp("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+    }
 }
@@ -0,0 +1,237 @@
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::{get_codecs, get_probe};
+
+use crate::archive::{open_entry, split_entry_path, EntryData};
+use crate::error::Error;
+
+const TRACK_SUFFIX: &str = "#track=";
+
+/// A decodable source as stored in `audio_files`: a file, optionally narrowed to one track.
+///
+/// Keys look like `/path/recording.mkv#track=2`. A plain path means the first audio track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceRef {
+    pub path: String,
+    pub track: Option<u32>,
+}
+
+impl SourceRef {
+    pub fn parse(key: &str) -> Self {
+        if let Some((path, track)) = key.rsplit_once(TRACK_SUFFIX) {
+            if let Ok(track) = track.parse() {
+                return Self {
+                    path: path.to_string(),
+                    track: Some(track),
+                };
+            }
+        }
+        Self {
+            path: key.to_string(),
+            track: None,
+        }
+    }
+
+    pub fn key(&self) -> String {
+        match self.track {
+            Some(track) => format!("{}{}{}", self.path, TRACK_SUFFIX, track),
+            None => self.path.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrackInfo {
+    pub id: u32,
+    // The key to import this track on its own
+    pub source: String,
+    pub codec: String,
+    pub language: Option<String>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub is_default: bool,
+    // Set when the track is audio but can't be decoded, saying why
+    pub unsupported: Option<String>,
+}
+
+/// Opens a file, or an entry inside an archive, for reading its media.
+pub fn open_media(path: &str) -> Result<Box<dyn MediaSource>, Error> {
+    match split_entry_path(path) {
+        // Decompressed up front so readers that need to seek (MP4 for one) still can
+        Some((archive, entry)) => Ok(Box::new(open_entry(archive, entry)?)),
+        None => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+impl MediaSource for EntryData {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.size())
+    }
+}
+
+pub fn open_format(path: &str) -> Result<Box<dyn FormatReader>, Error> {
+    let mss = MediaSourceStream::new(open_media(path)?, Default::default());
+
+    // The extension only orders the probe's guesses, the stream contents still decide
+    let mut hint = Hint::new();
+    if let Some(extension) = Path::new(path).extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+
+    let probed = get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed.format)
+}
+
+// Video, subtitle and data tracks come through with a null codec or one we can't decode
+fn is_decodable_audio(track: &Track) -> bool {
+    track.codec_params.codec != CODEC_TYPE_NULL
+        && get_codecs().get_codec(track.codec_params.codec).is_some()
+}
+
+/// The requested track, or the container's default if it's audio, or the first audio track.
+///
+/// Screen recordings usually list the video first and mark it as the default.
+pub fn select_track(format: &dyn FormatReader, track: Option<u32>) -> Result<&Track, Error> {
+    match track {
+        Some(id) => {
+            let track = format
+                .tracks()
+                .iter()
+                .find(|t| t.id == id)
+                .ok_or(Error::TrackNotFound(id))?;
+            if !is_decodable_audio(track) {
+                return Err(Error::TrackNotFound(id));
+            }
+            Ok(track)
+        }
+        None => format
+            .default_track()
+            .filter(|t| is_decodable_audio(t))
+            .or_else(|| format.tracks().iter().find(|t| is_decodable_audio(t)))
+            .ok_or(Error::NoDefaultTrackFound),
+    }
+}
+
+// Audio in a codec we don't know still says how many channels and what rate it has, video
+// and subtitles don't
+fn is_audio(track: &Track) -> bool {
+    is_decodable_audio(track)
+        || track.codec_params.sample_rate.is_some()
+        || track.codec_params.channels.is_some()
+}
+
+/// Every audio track in the container, in the order it lists them.
+///
+/// Tracks we can't decode are listed too, with the reason in `unsupported`.
+pub fn list_audio_tracks(path: &str, format: &dyn FormatReader) -> Vec<AudioTrackInfo> {
+    let default_id = select_track(format, None).ok().map(|track| track.id);
+    format
+        .tracks()
+        .iter()
+        .filter(|track| is_audio(track))
+        .map(|track| {
+            let params = &track.codec_params;
+            let descriptor = get_codecs().get_codec(params.codec);
+            let unsupported = match descriptor {
+                Some(_) => None,
+                None if params.codec == CODEC_TYPE_NULL => {
+                    Some("The codec of this track isn't recognised".to_string())
+                }
+                None => Some(format!("No decoder for codec {}", params.codec)),
+            };
+            AudioTrackInfo {
+                id: track.id,
+                source: SourceRef {
+                    path: path.to_string(),
+                    track: Some(track.id),
+                }
+                .key(),
+                codec: descriptor
+                    .map(|descriptor| descriptor.short_name.to_string())
+                    .unwrap_or_default(),
+                language: track.language.clone(),
+                channels: params.channels.map(|channels| channels.count() as u16),
+                sample_rate: params.sample_rate,
+                is_default: Some(track.id) == default_id,
+                unsupported,
+            }
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub fn get_audio_tracks(path: String) -> Result<Vec<AudioTrackInfo>, Error> {
+    let format = open_format(&path)?;
+    Ok(list_audio_tracks(&path, format.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_wav, TempDir};
+
+    #[test]
+    fn keys_round_trip() {
+        for key in [
+            "/music/a.wav",
+            "/video/screen.mkv#track=2",
+            "/music/odd#name.wav",
+        ] {
+            assert_eq!(SourceRef::parse(key).key(), key);
+        }
+        let source = SourceRef::parse("/video/screen.mkv#track=2");
+        assert_eq!(source.path, "/video/screen.mkv");
+        assert_eq!(source.track, Some(2));
+    }
+
+    #[test]
+    fn fragments_that_are_not_tracks_stay_in_the_path() {
+        let source = SourceRef::parse("/music/take#2.wav");
+        assert_eq!(source.path, "/music/take#2.wav");
+        assert_eq!(source.track, None);
+    }
+
+    #[test]
+    fn lists_and_selects_the_audio_track() {
+        let dir = TempDir::new("source");
+        let path = dir.join("tone.wav");
+        write_wav(&path, 44100, 2, 4410);
+        let path = path.to_string_lossy().to_string();
+
+        let format = open_format(&path).unwrap();
+        let tracks = list_audio_tracks(&path, format.as_ref());
+        assert_eq!(tracks.len(), 1);
+        let track = &tracks[0];
+        assert!(track.is_default);
+        assert_eq!(track.channels, Some(2));
+        assert_eq!(track.sample_rate, Some(44100));
+        assert_eq!(track.unsupported, None);
+        assert_eq!(SourceRef::parse(&track.source).track, Some(track.id));
+
+        assert_eq!(select_track(format.as_ref(), None).unwrap().id, track.id);
+        assert_eq!(
+            select_track(format.as_ref(), Some(track.id)).unwrap().id,
+            track.id
+        );
+        assert!(matches!(
+            select_track(format.as_ref(), Some(track.id + 7)),
+            Err(Error::TrackNotFound(_))
+        ));
+    }
+}
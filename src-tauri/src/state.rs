@@ -1,38 +1,115 @@
 use rodio::Sink;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use tauri::State;
 use uuid::Uuid;
 
+use crate::cache::SampleCache;
+use crate::combine::DecodeReport;
+use crate::duplicates::DuplicatePolicy;
+use crate::harmony::MusicalAnalysis;
+use crate::history::History;
+use crate::render::{CrossfadeSettings, GapSettings, Trim};
+use crate::resample::{ProjectFormat, SourceSpec};
+use crate::sample_store::{SampleStore, Samples};
+use crate::silence::{Compressed, PauseCompression, SilenceSettings};
+use crate::timeline::{Clip, Lane};
+use crate::watch::FolderWatcher;
+
 #[derive(Clone)]
 pub struct AudioFile {
-    pub samples: Vec<i16>,
-    pub start_offset: f64,
-    pub waveform_path: String,
+    pub samples: Samples,
     pub id: Uuid,
     pub path: String,
+    pub original_spec: SourceSpec,
+    pub peaks: Vec<(f32, f32)>,
+    pub decode_report: DecodeReport,
+    // Folder of the section the file was imported from
+    pub section: String,
+    pub content_hash: u64,
+    // Set when the duplicate policy leaves this copy off the timeline
+    pub duplicate_of: Option<Uuid>,
+    // Computed the first time near-duplicates are searched for
+    pub fingerprint: Option<Vec<u32>>,
+    // Key, tempo and energy, computed the first time clips are arranged harmonically
+    pub analysis: Option<MusicalAnalysis>,
+    // Cuts the silence off either end, found on import with the silence settings
+    pub suggested_trim: Trim,
 }
 
 pub struct AppState {
     pub current_song: Mutex<Option<Arc<Sink>>>,
     pub audio_files: Mutex<BTreeMap<String, AudioFile>>,
-    pub combined_audio: Mutex<Option<Vec<i16>>>,
+    pub combined_audio: Mutex<Option<Samples>>,
     pub cancel_playback: AtomicBool,
     pub buffering_samples: AtomicBool,
     pub svg_path: Mutex<Option<String>>,
     pub cancel_token: AtomicU64,
     pub combine_process: Arc<Mutex<i32>>,
-    pub custom_order: Mutex<Vec<Uuid>>, // Store the custom order
+    // The clips that play, in order, each pointing at a source in `audio_files`
+    pub timeline: Mutex<Vec<Clip>>,
+    // Always holds the main lane first. Locked after the timeline
+    pub lanes: Mutex<Vec<Lane>>,
+    pub project_format: Mutex<ProjectFormat>,
+    pub sample_cache: SampleCache,
+    pub sample_store: SampleStore,
+    pub duplicate_policy: Mutex<DuplicatePolicy>,
+    pub folder_watcher: FolderWatcher,
+    pub crossfades: Mutex<CrossfadeSettings>,
+    pub gaps: Mutex<GapSettings>,
+    pub silence: Mutex<SilenceSettings>,
+    // Pause compression of clips that follow their section, by section
+    pub section_pauses: Mutex<HashMap<String, PauseCompression>>,
+    // Pause-compressed clips from the last layout, by clip id. Locked after the lanes
+    pub pause_cache: Mutex<HashMap<Uuid, Arc<Compressed>>>,
+    pub history: History,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            current_song: Mutex::new(None),
+            audio_files: Mutex::new(BTreeMap::new()),
+            combined_audio: Mutex::new(None),
+            cancel_playback: AtomicBool::new(false),
+            buffering_samples: AtomicBool::new(false),
+            svg_path: Mutex::new(None),
+            cancel_token: AtomicU64::new(0),
+            combine_process: Arc::new(Mutex::new(0)),
+            timeline: Mutex::new(Vec::new()),
+            lanes: Mutex::new(vec![Lane::main()]),
+            project_format: Mutex::new(Default::default()),
+            sample_cache: SampleCache::new(),
+            sample_store: SampleStore::new(),
+            duplicate_policy: Mutex::new(Default::default()),
+            folder_watcher: FolderWatcher::new(),
+            crossfades: Mutex::new(Default::default()),
+            gaps: Mutex::new(Default::default()),
+            silence: Mutex::new(Default::default()),
+            section_pauses: Mutex::new(Default::default()),
+            pause_cache: Mutex::new(Default::default()),
+            history: History::new(),
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Serialize)]
 pub struct AudioFileDebug {
     samples: usize,
-    start_offset: f64,
-    waveform_path: String,
+    spilled: bool,
     id: String,
+    original_spec: SourceSpec,
+    decode_report: DecodeReport,
+    section: String,
+    duplicate_of: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -43,6 +120,9 @@ pub struct SerializableAppState {
     pub svg_path: String,
     pub cancel_token: u64,
     pub combine_process: i32,
+    pub project_format: ProjectFormat,
+    pub timeline: Vec<Clip>,
+    pub lanes: Vec<Lane>,
 }
 
 #[tauri::command]
@@ -55,9 +135,12 @@ pub fn get_app_state(state: State<'_, Arc<AppState>>) -> SerializableAppState {
                 path.clone(),
                 AudioFileDebug {
                     samples: audio_file.samples.len(),
-                    start_offset: audio_file.start_offset,
-                    waveform_path: audio_file.waveform_path.clone(),
+                    spilled: audio_file.samples.is_spilled(),
                     id: audio_file.id.to_string(),
+                    original_spec: audio_file.original_spec,
+                    decode_report: audio_file.decode_report.clone(),
+                    section: audio_file.section.clone(),
+                    duplicate_of: audio_file.duplicate_of.map(|id| id.to_string()),
                 },
             )
         })
@@ -81,5 +164,8 @@ pub fn get_app_state(state: State<'_, Arc<AppState>>) -> SerializableAppState {
         svg_path: svg_string,
         cancel_token: state.cancel_token.load(Ordering::Relaxed),
         combine_process: *state.combine_process.lock().unwrap(),
+        project_format: *state.project_format.lock().unwrap(),
+        timeline: state.timeline.lock().unwrap().clone(),
+        lanes: state.lanes.lock().unwrap().clone(),
     }
 }
@@ -0,0 +1,76 @@
+//! Fixtures shared by the unit tests.
+
+use hound::WavWriter;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::duplicates::content_hash;
+use crate::encoder::{wav_spec, write_wav_samples};
+use crate::render::Trim;
+use crate::resample::{ProjectFormat, SampleDepth, SourceSpec};
+use crate::sample_store::SampleStore;
+use crate::state::AudioFile;
+
+/// A folder under the system temp dir, removed with everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("crate-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A 16-bit sine at half scale, `frames` long.
+pub fn write_wav(path: &Path, sample_rate: u32, channels: u16, frames: usize) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let mut writer =
+        WavWriter::create(path, wav_spec(sample_rate, channels, SampleDepth::Int(16))).unwrap();
+    let samples: Vec<f32> = (0..frames * channels as usize)
+        .map(|i| ((i / channels as usize) as f32 * 0.05).sin() * 0.5)
+        .collect();
+    write_wav_samples(&mut writer, &samples, SampleDepth::Int(16)).unwrap();
+    writer.finalize().unwrap();
+}
+
+/// A loaded source at `path` in `section`, already in the default project format.
+pub fn audio_file(path: &str, section: &str, samples: Vec<f32>) -> AudioFile {
+    let format = ProjectFormat::default();
+    AudioFile {
+        content_hash: content_hash(&samples),
+        samples: SampleStore::new().insert(samples).unwrap(),
+        id: Uuid::new_v4(),
+        path: path.to_string(),
+        original_spec: SourceSpec {
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            channel_mask: 0,
+            depth: None,
+        },
+        peaks: Vec::new(),
+        decode_report: Default::default(),
+        section: section.to_string(),
+        duplicate_of: None,
+        fingerprint: None,
+        analysis: None,
+        suggested_trim: Trim::default(),
+    }
+}
@@ -0,0 +1,528 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::render::{invalidate_mix, Envelope, EnvelopeShape, Trim};
+use crate::resample::ProjectFormat;
+use crate::silence::PauseMode;
+use crate::state::{AppState, AudioFile};
+
+/// One placement of a decoded source on the timeline.
+///
+/// Several clips can point at the same source, each with its own trim and envelope. The
+/// first clip made for a source shares its id, so ids handed out before a source was
+/// repeated keep working.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Clip {
+    pub id: Uuid,
+    // Key of the source in `audio_files`
+    pub source: String,
+    pub trim: Trim,
+    pub envelope: Envelope,
+    pub pauses: PauseMode,
+    pub lane: Uuid,
+    // In samples per channel from the start of the mix. Set by the layout on the main
+    // lane, chosen freely on the others
+    pub start: u64,
+    pub waveform_path: String,
+}
+
+impl Clip {
+    /// The first clip of a freshly imported source.
+    pub fn for_source(source: &AudioFile) -> Self {
+        Self::with_id(source.id, source)
+    }
+
+    /// The first clips of a freshly imported source, one per trim, like the tracks a CUE
+    /// sheet cuts from its file. The first of them keeps the source's id.
+    pub fn for_tracks(source: &AudioFile, trims: &[Trim]) -> Vec<Self> {
+        if trims.is_empty() {
+            return vec![Self::for_source(source)];
+        }
+        trims
+            .iter()
+            .enumerate()
+            .map(|(i, &trim)| {
+                let id = if i == 0 { source.id } else { Uuid::new_v4() };
+                Self {
+                    trim,
+                    ..Self::with_id(id, source)
+                }
+            })
+            .collect()
+    }
+
+    fn with_id(id: Uuid, source: &AudioFile) -> Self {
+        Self {
+            id,
+            source: source.path.clone(),
+            trim: Trim::default(),
+            envelope: Envelope::default(),
+            pauses: PauseMode::default(),
+            lane: MAIN_LANE,
+            start: 0,
+            waveform_path: String::new(),
+        }
+    }
+}
+
+/// The lane every timeline starts with, which can't be removed.
+pub const MAIN_LANE: Uuid = Uuid::nil();
+
+/// A track of the timeline. Lanes play at the same time and are summed in the mix.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Lane {
+    pub id: Uuid,
+    pub name: String,
+    pub gain_db: f32,
+    pub muted: bool,
+}
+
+impl Lane {
+    pub fn main() -> Self {
+        Self {
+            id: MAIN_LANE,
+            name: "Main".to_string(),
+            gain_db: 0.0,
+            muted: false,
+        }
+    }
+
+    /// Linear gain of the lane, 0 when muted.
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            10f32.powf(self.gain_db / 20.0)
+        }
+    }
+}
+
+/// A clip together with the source it plays.
+#[derive(Clone, Copy)]
+pub struct ClipView<'a> {
+    pub clip: &'a Clip,
+    pub source: &'a AudioFile,
+    // The clip's own trim, or the source's suggested one when silence is trimmed automatically
+    pub trim: Trim,
+    // Samples with the long pauses cut down, played instead of the trimmed source
+    pub compressed: Option<&'a [f32]>,
+}
+
+impl<'a> ClipView<'a> {
+    /// `clip` playing `source`. With `auto_trim` set, a clip that hasn't been trimmed by
+    /// hand loses the silence at either end.
+    pub fn new(clip: &'a Clip, source: &'a AudioFile, auto_trim: bool) -> Self {
+        let trim = if auto_trim && clip.trim == Trim::default() {
+            source.suggested_trim
+        } else {
+            clip.trim
+        };
+        Self {
+            clip,
+            source,
+            trim,
+            compressed: None,
+        }
+    }
+
+    /// The same clip playing `samples` in place of its trimmed source.
+    pub fn with_samples(self, samples: &'a [f32]) -> Self {
+        Self {
+            compressed: Some(samples),
+            ..self
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.clip.id
+    }
+
+    /// The samples of the source that make it onto the timeline.
+    pub fn samples(&self, format: &ProjectFormat) -> &'a [f32] {
+        match self.compressed {
+            Some(samples) => samples,
+            None => self.trim.apply(&self.source.samples, format),
+        }
+    }
+
+    /// The clip's envelope over its trimmed length.
+    pub fn shape(&self, format: &ProjectFormat) -> EnvelopeShape {
+        let frames = self.samples(format).len() / format.channels.max(1) as usize;
+        self.clip.envelope.shape(frames, format.sample_rate)
+    }
+}
+
+/// The clips that play, in order, with their sources.
+///
+/// Clips of a copy the duplicate policy drops are left out, as are any whose source has gone.
+pub fn resolve<'a>(
+    clips: &'a [Clip],
+    audio_files: &'a BTreeMap<String, AudioFile>,
+    auto_trim: bool,
+) -> Vec<ClipView<'a>> {
+    clips
+        .iter()
+        .filter_map(|clip| {
+            let source = audio_files.get(&clip.source)?;
+            source
+                .duplicate_of
+                .is_none()
+                .then(|| ClipView::new(clip, source, auto_trim))
+        })
+        .collect()
+}
+
+/// Copies of the sources `clips` play, so they can be rendered without holding `audio_files`.
+pub fn sources_of(
+    clips: &[Clip],
+    audio_files: &BTreeMap<String, AudioFile>,
+) -> BTreeMap<String, AudioFile> {
+    clips
+        .iter()
+        .filter_map(|clip| {
+            let source = audio_files.get(&clip.source)?;
+            Some((clip.source.clone(), source.clone()))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipInfo {
+    #[serde(flatten)]
+    pub clip: Clip,
+    pub source_id: Uuid,
+    // Other clips point at the same source
+    pub repeated: bool,
+}
+
+#[tauri::command]
+pub fn get_timeline(state: State<'_, Arc<AppState>>) -> Result<Vec<ClipInfo>, Error> {
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    let timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    for clip in timeline.iter() {
+        *uses.entry(clip.source.as_str()).or_default() += 1;
+    }
+    Ok(timeline
+        .iter()
+        .filter_map(|clip| {
+            let source = audio_files.get(&clip.source)?;
+            Some(ClipInfo {
+                clip: clip.clone(),
+                source_id: source.id,
+                repeated: uses[clip.source.as_str()] > 1,
+            })
+        })
+        .collect())
+}
+
+/// Puts another clip of source `source_id` on the timeline, at `index` or on the end.
+#[tauri::command]
+pub fn add_clip(
+    source_id: Uuid,
+    index: Option<usize>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Clip, Error> {
+    let edit = state.history.begin(&state, false)?;
+    let clip = {
+        let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        let source = audio_files
+            .values()
+            .find(|file| file.id == source_id)
+            .ok_or(Error::NoAudioData)?;
+
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        // The source keeps its own id for its first clip
+        let id = if timeline.iter().any(|clip| clip.id == source.id) {
+            Uuid::new_v4()
+        } else {
+            source.id
+        };
+        let clip = Clip::with_id(id, source);
+        let index = index.unwrap_or(timeline.len()).min(timeline.len());
+        timeline.insert(index, clip.clone());
+        println!("➕ {} placed at {}", source.path, index);
+        clip
+    };
+
+    edit.record("Add clip")?;
+    invalidate_mix(&state)?;
+    Ok(clip)
+}
+
+/// Copies clip `id`, settings and all, and places the copy right after it.
+#[tauri::command]
+pub fn duplicate_clip(id: Uuid, state: State<'_, Arc<AppState>>) -> Result<Clip, Error> {
+    let edit = state.history.begin(&state, false)?;
+    let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+    let index = timeline
+        .iter()
+        .position(|clip| clip.id == id)
+        .ok_or(Error::ClipNotFound(id))?;
+    let copy = Clip {
+        id: Uuid::new_v4(),
+        ..timeline[index].clone()
+    };
+    timeline.insert(index + 1, copy.clone());
+    drop(timeline);
+
+    edit.record("Duplicate clip")?;
+    invalidate_mix(&state)?;
+    Ok(copy)
+}
+
+/// Takes clip `id` off the timeline. Its source stays loaded.
+#[tauri::command]
+pub fn remove_clip(id: Uuid, state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let index = timeline
+            .iter()
+            .position(|clip| clip.id == id)
+            .ok_or(Error::ClipNotFound(id))?;
+        timeline.remove(index);
+    }
+    edit.record("Remove clip")?;
+    invalidate_mix(&state)
+}
+
+/// Puts clip `id` on `lane`, at `start` samples per channel into the mix.
+///
+/// On the main lane the position comes from the layout, so `start` only matters elsewhere.
+#[tauri::command]
+pub fn move_clip(
+    id: Uuid,
+    lane: Uuid,
+    start: u64,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Clip, Error> {
+    if !state
+        .lanes
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .iter()
+        .any(|existing| existing.id == lane)
+    {
+        return Err(Error::LaneNotFound(lane));
+    }
+    let edit = state.history.begin(&state, false)?;
+    let moved = {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let clip = timeline
+            .iter_mut()
+            .find(|clip| clip.id == id)
+            .ok_or(Error::ClipNotFound(id))?;
+        clip.lane = lane;
+        clip.start = start;
+        println!("↔️ {} moved to lane {} at {}", clip.source, lane, start);
+        clip.clone()
+    };
+    edit.record("Move clip")?;
+    invalidate_mix(&state)?;
+    Ok(moved)
+}
+
+#[tauri::command]
+pub fn get_lanes(state: State<'_, Arc<AppState>>) -> Result<Vec<Lane>, Error> {
+    let lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(lanes.clone())
+}
+
+/// Adds an empty lane under the others.
+#[tauri::command]
+pub fn add_lane(name: String, state: State<'_, Arc<AppState>>) -> Result<Lane, Error> {
+    let lane = Lane {
+        id: Uuid::new_v4(),
+        name,
+        gain_db: 0.0,
+        muted: false,
+    };
+    let edit = state.history.begin(&state, false)?;
+    state
+        .lanes
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .push(lane.clone());
+    edit.record("Add lane")?;
+    Ok(lane)
+}
+
+/// Sets the gain and mute of lane `id`.
+#[tauri::command]
+pub fn set_lane(
+    id: Uuid,
+    gain_db: f32,
+    muted: bool,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Lane, Error> {
+    if !gain_db.is_finite() {
+        return Err(Error::InvalidGain);
+    }
+    let edit = state.history.begin(&state, false)?;
+    let lane = {
+        let mut lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?;
+        let lane = lanes
+            .iter_mut()
+            .find(|lane| lane.id == id)
+            .ok_or(Error::LaneNotFound(id))?;
+        lane.gain_db = gain_db;
+        lane.muted = muted;
+        println!(
+            "🎚️ Lane {} at {} dB{}",
+            lane.name,
+            gain_db,
+            if muted { ", muted" } else { "" }
+        );
+        lane.clone()
+    };
+    edit.record("Set lane")?;
+    invalidate_mix(&state)?;
+    Ok(lane)
+}
+
+/// Removes lane `id` and the clips on it. The main lane always stays.
+#[tauri::command]
+pub fn remove_lane(id: Uuid, state: State<'_, Arc<AppState>>) -> Result<(), Error> {
+    if id == MAIN_LANE {
+        return Err(Error::MainLaneRequired);
+    }
+    let edit = state.history.begin(&state, false)?;
+    {
+        let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+        let mut lanes = state.lanes.lock().map_err(|_| Error::LockPoisoned)?;
+        let index = lanes
+            .iter()
+            .position(|lane| lane.id == id)
+            .ok_or(Error::LaneNotFound(id))?;
+        lanes.remove(index);
+        timeline.retain(|clip| clip.lane != id);
+    }
+    edit.record("Remove lane")?;
+    invalidate_mix(&state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::audio_file;
+
+    #[test]
+    fn tracks_become_clips_on_one_source() {
+        let source = audio_file("/music/album.flac", "/music/album.cue", vec![0.0; 1000]);
+        let trims = [
+            Trim {
+                start: 0.0,
+                end: Some(60.0),
+            },
+            Trim {
+                start: 60.0,
+                end: None,
+            },
+        ];
+        let clips = Clip::for_tracks(&source, &trims);
+        assert_eq!(clips.len(), 2);
+        assert_eq!(clips[0].id, source.id);
+        assert_ne!(clips[1].id, source.id);
+        assert!(clips.iter().all(|clip| clip.source == source.path));
+        assert_eq!(clips[1].trim, trims[1]);
+
+        let untrimmed = Clip::for_tracks(&source, &[]);
+        assert_eq!(untrimmed.len(), 1);
+        assert_eq!(untrimmed[0].id, source.id);
+        assert_eq!(untrimmed[0].trim, Trim::default());
+    }
+
+    fn sources() -> BTreeMap<String, AudioFile> {
+        let format = ProjectFormat::default();
+        let frames = format.sample_rate as usize;
+        let mut hit = audio_file("/kit/hit.wav", "/kit", vec![0.5; frames * 2]);
+        hit.suggested_trim = Trim {
+            start: 0.25,
+            end: Some(0.5),
+        };
+        let mut copy = audio_file("/kit/copy.wav", "/kit", vec![0.5; frames * 2]);
+        copy.duplicate_of = Some(hit.id);
+        [hit, copy]
+            .into_iter()
+            .map(|file| (file.path.clone(), file))
+            .collect()
+    }
+
+    #[test]
+    fn one_source_plays_in_several_clips() {
+        let sources = sources();
+        let hit = &sources["/kit/hit.wav"];
+        let first = Clip::for_source(hit);
+        let again = Clip {
+            id: Uuid::new_v4(),
+            trim: Trim {
+                start: 0.0,
+                end: Some(0.1),
+            },
+            ..first.clone()
+        };
+        let clips = vec![first.clone(), again.clone(), first.clone()];
+
+        let views = resolve(&clips, &sources, false);
+        assert_eq!(views.len(), 3);
+        assert!(views.iter().all(|view| view.source.id == hit.id));
+        let format = ProjectFormat::default();
+        let frames = |view: &ClipView| view.samples(&format).len() / format.channels as usize;
+        assert_eq!(frames(&views[0]), format.sample_rate as usize);
+        assert_eq!(frames(&views[1]), format.sample_rate as usize / 10);
+
+        // Copied once however many clips play it
+        assert_eq!(sources_of(&clips, &sources).len(), 1);
+    }
+
+    #[test]
+    fn dropped_copies_and_missing_sources_are_left_out() {
+        let sources = sources();
+        let mut gone = Clip::for_source(&sources["/kit/hit.wav"]);
+        gone.source = "/kit/gone.wav".to_string();
+        let clips = vec![
+            Clip::for_source(&sources["/kit/copy.wav"]),
+            gone,
+            Clip::for_source(&sources["/kit/hit.wav"]),
+        ];
+        let views = resolve(&clips, &sources, false);
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].source.path, "/kit/hit.wav");
+    }
+
+    #[test]
+    fn auto_trim_only_applies_to_untrimmed_clips() {
+        let sources = sources();
+        let hit = &sources["/kit/hit.wav"];
+        let clip = Clip::for_source(hit);
+        assert_eq!(ClipView::new(&clip, hit, true).trim, hit.suggested_trim);
+        assert_eq!(ClipView::new(&clip, hit, false).trim, Trim::default());
+
+        let trimmed = Clip {
+            trim: Trim {
+                start: 0.1,
+                end: None,
+            },
+            ..clip.clone()
+        };
+        assert_eq!(ClipView::new(&trimmed, hit, true).trim, trimmed.trim);
+    }
+
+    #[test]
+    fn lane_gain_is_in_decibels_and_muting_silences() {
+        let mut lane = Lane::main();
+        assert_eq!(lane.gain(), 1.0);
+        lane.gain_db = -6.0;
+        assert!((lane.gain() - 0.501).abs() < 0.001);
+        lane.muted = true;
+        assert_eq!(lane.gain(), 0.0);
+    }
+}
@@ -0,0 +1,799 @@
+use notify_debouncer_full::notify::event::ModifyKind;
+use notify_debouncer_full::notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use uuid::Uuid;
+
+use crate::archive::{
+    backing_file, entry_folder, entry_path, split_entry_path, visit_entries, ArchiveKind,
+};
+use crate::combine::{load_audio, DecodeReport};
+use crate::duplicates::{content_hash, refresh_duplicates};
+use crate::error::Error;
+use crate::playlist::{cue_tracks, playlist_sources, PlaylistKind};
+use crate::render::Trim;
+use crate::resample::{ProjectFormat, SourceSpec};
+use crate::sample_store::Samples;
+use crate::scan::{detect_format, probe_entry, Detection};
+use crate::silence::suggest_trim;
+use crate::source::SourceRef;
+use crate::state::{AppState, AudioFile};
+use crate::timeline::Clip;
+
+// Long enough for an export or re-render to finish writing before we read the file
+const DEBOUNCE: Duration = Duration::from_millis(1000);
+
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SourcesChanged {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+impl SourcesChanged {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+            && self.failed.is_empty()
+    }
+}
+
+struct WatchState {
+    debouncer: Option<Debouncer<RecommendedWatcher, RecommendedCache>>,
+    // The sections as imported: folders, playlists, archives and folders inside archives
+    sections: BTreeSet<String>,
+    // The folders with a watch on them
+    watched: BTreeSet<String>,
+}
+
+/// Watches the folders of the imported sections and keeps `audio_files` in step with them.
+pub struct FolderWatcher {
+    state: Mutex<WatchState>,
+}
+
+impl FolderWatcher {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(WatchState {
+                debouncer: None,
+                sections: BTreeSet::new(),
+                watched: BTreeSet::new(),
+            }),
+        }
+    }
+
+    /// Watches exactly `sections`, starting and stopping individual watches as needed.
+    pub fn sync(&self, app: &AppHandle, sections: BTreeSet<String>) -> Result<(), Error> {
+        let mut state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        if state.debouncer.is_none() {
+            let handle = app.clone();
+            let debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| {
+                handle_events(&handle, result)
+            })
+            .map_err(|e| Error::Watch(e.to_string()))?;
+            state.debouncer = Some(debouncer);
+        }
+
+        let folders = watch_folders(&sections);
+        let WatchState {
+            debouncer,
+            sections: current,
+            watched,
+        } = &mut *state;
+        *current = sections;
+        let Some(debouncer) = debouncer.as_mut() else {
+            return Ok(());
+        };
+
+        for folder in watched.difference(&folders) {
+            if let Err(e) = debouncer.unwatch(folder) {
+                eprintln!("⚠️ Failed to stop watching {}: {}", folder, e);
+            }
+        }
+        let mut now_watched = BTreeSet::new();
+        for folder in folders {
+            if watched.contains(&folder) {
+                now_watched.insert(folder);
+                continue;
+            }
+            // Sections are grouped per sub-folder already, so each one is watched on its own
+            match debouncer.watch(&folder, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    println!("👀 Watching {}", folder);
+                    now_watched.insert(folder);
+                }
+                Err(e) => eprintln!("⚠️ Failed to watch {}: {}", folder, e),
+            }
+        }
+        *watched = now_watched;
+        Ok(())
+    }
+
+    /// The sections being watched.
+    pub fn sections(&self) -> Result<BTreeSet<String>, Error> {
+        let state = self.state.lock().map_err(|_| Error::LockPoisoned)?;
+        Ok(state.sections.clone())
+    }
+}
+
+impl Default for FolderWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Folders are watched rather than single files, so a file an editor saves by replacing it is
+// still seen. A playlist's folder is watched for the playlist, and the folders of the files
+// it lists for those files, the audio file of a CUE sheet among them.
+fn watch_folders(sections: &BTreeSet<String>) -> BTreeSet<String> {
+    let parent = |path: &str| {
+        Path::new(path)
+            .parent()
+            .map(|folder| folder.to_string_lossy().to_string())
+    };
+    let mut folders = BTreeSet::new();
+    for section in sections {
+        let file = backing_file(section);
+        if PlaylistKind::from_path(Path::new(section)).is_some() {
+            folders.extend(parent(section));
+            match playlist_sources(section) {
+                Ok(sources) => folders.extend(
+                    sources
+                        .iter()
+                        .filter_map(|source| parent(backing_file(&SourceRef::parse(source).path))),
+                ),
+                Err(e) => eprintln!("⚠️ Failed to read {}: {}", section, e),
+            }
+        } else if ArchiveKind::from_path(Path::new(file)).is_some() {
+            folders.extend(parent(file));
+        } else {
+            folders.insert(section.clone());
+        }
+    }
+    folders
+}
+
+fn handle_events(app: &AppHandle, result: DebounceEventResult) {
+    let events = match result {
+        Ok(events) => events,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("⚠️ Folder watch error: {}", e);
+            }
+            return;
+        }
+    };
+
+    let mut touched: BTreeSet<PathBuf> = BTreeSet::new();
+    for event in events {
+        match event.kind {
+            // Reads and permission changes don't change the audio
+            EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => continue,
+            _ => touched.extend(event.paths.iter().cloned()),
+        }
+    }
+    if touched.is_empty() {
+        return;
+    }
+
+    let state = app.state::<Arc<AppState>>();
+    let sections = match state.folder_watcher.sections() {
+        Ok(sections) => sections,
+        Err(e) => {
+            eprintln!("⚠️ Failed to apply folder changes: {}", e);
+            return;
+        }
+    };
+    // An edited playlist can list files in folders that aren't watched yet
+    let playlist_touched = touched
+        .iter()
+        .any(|path| is_playlist_section(&sections, path));
+    match apply_changes(&state, &sections, touched) {
+        Ok(changes) if !changes.is_empty() => {
+            println!(
+                "🔄 Sources changed: {} added, {} changed, {} removed, {} failed",
+                changes.added.len(),
+                changes.changed.len(),
+                changes.removed.len(),
+                changes.failed.len()
+            );
+            let _ = app.emit("sources-changed", changes);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("⚠️ Failed to apply folder changes: {}", e),
+    }
+    if playlist_touched {
+        if let Err(e) = state.folder_watcher.sync(app, sections) {
+            eprintln!("⚠️ Failed to update folder watches: {}", e);
+        }
+    }
+}
+
+fn is_playlist_section(sections: &BTreeSet<String>, path: &Path) -> bool {
+    PlaylistKind::from_path(path).is_some()
+        && path.to_str().is_some_and(|path| sections.contains(path))
+}
+
+// A change to `audio_files`, decoded ahead of taking the locks
+enum Update {
+    Remove(Vec<String>),
+    Add {
+        path: String,
+        section: String,
+        // One clip per trim, like the tracks of a CUE sheet
+        trims: Vec<Trim>,
+        source: Prepared,
+    },
+    Reload {
+        key: String,
+        source: Prepared,
+    },
+    // The tracks of a CUE sheet moved, the clips of its file are cut again
+    Retrim {
+        key: String,
+        trims: Vec<Trim>,
+    },
+}
+
+// Everything about a source that comes from decoding it
+struct Prepared {
+    samples: Samples,
+    content_hash: u64,
+    original_spec: SourceSpec,
+    peaks: Vec<(f32, f32)>,
+    decode_report: DecodeReport,
+    suggested_trim: Trim,
+}
+
+fn apply_changes(
+    state: &AppState,
+    sections: &BTreeSet<String>,
+    touched: BTreeSet<PathBuf>,
+) -> Result<SourcesChanged, Error> {
+    let mut changes = SourcesChanged::default();
+    let project_format = *state
+        .project_format
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?;
+
+    let mut updates = Vec::new();
+    for path in touched {
+        let Some(path_str) = path.to_str() else {
+            continue;
+        };
+        if is_playlist_section(sections, &path) {
+            playlist_updates(state, path_str, &project_format, &mut updates, &mut changes)?;
+            continue;
+        }
+        let archive_sections: BTreeSet<&String> = sections
+            .iter()
+            .filter(|section| {
+                split_entry_path(section).map_or(section.as_str(), |(archive, _)| archive)
+                    == path_str
+            })
+            .filter(|_| ArchiveKind::from_path(&path).is_some())
+            .collect();
+        if !archive_sections.is_empty() {
+            archive_updates(
+                state,
+                path_str,
+                &archive_sections,
+                &project_format,
+                &mut updates,
+                &mut changes,
+            )?;
+            continue;
+        }
+
+        // A file can back several entries, one per chosen track
+        let keys = keys_of(state, |key| SourceRef::parse(key).path == path_str)?;
+        if !path.is_file() {
+            if !keys.is_empty() {
+                updates.push(Update::Remove(keys));
+            }
+            continue;
+        }
+
+        if keys.is_empty() {
+            // New files only count inside a watched section folder, and only if they're audio
+            let resource_fork = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("._"));
+            let Some(folder) = path.parent().and_then(Path::to_str) else {
+                continue;
+            };
+            if resource_fork || !sections.contains(folder) {
+                continue;
+            }
+            if !detect_format(&path).is_supported() {
+                continue;
+            }
+            add_update(
+                state,
+                path_str,
+                folder,
+                Vec::new(),
+                &project_format,
+                &mut updates,
+                &mut changes,
+            );
+            continue;
+        }
+
+        // The cache misses on its own because the mtime moved
+        for key in keys {
+            reload_update(state, key, &project_format, &mut updates, &mut changes);
+        }
+    }
+    if updates.is_empty() {
+        return Ok(changes);
+    }
+
+    // Recorded like any other edit, so undoing an earlier one doesn't bring back files
+    // that have gone from disk since, or drop ones that appeared
+    let edit = state.history.begin(state, true)?;
+    apply_updates(state, updates, &mut changes)?;
+    refresh_duplicates(state)?;
+    // The mix no longer matches what's on disk
+    *state
+        .combined_audio
+        .lock()
+        .map_err(|_| Error::LockPoisoned)? = None;
+    edit.record("Update from watched folders")?;
+    Ok(changes)
+}
+
+// The keys of `audio_files` that `matches` picks
+fn keys_of(state: &AppState, matches: impl Fn(&str) -> bool) -> Result<Vec<String>, Error> {
+    let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(audio_files
+        .keys()
+        .filter(|key| matches(key))
+        .cloned()
+        .collect())
+}
+
+// Re-reads an edited playlist: sources it no longer lists go, new ones are added on the end,
+// and the clips of a CUE sheet's file follow its track times
+fn playlist_updates(
+    state: &AppState,
+    playlist: &str,
+    project_format: &ProjectFormat,
+    updates: &mut Vec<Update>,
+    changes: &mut SourcesChanged,
+) -> Result<(), Error> {
+    let listed = if Path::new(playlist).is_file() {
+        match playlist_sources(playlist) {
+            Ok(listed) => listed,
+            Err(e) => {
+                eprintln!("⚠️ Failed to re-read {}: {}", playlist, e);
+                changes.failed.push(playlist.to_string());
+                return Ok(());
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let mut trims: BTreeMap<String, Vec<Trim>> = BTreeMap::new();
+    if PlaylistKind::from_path(Path::new(playlist)) == Some(PlaylistKind::Cue) && !listed.is_empty()
+    {
+        for track in cue_tracks(playlist)? {
+            trims
+                .entry(track.file.clone())
+                .or_default()
+                .push(track.trim());
+        }
+    }
+
+    // Sources already imported under another section stay with it
+    let (current, elsewhere): (BTreeSet<String>, BTreeSet<String>) = {
+        let audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+        audio_files
+            .values()
+            .map(|file| file.path.clone())
+            .partition(|path| audio_files[path].section == playlist)
+    };
+
+    let removed: Vec<String> = current
+        .iter()
+        .filter(|key| !listed.contains(key))
+        .cloned()
+        .collect();
+    if !removed.is_empty() {
+        updates.push(Update::Remove(removed));
+    }
+    for path in listed {
+        let trims = trims.remove(&path).unwrap_or_default();
+        if current.contains(&path) {
+            if !trims.is_empty() {
+                updates.push(Update::Retrim { key: path, trims });
+            }
+            continue;
+        }
+        if elsewhere.contains(&path) {
+            continue;
+        }
+        add_update(
+            state,
+            &path,
+            playlist,
+            trims,
+            project_format,
+            updates,
+            changes,
+        );
+    }
+    Ok(())
+}
+
+// Lists a changed archive again: entries that went are dropped, new ones in an imported
+// folder of it are added and the rest re-decoded
+fn archive_updates(
+    state: &AppState,
+    archive: &str,
+    sections: &BTreeSet<&String>,
+    project_format: &ProjectFormat,
+    updates: &mut Vec<Update>,
+    changes: &mut SourcesChanged,
+) -> Result<(), Error> {
+    let keys = keys_of(state, |key| backing_file(key) == archive)?;
+    // Entry to its detection, None for entries that aren't new or aren't in an imported folder
+    let mut entries: BTreeMap<String, Option<Detection>> = BTreeMap::new();
+    if Path::new(archive).is_file() {
+        let listed = visit_entries(archive, |name, size, reader| {
+            let path = entry_path(archive, name);
+            let section = entry_folder(archive, name);
+            let detection = if keys.contains(&path) || !sections.contains(&section) {
+                None
+            } else {
+                Some(probe_entry(&path, size, reader).0)
+            };
+            entries.insert(path, detection);
+            Ok(())
+        });
+        if let Err(e) = listed {
+            eprintln!("⚠️ Failed to re-read {}: {}", archive, e);
+            changes.failed.push(archive.to_string());
+            return Ok(());
+        }
+    }
+
+    let removed: Vec<String> = keys
+        .iter()
+        .filter(|key| !entries.contains_key(*key))
+        .cloned()
+        .collect();
+    if !removed.is_empty() {
+        updates.push(Update::Remove(removed));
+    }
+    for (path, detection) in entries {
+        match detection {
+            Some(detection) if detection.is_supported() => {
+                let Some((_, entry)) = split_entry_path(&path) else {
+                    continue;
+                };
+                let section = entry_folder(archive, entry);
+                add_update(
+                    state,
+                    &path,
+                    &section,
+                    Vec::new(),
+                    project_format,
+                    updates,
+                    changes,
+                );
+            }
+            Some(_) => {}
+            None if keys.contains(&path) => {
+                reload_update(state, path, project_format, updates, changes)
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn add_update(
+    state: &AppState,
+    path: &str,
+    section: &str,
+    trims: Vec<Trim>,
+    project_format: &ProjectFormat,
+    updates: &mut Vec<Update>,
+    changes: &mut SourcesChanged,
+) {
+    match prepare(state, path, project_format) {
+        Ok(source) => updates.push(Update::Add {
+            path: path.to_string(),
+            section: section.to_string(),
+            trims,
+            source,
+        }),
+        Err(e) => {
+            eprintln!("⚠️ Failed to decode new file {}: {}", path, e);
+            changes.failed.push(path.to_string());
+        }
+    }
+}
+
+fn reload_update(
+    state: &AppState,
+    key: String,
+    project_format: &ProjectFormat,
+    updates: &mut Vec<Update>,
+    changes: &mut SourcesChanged,
+) {
+    match prepare(state, &key, project_format) {
+        Ok(source) => updates.push(Update::Reload { key, source }),
+        Err(e) => {
+            eprintln!("⚠️ Failed to re-decode {}: {}", key, e);
+            changes.failed.push(key);
+        }
+    }
+}
+
+fn apply_updates(
+    state: &AppState,
+    updates: Vec<Update>,
+    changes: &mut SourcesChanged,
+) -> Result<(), Error> {
+    let mut audio_files = state.audio_files.lock().map_err(|_| Error::LockPoisoned)?;
+    let mut timeline = state.timeline.lock().map_err(|_| Error::LockPoisoned)?;
+    for update in updates {
+        match update {
+            Update::Remove(keys) => {
+                for key in &keys {
+                    audio_files.remove(key);
+                }
+                changes.removed.extend(keys);
+            }
+            Update::Add {
+                path,
+                section,
+                trims,
+                source,
+            } => {
+                let audio_file = AudioFile {
+                    samples: source.samples,
+                    id: Uuid::new_v4(),
+                    path: path.clone(),
+                    original_spec: source.original_spec,
+                    peaks: source.peaks,
+                    decode_report: source.decode_report,
+                    section,
+                    content_hash: source.content_hash,
+                    duplicate_of: None,
+                    fingerprint: None,
+                    analysis: None,
+                    suggested_trim: source.suggested_trim,
+                };
+                // New files go on the end of the timeline
+                timeline.extend(Clip::for_tracks(&audio_file, &trims));
+                audio_files.insert(path.clone(), audio_file);
+                changes.added.push(path);
+            }
+            Update::Reload { key, source } => {
+                // Removed by an edit while it was decoding
+                let Some(audio_file) = audio_files.get_mut(&key) else {
+                    continue;
+                };
+                audio_file.samples = source.samples;
+                audio_file.content_hash = source.content_hash;
+                audio_file.original_spec = source.original_spec;
+                audio_file.peaks = source.peaks;
+                audio_file.decode_report = source.decode_report;
+                audio_file.fingerprint = None;
+                audio_file.analysis = None;
+                audio_file.suggested_trim = source.suggested_trim;
+                changes.changed.push(key);
+            }
+            Update::Retrim { key, trims } => {
+                let Some(audio_file) = audio_files.get(&key) else {
+                    continue;
+                };
+                let current: Vec<Trim> = timeline
+                    .iter()
+                    .filter(|clip| clip.source == key)
+                    .map(|clip| clip.trim)
+                    .collect();
+                if current == trims {
+                    continue;
+                }
+                // The new clips take the place of the first old one
+                let index = timeline
+                    .iter()
+                    .position(|clip| clip.source == key)
+                    .unwrap_or(timeline.len());
+                timeline.retain(|clip| clip.source != key);
+                let index = index.min(timeline.len());
+                timeline.splice(index..index, Clip::for_tracks(audio_file, &trims));
+                if !changes.changed.contains(&key) {
+                    changes.changed.push(key);
+                }
+            }
+        }
+    }
+    timeline.retain(|clip| audio_files.contains_key(&clip.source));
+    Ok(())
+}
+
+fn prepare(state: &AppState, key: &str, project_format: &ProjectFormat) -> Result<Prepared, Error> {
+    let decoded = load_audio(key, project_format, &state.sample_cache)?;
+    let silence = *state.silence.lock().map_err(|_| Error::LockPoisoned)?;
+    Ok(Prepared {
+        suggested_trim: suggest_trim(&decoded.samples, project_format, &silence),
+        content_hash: content_hash(&decoded.samples),
+        samples: state.sample_store.insert(decoded.samples)?,
+        original_spec: decoded.original_spec,
+        peaks: decoded.peaks,
+        decode_report: decoded.report,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_wav, TempDir};
+    use std::fs;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn path(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    fn touch(state: &AppState, sections: &[&str], paths: &[&Path]) -> SourcesChanged {
+        let sections = sections.iter().map(|section| section.to_string()).collect();
+        let touched = paths.iter().map(|path| path.to_path_buf()).collect();
+        apply_changes(state, &sections, touched).unwrap()
+    }
+
+    fn frames(state: &AppState, key: &str) -> usize {
+        state.audio_files.lock().unwrap()[key].samples.len()
+    }
+
+    fn trims(state: &AppState, key: &str) -> Vec<Trim> {
+        let timeline = state.timeline.lock().unwrap();
+        timeline
+            .iter()
+            .filter(|clip| clip.source == key)
+            .map(|clip| clip.trim)
+            .collect()
+    }
+
+    fn write_zip(path: &Path, entries: &[&str], wav: &[u8]) {
+        let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+        for entry in entries {
+            zip.start_file(*entry, SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(wav).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn new_and_removed_files_in_a_section_folder() {
+        let dir = TempDir::new("watch");
+        let section = path(dir.path());
+        let kick = dir.join("kick.wav");
+        write_wav(&kick, 44100, 1, 4410);
+        write_wav(&dir.join("._kick.wav"), 44100, 1, 4410);
+        let state = AppState::new();
+
+        let changes = touch(&state, &[&section], &[&kick, &dir.join("._kick.wav")]);
+        assert_eq!(changes.added, vec![path(&kick)]);
+        assert_eq!(state.timeline.lock().unwrap().len(), 1);
+
+        fs::remove_file(&kick).unwrap();
+        let changes = touch(&state, &[&section], &[&kick]);
+        assert_eq!(changes.removed, vec![path(&kick)]);
+        assert!(changes.failed.is_empty());
+        assert!(state.timeline.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cue_tracks_follow_the_sheet_and_its_audio_file() {
+        let dir = TempDir::new("watch");
+        let album = dir.join("audio/album.wav");
+        write_wav(&album, 44100, 1, 44100 * 3);
+        let cue = dir.join("album.cue");
+        let sheet = |second: u32| {
+            format!(
+                "FILE \"audio/album.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nTRACK 02 AUDIO\nINDEX 01 00:0{}:00\n",
+                second
+            )
+        };
+        fs::write(&cue, sheet(1)).unwrap();
+        let (cue_str, album_str) = (path(&cue), path(&album));
+        let state = AppState::new();
+
+        // The sheet's folder is watched, and so is the folder of the file it cuts up
+        let folders = watch_folders(&BTreeSet::from([cue_str.clone()]));
+        assert!(folders.contains(&path(dir.path())));
+        assert!(folders.contains(&path(&dir.join("audio"))));
+
+        let changes = touch(&state, &[&cue_str], &[&cue]);
+        assert_eq!(changes.added, vec![album_str.clone()]);
+        assert_eq!(trims(&state, &album_str)[1].start, 1.0);
+
+        // Re-rendering the audio file decodes it again, the clips stay
+        let before = frames(&state, &album_str);
+        write_wav(&album, 44100, 1, 44100 * 4);
+        let changes = touch(&state, &[&cue_str], &[&album]);
+        assert_eq!(changes.changed, vec![album_str.clone()]);
+        assert!(frames(&state, &album_str) > before);
+        assert_eq!(trims(&state, &album_str).len(), 2);
+
+        // Moving a track's index cuts the clips again
+        fs::write(&cue, sheet(2)).unwrap();
+        let changes = touch(&state, &[&cue_str], &[&cue]);
+        assert_eq!(changes.changed, vec![album_str.clone()]);
+        assert_eq!(trims(&state, &album_str)[1].start, 2.0);
+    }
+
+    #[test]
+    fn edited_playlists_are_read_again() {
+        let dir = TempDir::new("watch");
+        let (a, b) = (dir.join("a.wav"), dir.join("b.wav"));
+        write_wav(&a, 44100, 1, 4410);
+        write_wav(&b, 44100, 1, 4410);
+        let playlist = dir.join("set.m3u");
+        fs::write(&playlist, "#EXTM3U\na.wav\n").unwrap();
+        let section = path(&playlist);
+        let state = AppState::new();
+
+        let changes = touch(&state, &[&section], &[&playlist]);
+        assert_eq!(changes.added, vec![path(&a)]);
+
+        fs::write(&playlist, "#EXTM3U\nb.wav\n").unwrap();
+        let changes = touch(&state, &[&section], &[&playlist]);
+        assert_eq!(changes.removed, vec![path(&a)]);
+        assert_eq!(changes.added, vec![path(&b)]);
+        let audio_files = state.audio_files.lock().unwrap();
+        assert_eq!(audio_files.keys().collect::<Vec<_>>(), vec![&path(&b)]);
+        assert_eq!(audio_files[&path(&b)].section, section);
+    }
+
+    #[test]
+    fn archive_entries_come_and_go_with_the_archive() {
+        let dir = TempDir::new("watch");
+        let wav = dir.join("source.wav");
+        write_wav(&wav, 44100, 1, 4410);
+        let wav = fs::read(&wav).unwrap();
+        let archive = dir.join("pack.zip");
+        write_zip(&archive, &["kick.wav", "Other/tom.wav"], &wav);
+        let pack = path(&archive);
+        let entry = |name: &str| entry_path(&pack, name);
+        let state = AppState::new();
+
+        // Only entries in an imported folder of the archive are picked up
+        let changes = touch(&state, &[&pack], &[&archive]);
+        assert_eq!(changes.added, vec![entry("kick.wav")]);
+
+        write_zip(&archive, &["snare.wav", "Other/tom.wav"], &wav);
+        let changes = touch(&state, &[&pack], &[&archive]);
+        assert_eq!(changes.removed, vec![entry("kick.wav")]);
+        assert_eq!(changes.added, vec![entry("snare.wav")]);
+        assert!(changes.failed.is_empty());
+        assert!(state
+            .timeline
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|clip| clip.source == entry("snare.wav")));
+
+        fs::remove_file(&archive).unwrap();
+        let changes = touch(&state, &[&pack], &[&archive]);
+        assert_eq!(changes.removed, vec![entry("snare.wav")]);
+        assert!(state.audio_files.lock().unwrap().is_empty());
+    }
+}